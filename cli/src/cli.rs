@@ -10,6 +10,8 @@ pub struct Args {
 #[derive(Debug, clap::Subcommand)]
 pub enum Subcommand {
     Ninja(Box<NinjaSubcommand>),
+    /// Inspect or edit a build database
+    Db(DbSubcommand),
 }
 
 #[derive(Debug, clap::Parser)]
@@ -36,4 +38,128 @@ pub struct NinjaSubcommand {
     /// Dry run (don't commands but act like they succeeded)
     #[clap(short = 'n', long)]
     pub dry_run: bool,
+
+    /// Run a subtool instead of building (supported: clean, cleandead, recompact, import).
+    ///
+    /// Unlike in ninja, `recompact` removes the database records of builds and
+    /// files that are no longer part of the build graph.
+    #[clap(short = 't', name = "TOOL")]
+    pub tool: Option<String>,
+
+    /// Fail instead of waiting if another n2o5 process uses the build directory
+    #[clap(long)]
+    pub no_wait: bool,
+
+    /// How to report build progress
+    #[clap(long, value_enum, default_value = "fancy")]
+    pub progress: ProgressKind,
+
+    /// Enable a debugging MODE (use `-d list` to list modes)
+    #[clap(short = 'd', name = "MODE")]
+    pub debug: Vec<DebugMode>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugMode {
+    /// List the debugging modes
+    List,
+    /// Write a Chrome trace of the build to a file
+    Trace(PathBuf),
+}
+
+impl DebugMode {
+    pub const HELP: &str = "debugging modes:
+  trace=FILE  write a Chrome trace of the build to FILE, for Perfetto or chrome://tracing";
+}
+
+impl std::str::FromStr for DebugMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            _ if s == "list" => Ok(Self::List),
+            Some(("trace", file)) if !file.is_empty() => Ok(Self::Trace(file.into())),
+            _ => Err(format!(
+                "unknown debug mode {s:?}, use `-d list` to list modes"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ProgressKind {
+    /// A progress bar with the running builds
+    Fancy,
+    /// One JSON event per line on stdout, including the output of builds
+    Json,
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct DbSubcommand {
+    /// The database to open
+    #[clap(long, default_value = "n2o5_ninja.db")]
+    pub db: PathBuf,
+
+    /// The backend of the database; detected from the file if not given
+    #[clap(long, value_enum)]
+    pub backend: Option<DbBackend>,
+
+    /// The namespace in the database to use, instead of the default one
+    #[clap(long)]
+    pub namespace: Option<String>,
+
+    /// Change to DIR before doing anything else
+    #[clap(short = 'C', name = "DIR")]
+    pub chdir: Option<PathBuf>,
+
+    /// Fail instead of waiting if another n2o5 process uses the database
+    #[clap(long)]
+    pub no_wait: bool,
+
+    #[command(subcommand)]
+    pub action: DbAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DbBackend {
+    Redb,
+    Heed,
+    Sqlite,
+    /// The single-file database of `n2o5::db::dumb`, without namespaces
+    Dumb,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum DbAction {
+    /// Print all build and file records as JSON
+    Dump,
+    /// Print the record of a file and the build that generated it as JSON
+    Show { path: PathBuf },
+    /// Forget the build that generated the target, so that it runs again
+    Forget { target: PathBuf },
+    /// Write all records to FILE in a backend-neutral format
+    Export {
+        file: PathBuf,
+        #[clap(long, value_enum, default_value = "json")]
+        format: PortableFormat,
+    },
+    /// Read records exported by `export` from FILE, creating the database if
+    /// needed
+    Import {
+        file: PathBuf,
+        #[clap(long, value_enum, default_value = "json")]
+        format: PortableFormat,
+        /// Replace the path prefix FROM with TO, for a checkout at a different
+        /// location. May be given multiple times.
+        #[clap(long, name = "FROM=TO")]
+        rewrite: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PortableFormat {
+    /// JSON lines
+    Json,
+    /// Postcard, which is more compact
+    Postcard,
 }
@@ -0,0 +1,375 @@
+//! The `n2o5 db` subcommand for inspecting and editing build databases.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::Path,
+    time::SystemTime,
+};
+
+use anyhow::{Context, anyhow};
+use n2o5::{
+    ExecDb,
+    db::{
+        BuildHash, BuildInfo, ExecRecord, FileInfo,
+        dumb::{self, DumbDb},
+        portable::{self, Format, PathRewrite},
+    },
+    lock::BuildLock,
+};
+use n2o5_heed::ExecHeedDb;
+use n2o5_redb::ExecRedb;
+use n2o5_sqlite::ExecSqlite;
+use serde::Serialize;
+
+use crate::cli::{DbAction, DbBackend, DbSubcommand, PortableFormat};
+
+static SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+pub fn run(cmd: &DbSubcommand) -> anyhow::Result<()> {
+    if let Some(path) = &cmd.chdir {
+        std::env::set_current_dir(path).context("failed to change directory")?;
+    }
+
+    let _lock = lock(&cmd.db, !cmd.no_wait)?;
+    let namespace = cmd.namespace.as_deref().unwrap_or("");
+    let db = match cmd.action {
+        // Inspect the records as they are, without upgrading or resetting them
+        DbAction::Dump | DbAction::Show { .. } => open_existing(&cmd.db, cmd.backend, namespace)?,
+        DbAction::Import { .. } => open(&cmd.db, cmd.backend, namespace, true)?,
+        DbAction::Forget { .. } | DbAction::Export { .. } => {
+            open(&cmd.db, cmd.backend, namespace, false)?
+        }
+    };
+    let mut out = std::io::stdout().lock();
+    match &cmd.action {
+        DbAction::Dump => dump(&*db, &mut out)?,
+        DbAction::Show { path } => show(&*db, path, &mut out)?,
+        DbAction::Forget { target } => {
+            let forgotten = forget(&*db, target)?;
+            println!("Forgot the build of {} output files.", forgotten);
+        }
+        DbAction::Export { file, format } => {
+            let mut file =
+                BufWriter::new(File::create(file).context("Failed to create the export file")?);
+            let stats = portable::export(&*db, portable_format(*format), &mut file)?;
+            println!(
+                "Exported {} build records and {} file records.",
+                stats.builds, stats.files
+            );
+        }
+        DbAction::Import {
+            file,
+            format,
+            rewrite,
+        } => {
+            let rewrites = rewrite
+                .iter()
+                .map(|s| {
+                    PathRewrite::parse(s)
+                        .ok_or_else(|| anyhow!("Invalid rewrite {s:?}, expected FROM=TO"))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let mut file = File::open(file).context("Failed to open the export file")?;
+            let stats = portable::import(&*db, portable_format(*format), &mut file, &rewrites)?;
+            println!(
+                "Imported {} build records and {} file records.",
+                stats.builds, stats.files
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Lock the database at `db` against other n2o5 processes, through a lock
+/// file next to it. If `wait` is set, tell the user and wait for the lock if
+/// it is held, otherwise fail.
+pub fn lock(db: &Path, wait: bool) -> anyhow::Result<BuildLock> {
+    let mut path = db.as_os_str().to_owned();
+    path.push(".lock");
+    if wait {
+        BuildLock::acquire_or_wait(&path, |pid| match pid {
+            Some(pid) => eprintln!(
+                "n2o5: waiting for another process (pid {pid}) to release the build directory..."
+            ),
+            None => {
+                eprintln!("n2o5: waiting for another process to release the build directory...")
+            }
+        })
+        .context("Failed to lock the build directory")
+    } else {
+        Ok(BuildLock::try_acquire(&path)?)
+    }
+}
+
+/// Open a database, detecting its backend if not given. If `create` is set, a
+/// missing database is created, using redb if no backend is given.
+pub fn open(
+    path: &Path,
+    backend: Option<DbBackend>,
+    namespace: &str,
+    create: bool,
+) -> anyhow::Result<Box<dyn ExecDb>> {
+    let backend = match backend {
+        _ if !path.exists() && !create => {
+            return Err(anyhow!("Database {} does not exist", path.display()));
+        }
+        Some(backend) => backend,
+        None if !path.exists() => DbBackend::Redb,
+        None => detect_backend(path)?,
+    };
+    if backend == DbBackend::Heed {
+        std::fs::create_dir_all(path).context("Failed to create the database directory")?;
+    }
+    let context = || format!("Failed to open the database {}", path.display());
+    let db: Box<dyn ExecDb> = match backend {
+        DbBackend::Redb => Box::new(
+            ExecRedb::open(path)
+                .with_context(context)?
+                .namespace(namespace),
+        ),
+        DbBackend::Heed => {
+            let db = ExecHeedDb::open(path).with_context(context)?;
+            if namespace.is_empty() {
+                Box::new(db)
+            } else {
+                Box::new(db.namespace(namespace).with_context(context)?)
+            }
+        }
+        DbBackend::Sqlite => {
+            let db = ExecSqlite::open(path).with_context(context)?;
+            if namespace.is_empty() {
+                Box::new(db)
+            } else {
+                Box::new(db.namespace(namespace).with_context(context)?)
+            }
+        }
+        DbBackend::Dumb => {
+            if !namespace.is_empty() {
+                return Err(anyhow!("The dumb backend does not support namespaces"));
+            }
+            Box::new(DumbDb::new(path).with_context(context)?)
+        }
+    };
+    Ok(db)
+}
+
+/// Open an existing database as it is, detecting its backend if not given.
+/// Unlike [`open`], this never creates, upgrades or resets it, and fails if its
+/// data is from another version.
+pub fn open_existing(
+    path: &Path,
+    backend: Option<DbBackend>,
+    namespace: &str,
+) -> anyhow::Result<Box<dyn ExecDb>> {
+    if !path.exists() {
+        return Err(anyhow!("Database {} does not exist", path.display()));
+    }
+    let backend = match backend {
+        Some(backend) => backend,
+        None => detect_backend(path)?,
+    };
+    let context = || format!("Failed to open the database {}", path.display());
+    let db: Box<dyn ExecDb> = match backend {
+        DbBackend::Redb => {
+            Box::new(ExecRedb::open_existing(path, namespace).with_context(context)?)
+        }
+        DbBackend::Heed => {
+            Box::new(ExecHeedDb::open_existing(path, namespace).with_context(context)?)
+        }
+        DbBackend::Sqlite => {
+            Box::new(ExecSqlite::open_existing(path, namespace).with_context(context)?)
+        }
+        DbBackend::Dumb => {
+            if !namespace.is_empty() {
+                return Err(anyhow!("The dumb backend does not support namespaces"));
+            }
+            Box::new(DumbDb::open_existing(path).with_context(context)?)
+        }
+    };
+    Ok(db)
+}
+
+/// LMDB environments are directories, and SQLite and dumb database files start
+/// with a fixed header. Anything else is assumed to be redb.
+fn detect_backend(path: &Path) -> anyhow::Result<DbBackend> {
+    if path.is_dir() {
+        return Ok(DbBackend::Heed);
+    }
+    let mut header = [0; SQLITE_HEADER.len()];
+    let mut file = File::open(path).context("Failed to read the database file")?;
+    let len = file.read(&mut header)?;
+    if header[..len] == *SQLITE_HEADER {
+        Ok(DbBackend::Sqlite)
+    } else if header[..len] == *dumb::MAGIC {
+        Ok(DbBackend::Dumb)
+    } else {
+        Ok(DbBackend::Redb)
+    }
+}
+
+#[derive(Serialize)]
+struct DumpJson {
+    schema_version: u64,
+    builds: Vec<BuildJson>,
+    files: Vec<FileJson>,
+}
+
+#[derive(Serialize)]
+struct ShowJson {
+    file: FileJson,
+    build: Option<BuildJson>,
+}
+
+/// A build record, with hashes in hex and times in nanoseconds since the Unix
+/// epoch.
+#[derive(Serialize)]
+struct BuildJson {
+    hash: String,
+    last_start: u128,
+    last_end: Option<u128>,
+    input_set_digest: String,
+    additional_inputs: Vec<String>,
+    history: Vec<ExecRecordJson>,
+}
+
+#[derive(Serialize)]
+struct ExecRecordJson {
+    start: u128,
+    duration: u128,
+    succeeded: bool,
+    exit_code: Option<i32>,
+    peak_memory: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct FileJson {
+    path: String,
+    last_seen: u128,
+    generated_by: String,
+}
+
+fn portable_format(format: PortableFormat) -> Format {
+    match format {
+        PortableFormat::Json => Format::JsonLines,
+        PortableFormat::Postcard => Format::Postcard,
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn nanos(time: SystemTime) -> u128 {
+    match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(d) => d.as_nanos(),
+        Err(_) => 0,
+    }
+}
+
+impl BuildJson {
+    fn new(hash: BuildHash, info: BuildInfo) -> Self {
+        Self {
+            hash: hex(&hash.0),
+            last_start: nanos(info.last_start),
+            last_end: info.last_end.map(nanos),
+            input_set_digest: hex(&info.input_set_digest.0),
+            additional_inputs: info
+                .additional_inputs
+                .iter()
+                .map(|p| p.to_string_lossy().into_owned())
+                .collect(),
+            history: info.history.into_iter().map(ExecRecordJson::new).collect(),
+        }
+    }
+}
+
+impl ExecRecordJson {
+    fn new(record: ExecRecord) -> Self {
+        Self {
+            start: nanos(record.start),
+            duration: record.duration.as_nanos(),
+            succeeded: record.succeeded,
+            exit_code: record.exit_code,
+            peak_memory: record.peak_memory,
+        }
+    }
+}
+
+impl FileJson {
+    fn new(path: &Path, info: FileInfo) -> Self {
+        Self {
+            path: path.to_string_lossy().into_owned(),
+            last_seen: nanos(info.last_seen),
+            generated_by: hex(&info.generated_by.0),
+        }
+    }
+}
+
+/// Write all build and file records as JSON, sorted by hash and path.
+pub fn dump(db: &dyn ExecDb, out: &mut dyn Write) -> anyhow::Result<()> {
+    let rd = db.begin_read();
+    let mut builds: Vec<_> = rd.builds().collect();
+    builds.sort_by_key(|(hash, _)| hash.0);
+    let mut files: Vec<_> = rd.files().collect();
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let json = DumpJson {
+        schema_version: db.get_schema_version(),
+        builds: builds
+            .into_iter()
+            .map(|(hash, info)| BuildJson::new(hash, info))
+            .collect(),
+        files: files
+            .into_iter()
+            .map(|(path, info)| FileJson::new(&path, info))
+            .collect(),
+    };
+    serde_json::to_writer_pretty(&mut *out, &json)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Write the record of a file and of the build that generated it as JSON.
+pub fn show(db: &dyn ExecDb, path: &Path, out: &mut dyn Write) -> anyhow::Result<()> {
+    let rd = db.begin_read();
+    let file = rd
+        .get_file_info(path)
+        .ok_or_else(|| anyhow!("No record of {} in the database", path.display()))?;
+    let hash = file.generated_by;
+    let json = ShowJson {
+        file: FileJson::new(path, file),
+        build: rd
+            .get_build_info(hash)
+            .map(|info| BuildJson::new(hash, info)),
+    };
+    serde_json::to_writer_pretty(&mut *out, &json)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Remove the record of the build that generated `target` and of all its
+/// outputs, so that the build is outdated on the next run. Returns the number
+/// of output records removed.
+pub fn forget(db: &dyn ExecDb, target: &Path) -> anyhow::Result<usize> {
+    let (hash, outputs) = {
+        let rd = db.begin_read();
+        let file = rd
+            .get_file_info(target)
+            .ok_or_else(|| anyhow!("No record of {} in the database", target.display()))?;
+        let hash = file.generated_by;
+        let mut outputs: Vec<_> = rd.files_generated_by(hash).collect();
+        if !outputs.iter().any(|p| p == target) {
+            outputs.push(target.to_owned());
+        }
+        (hash, outputs)
+    };
+
+    let mut txn = db.begin_write();
+    txn.invalidate_build(hash);
+    for output in &outputs {
+        txn.invalidate_file(output);
+    }
+    txn.commit();
+    Ok(outputs.len())
+}
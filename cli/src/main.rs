@@ -3,6 +3,7 @@ use clap::Parser;
 use crate::cli::{Args, NinjaSubcommand};
 
 mod cli;
+mod db;
 mod ninja;
 
 fn main() -> anyhow::Result<()> {
@@ -16,6 +17,7 @@ fn main() -> anyhow::Result<()> {
         let argv = Args::parse();
         match argv.subcommand {
             cli::Subcommand::Ninja(ninja_subcommand) => ninja::run(&ninja_subcommand),
+            cli::Subcommand::Db(db_subcommand) => db::run(&db_subcommand),
         }
     }
 }
@@ -0,0 +1,412 @@
+//! Importing the incremental state of ninja from `.ninja_log` and
+//! `.ninja_deps`, so that switching from ninja doesn't rebuild everything.
+//!
+//! A build is imported only if ninja itself would consider it up to date:
+//! every output is in the build log with the hash of the current command,
+//! discovered dependencies are in the deps log if the rule has `deps`, and no
+//! input has been modified since the build ran. Everything else is left out
+//! of the database, so n2o5 rebuilds it as usual.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, anyhow, bail};
+use n2o5::{
+    ExecDb,
+    db::{BuildInfo, ExecRecord, FileInfo, InputHash},
+    graph::{hash_build, hash_input_set},
+    world::World,
+};
+
+use crate::ninja::{
+    convert::ConvertOutput,
+    model::{Build, NinjaFile},
+};
+
+pub static NINJA_LOG_FILENAME: &str = ".ninja_log";
+pub static NINJA_DEPS_FILENAME: &str = ".ninja_deps";
+
+/// Timestamps below this value are in seconds rather than nanoseconds, as
+/// written by ninja before 1.10. In nanoseconds, it would be a time within the
+/// first two minutes of 1970.
+const SECONDS_TIMESTAMP_LIMIT: i64 = 100_000_000_000;
+
+/// An entry of `.ninja_log`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    /// Milliseconds since the start of the ninja run
+    pub start_ms: u64,
+    /// Milliseconds since the start of the ninja run
+    pub end_ms: u64,
+    /// The time the command started, or the mtime of the output for some
+    /// rules. `None` if it was not recorded.
+    pub mtime: Option<SystemTime>,
+    /// The hash of the command line, see [`command_hash`]
+    pub command_hash: u64,
+}
+
+/// The contents of `.ninja_log`, keyed by output path.
+#[derive(Debug, Default)]
+pub struct NinjaLog {
+    pub entries: HashMap<String, LogEntry>,
+    /// The number of lines that could not be parsed and were skipped
+    pub malformed_lines: usize,
+}
+
+/// A record of `.ninja_deps`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DepsEntry {
+    /// The mtime of the output when the dependencies were recorded
+    pub mtime: Option<SystemTime>,
+    /// The discovered dependencies
+    pub inputs: Vec<String>,
+}
+
+/// The contents of `.ninja_deps`, keyed by output path.
+#[derive(Debug, Default)]
+pub struct NinjaDeps {
+    pub entries: HashMap<String, DepsEntry>,
+}
+
+/// Statistics of an import.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ImportStats {
+    /// Builds that were written to the database as up to date
+    pub imported: usize,
+    /// Builds that could not be proven up to date, or were already known
+    pub skipped: usize,
+}
+
+/// The hash ninja uses for command lines in `.ninja_log`, which is
+/// MurmurHash64A with a fixed seed.
+pub fn command_hash(command: &str) -> u64 {
+    const SEED: u64 = 0xDECA_FBAD_DECA_FBAD;
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+
+    let data = command.as_bytes();
+    let mut h = SEED ^ (data.len() as u64).wrapping_mul(M);
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &b) in tail.iter().enumerate() {
+            h ^= (b as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+/// Convert a timestamp of ninja to a time. Zero means unknown.
+fn timestamp(value: i64) -> Option<SystemTime> {
+    match value {
+        ..=0 => None,
+        1..SECONDS_TIMESTAMP_LIMIT => {
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(value as u64))
+        }
+        _ => Some(SystemTime::UNIX_EPOCH + Duration::from_nanos(value as u64)),
+    }
+}
+
+/// Parse the contents of `.ninja_log`. Versions 5 and 6 are supported.
+///
+/// Later entries of the same output replace earlier ones, as in ninja.
+pub fn parse_log(content: &str) -> anyhow::Result<NinjaLog> {
+    let mut lines = content.lines();
+    let header = lines.next().unwrap_or_default();
+    let version = header
+        .strip_prefix("# ninja log v")
+        .and_then(|v| v.trim().parse::<u32>().ok())
+        .ok_or_else(|| anyhow!("Not a ninja log file"))?;
+    if !(5..=6).contains(&version) {
+        bail!("Unsupported ninja log version {version}");
+    }
+
+    let mut log = NinjaLog::default();
+    for line in lines {
+        let parse = || -> Option<(String, LogEntry)> {
+            let mut fields = line.split('\t');
+            let start_ms = fields.next()?.parse().ok()?;
+            let end_ms = fields.next()?.parse().ok()?;
+            let mtime = fields.next()?.parse().ok()?;
+            let output = fields.next()?;
+            let command_hash = u64::from_str_radix(fields.next()?, 16).ok()?;
+            let entry = LogEntry {
+                start_ms,
+                end_ms,
+                mtime: timestamp(mtime),
+                command_hash,
+            };
+            Some((output.to_owned(), entry))
+        };
+        // Ninja skips lines it cannot parse, such as the last line of a log
+        // interrupted while writing.
+        match parse() {
+            Some((output, entry)) => {
+                log.entries.insert(output, entry);
+            }
+            None if line.is_empty() => {}
+            None => log.malformed_lines += 1,
+        }
+    }
+    Ok(log)
+}
+
+/// Parse the contents of `.ninja_deps`. Version 4 is supported, which is
+/// written by ninja 1.10 and later.
+///
+/// Reading stops at the first damaged record, like ninja does.
+pub fn parse_deps(data: &[u8]) -> anyhow::Result<NinjaDeps> {
+    const SIGNATURE: &[u8] = b"# ninjadeps\n";
+    const VERSION: u32 = 4;
+
+    let rest = data
+        .strip_prefix(SIGNATURE)
+        .ok_or_else(|| anyhow!("Not a ninja deps file"))?;
+    let (version, mut rest) =
+        split_u32(rest).ok_or_else(|| anyhow!("Truncated ninja deps file"))?;
+    if version != VERSION {
+        bail!("Unsupported ninja deps version {version}");
+    }
+
+    let mut paths: Vec<String> = vec![];
+    let mut deps: HashMap<u32, DepsEntry> = HashMap::new();
+    while let Some((header, after)) = split_u32(rest) {
+        let is_deps = header & 0x8000_0000 != 0;
+        let size = (header & 0x7FFF_FFFF) as usize;
+        let Some(record) = after.get(..size) else {
+            break;
+        };
+        rest = &after[size..];
+
+        if is_deps {
+            if !size.is_multiple_of(4) || size < 12 {
+                break;
+            }
+            let ints = record
+                .chunks_exact(4)
+                .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+                .collect::<Vec<_>>();
+            let mtime = ((ints[2] as u64) << 32 | ints[1] as u64) as i64;
+            let Some(inputs) = ints[3..]
+                .iter()
+                .map(|&id| paths.get(id as usize).cloned())
+                .collect::<Option<Vec<_>>>()
+            else {
+                break;
+            };
+            let entry = DepsEntry {
+                mtime: timestamp(mtime),
+                inputs,
+            };
+            deps.insert(ints[0], entry);
+        } else {
+            // The path is padded with NULs to a multiple of 4 bytes, followed
+            // by the one's complement of its id as a checksum.
+            let Some((path, checksum)) = record
+                .split_last_chunk::<4>()
+                .map(|(path, checksum)| (path, u32::from_le_bytes(*checksum)))
+            else {
+                break;
+            };
+            if !checksum != paths.len() as u32 {
+                break;
+            }
+            let path = path.strip_suffix(b"\0").unwrap_or(path);
+            let path = path.strip_suffix(b"\0").unwrap_or(path);
+            let path = path.strip_suffix(b"\0").unwrap_or(path);
+            paths.push(String::from_utf8_lossy(path).into_owned());
+        }
+    }
+
+    let entries = deps
+        .into_iter()
+        .filter_map(|(id, entry)| Some((paths.get(id as usize)?.clone(), entry)))
+        .collect();
+    Ok(NinjaDeps { entries })
+}
+
+fn split_u32(data: &[u8]) -> Option<(u32, &[u8])> {
+    let (value, rest) = data.split_first_chunk::<4>()?;
+    Some((u32::from_le_bytes(*value), rest))
+}
+
+/// Read `.ninja_log` and `.ninja_deps` from the given directory. A missing
+/// deps log is treated as empty, since it only exists if a rule uses `deps`.
+pub fn read_logs(dir: &Path) -> anyhow::Result<(NinjaLog, NinjaDeps)> {
+    let log_path = dir.join(NINJA_LOG_FILENAME);
+    let content =
+        std::fs::read(&log_path).with_context(|| format!("Failed to read {log_path:?}"))?;
+    let log = parse_log(&String::from_utf8_lossy(&content))
+        .with_context(|| format!("Failed to parse {log_path:?}"))?;
+
+    let deps_path = dir.join(NINJA_DEPS_FILENAME);
+    let deps = match std::fs::read(&deps_path) {
+        Ok(data) => parse_deps(&data).with_context(|| format!("Failed to parse {deps_path:?}"))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => NinjaDeps::default(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {deps_path:?}")),
+    };
+    Ok((log, deps))
+}
+
+/// Write the builds that ninja considers up to date into the database.
+///
+/// Builds that already have a record in the database are left alone, since
+/// they may have been rebuilt by n2o5 after ninja ran.
+pub fn import(
+    parsed: &NinjaFile<'_>,
+    converted: &ConvertOutput,
+    log: &NinjaLog,
+    deps: &NinjaDeps,
+    db: &dyn ExecDb,
+    world: &dyn World,
+) -> ImportStats {
+    let graph = &converted.graph;
+    let mut stats = ImportStats::default();
+    let mut records = vec![];
+    let read = db.begin_read();
+
+    for build in &parsed.builds {
+        let Some(id) = build
+            .outputs
+            .iter()
+            .chain(&build.implicit_outputs)
+            .find_map(|out| {
+                let file = graph.lookup_fileid(Path::new(out.as_ref()))?;
+                converted.file_to_build.get(&file).copied()
+            })
+        else {
+            continue;
+        };
+        let node = graph.lookup_build(id).expect("invalid BuildId");
+        let build_hash = hash_build(node, graph);
+        if read.get_build_info(build_hash).is_some() {
+            stats.skipped += 1;
+            continue;
+        }
+
+        let inputs = node
+            .ins
+            .iter()
+            .map(|&file| graph.lookup_path(file).expect("invalid FileId").as_path());
+        let input_set_digest = hash_input_set(id, graph);
+        let Some(info) = prove_up_to_date(build, inputs, input_set_digest, log, deps, world) else {
+            stats.skipped += 1;
+            continue;
+        };
+        let outs = node
+            .outs
+            .iter()
+            .map(|&out| graph.lookup_path(out).expect("invalid FileId"))
+            .collect::<Vec<_>>();
+        records.push((build_hash, info, outs));
+    }
+    drop(read);
+
+    let mut txn = db.begin_write();
+    for (build_hash, info, outs) in records {
+        for path in outs {
+            let Ok(last_seen) = world.mtime(path) else {
+                continue;
+            };
+            let file_info = FileInfo {
+                last_seen,
+                generated_by: build_hash,
+            };
+            txn.set_file_info(path, file_info);
+        }
+        txn.set_build_info(build_hash, info);
+        stats.imported += 1;
+    }
+    txn.commit();
+    stats
+}
+
+/// Check whether ninja would consider the build up to date, and if so, return
+/// the build record to store.
+fn prove_up_to_date<'a>(
+    build: &Build<'_>,
+    inputs: impl Iterator<Item = &'a Path>,
+    input_set_digest: InputHash,
+    log: &NinjaLog,
+    deps: &NinjaDeps,
+    world: &dyn World,
+) -> Option<BuildInfo> {
+    let command_hash = command_hash(&build.command);
+    let outputs = build.outputs.iter().chain(&build.implicit_outputs);
+
+    // Every output must have been built with the current command, and exist.
+    let mut first_entry = None;
+    let mut last_start: Option<SystemTime> = None;
+    for out in outputs.clone() {
+        let entry = log.entries.get(out.as_ref())?;
+        if entry.command_hash != command_hash {
+            return None;
+        }
+        let mtime = entry.mtime?;
+        last_start = Some(last_start.map_or(mtime, |t| t.min(mtime)));
+        first_entry.get_or_insert(entry);
+        world.mtime(Path::new(out.as_ref())).ok()?;
+    }
+    let (first_entry, last_start) = (first_entry?, last_start?);
+
+    // Discovered dependencies are only known if they are in the deps log.
+    // Those only listed in a depfile are not, since ninja reads the depfile
+    // again on every run.
+    let additional_inputs = if build.deps.is_some() {
+        let first_out = outputs.clone().next()?;
+        let entry = deps.entries.get(first_out.as_ref())?;
+        let out_mtime = world.mtime(Path::new(first_out.as_ref())).ok()?;
+        if entry.mtime? < out_mtime {
+            return None;
+        }
+        entry.inputs.iter().map(PathBuf::from).collect::<Vec<_>>()
+    } else if build.depfile.is_some() {
+        return None;
+    } else {
+        vec![]
+    };
+
+    // No input may have been modified since the build started.
+    let modified_since = |path: &Path| match world.mtime(path) {
+        Ok(mtime) => mtime > last_start,
+        Err(_) => true,
+    };
+    if inputs.into_iter().any(modified_since) || additional_inputs.iter().any(|p| modified_since(p))
+    {
+        return None;
+    }
+
+    let duration = Duration::from_millis(first_entry.end_ms.saturating_sub(first_entry.start_ms));
+    Some(BuildInfo {
+        last_start,
+        last_end: Some(last_start + duration),
+        input_set_digest,
+        additional_inputs,
+        // Ninja only logs commands that succeeded
+        history: vec![ExecRecord {
+            start: last_start,
+            duration,
+            succeeded: true,
+            exit_code: Some(0),
+            peak_memory: None,
+        }],
+    })
+}
@@ -1,14 +1,25 @@
 pub mod convert;
+pub mod import;
 pub mod model;
 pub mod parser;
 pub mod run;
 mod tokenizer;
+pub mod tool;
 
-use crate::{cli::NinjaSubcommand, ninja::parser::ParseSource};
+use crate::{
+    cli::{DebugMode, NinjaSubcommand, ProgressKind},
+    ninja::parser::ParseSource,
+};
+
+use std::{fs::File, io::BufWriter, path::Path};
 
 use anyhow::{Context, anyhow};
 use n2o5::exec::{ExecConfig, Executor};
 use n2o5::progress::fancy::FancyConsoleProgress;
+use n2o5::progress::format::StatusFormat;
+use n2o5::progress::json::JsonProgress;
+use n2o5::progress::trace::ChromeTraceProgress;
+use n2o5::progress::{Progress, Tee};
 use n2o5_redb::ExecRedb;
 
 static NINJA_DEFAULT_FILENAME: &str = "build.ninja";
@@ -18,11 +29,19 @@ pub fn run(cmd: &NinjaSubcommand) -> anyhow::Result<()> {
     assert!(!cmd.quiet, "Quiet mode not yet implemented");
     assert!(!cmd.dry_run, "Dry-run mode not yet implemented");
 
+    if cmd.debug.contains(&DebugMode::List) {
+        println!("{}", DebugMode::HELP);
+        return Ok(());
+    }
+
     // Change working directory if requested
     if let Some(path) = &cmd.chdir {
         std::env::set_current_dir(path).context("failed to change directory")?;
     }
 
+    // Keep other n2o5 processes out of this directory until we are done
+    let _lock = crate::db::lock(Path::new(NINJA_DB_FILENAME), !cmd.no_wait)?;
+
     // Parse Ninja file
     let parse_source = ParseSource::new(NINJA_DEFAULT_FILENAME);
     let parsed = parser::parse(&parse_source, parse_source.main_file())
@@ -30,9 +49,19 @@ pub fn run(cmd: &NinjaSubcommand) -> anyhow::Result<()> {
 
     // Convert to n2o5 graph
     let converted = convert::ninja_to_n2o5(&parsed)?;
+    let new_db = !Path::new(NINJA_DB_FILENAME).exists();
     let db = ExecRedb::open(NINJA_DB_FILENAME)
         .context("Failed to open or create the n2o5_ninja.db database file")?;
 
+    // Carry over the state of a previous ninja build on the first run
+    if new_db && Path::new(import::NINJA_LOG_FILENAME).exists() && cmd.tool.is_none() {
+        tool::import_ninja_logs(&parsed, &converted, &db)?;
+    }
+
+    if let Some(tool) = &cmd.tool {
+        return tool::run(tool, cmd, &parsed, &converted, &db);
+    }
+
     // Map jobs -> parallelism; default to available parallelism
     let parallelism = match cmd.jobs {
         Some(n) if n > 0 => n,
@@ -40,11 +69,37 @@ pub fn run(cmd: &NinjaSubcommand) -> anyhow::Result<()> {
             .map(|nz| nz.get())
             .unwrap_or(1),
     };
-    let cfg = ExecConfig { parallelism };
+    let cfg = ExecConfig {
+        parallelism,
+        ..Default::default()
+    };
 
     // Build executor
-    let progress = FancyConsoleProgress::new();
-    let mut exec = Executor::new(&cfg, &converted.graph, &db, &progress, &());
+    let progress: Box<dyn Progress> = match cmd.progress {
+        ProgressKind::Fancy => Box::new(match std::env::var("NINJA_STATUS") {
+            Ok(format) => FancyConsoleProgress::with_format(
+                StatusFormat::parse(&format).context("Invalid NINJA_STATUS")?,
+            ),
+            Err(_) => FancyConsoleProgress::new(),
+        }),
+        ProgressKind::Json => Box::new(JsonProgress::new(std::io::stdout())),
+    };
+    let trace = cmd.debug.iter().rev().find_map(|mode| match mode {
+        DebugMode::Trace(path) => Some(path),
+        DebugMode::List => None,
+    });
+    let progress: Box<dyn Progress> = match trace {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("Failed to create trace file {path:?}"))?;
+            Box::new(Tee(
+                progress,
+                ChromeTraceProgress::new(BufWriter::new(file)),
+            ))
+        }
+        None => progress,
+    };
+    let mut exec = Executor::new(&cfg, &converted.graph, &db, &*progress, &());
 
     // Resolve targets (skip dry-run; we always run)
     let wanted = run::resolve_targets_to_build_ids(&cmd.targets, &parsed, &converted);
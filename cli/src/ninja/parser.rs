@@ -452,8 +452,7 @@ fn parse_expand_word<'s>(
     inline: bool,
 ) -> Result<Cow<'s, str>, Error> {
     let mut res: std::borrow::Cow<'s, str> = std::borrow::Cow::Borrowed("");
-    loop {
-        let Some(peek) = lexer.peek()? else { break };
+    while let Some(peek) = lexer.peek()? {
         match peek {
             Token::Word(w) => {
                 if res.is_empty() {
@@ -495,8 +494,7 @@ fn parse_expand_word<'s>(
 fn parse_noexpand_word<'s>(lexer: &mut Lexer<'s>) -> Result<Expandable<'s>, Error> {
     let mut res = SmallVec::new();
     let mut acc: Option<Cow<'_, str>> = None;
-    loop {
-        let Some(peek) = lexer.peek()? else { break };
+    while let Some(peek) = lexer.peek()? {
         match peek {
             Token::Word(w) | Token::Spaces(w) => {
                 if let Some(acc) = acc.as_mut() {
@@ -0,0 +1,83 @@
+use std::path::Path;
+
+use anyhow::{Context, anyhow};
+use n2o5::{ExecDb, world::LOCAL_WORLD};
+
+use crate::{cli::NinjaSubcommand, ninja::model::NinjaFile};
+
+use super::{convert::ConvertOutput, import, run::resolve_targets_to_build_ids};
+
+/// Run a ninja subtool, as in `ninja -t <tool>`.
+pub fn run(
+    tool: &str,
+    cmd: &NinjaSubcommand,
+    parsed: &NinjaFile<'_>,
+    converted: &ConvertOutput,
+    db: &dyn ExecDb,
+) -> anyhow::Result<()> {
+    match tool {
+        "clean" => clean(cmd, parsed, converted, db),
+        "cleandead" => clean_dead(converted, db),
+        "recompact" => recompact(converted, db),
+        "import" => import_ninja_logs(parsed, converted, db),
+        _ => Err(anyhow!("Unknown tool: {tool}")),
+    }
+}
+
+fn clean(
+    cmd: &NinjaSubcommand,
+    parsed: &NinjaFile<'_>,
+    converted: &ConvertOutput,
+    db: &dyn ExecDb,
+) -> anyhow::Result<()> {
+    println!("Cleaning...");
+    let removed = if cmd.targets.is_empty() {
+        n2o5::clean::clean_all(&converted.graph, db, &LOCAL_WORLD)
+    } else {
+        let wanted = resolve_targets_to_build_ids(&cmd.targets, parsed, converted);
+        if wanted.is_empty() {
+            return Err(anyhow!("No matching builds for targets: {:?}", cmd.targets));
+        }
+        n2o5::clean::clean(&converted.graph, db, &LOCAL_WORLD, wanted)
+    }
+    .context("Failed to remove build outputs")?;
+    println!("{removed} files.");
+    Ok(())
+}
+
+fn clean_dead(converted: &ConvertOutput, db: &dyn ExecDb) -> anyhow::Result<()> {
+    let removed = n2o5::clean::clean_dead(&converted.graph, db, &LOCAL_WORLD)
+        .context("Failed to remove dead build outputs")?;
+    println!("Removed {removed} dead files.");
+    Ok(())
+}
+
+fn recompact(converted: &ConvertOutput, db: &dyn ExecDb) -> anyhow::Result<()> {
+    let stats = n2o5::db::gc::collect_garbage(db, &converted.graph);
+    println!(
+        "Removed {} stale build records and {} stale file records.",
+        stats.builds, stats.files
+    );
+    Ok(())
+}
+
+/// Import `.ninja_log` and `.ninja_deps` from the current directory.
+pub fn import_ninja_logs(
+    parsed: &NinjaFile<'_>,
+    converted: &ConvertOutput,
+    db: &dyn ExecDb,
+) -> anyhow::Result<()> {
+    let (log, deps) = import::read_logs(Path::new("."))?;
+    if log.malformed_lines > 0 {
+        println!(
+            "Skipped {} malformed lines of the ninja log.",
+            log.malformed_lines
+        );
+    }
+    let stats = import::import(parsed, converted, &log, &deps, db, &LOCAL_WORLD);
+    println!(
+        "Imported {} up-to-date builds from ninja, skipped {}.",
+        stats.imported, stats.skipped
+    );
+    Ok(())
+}
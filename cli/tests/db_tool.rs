@@ -0,0 +1,140 @@
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use n2o5::{
+    ExecDb,
+    db::{BuildHash, BuildInfo, ExecRecord, FileInfo, InputHash, in_memory::InMemoryDb},
+};
+use n2o5_cli::db::{dump, forget, open, open_existing, show};
+
+fn time(secs: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// Record build `n` as generating the given files.
+fn record(db: &dyn ExecDb, n: u8, outputs: &[&str]) {
+    let mut txn = db.begin_write();
+    txn.set_build_info(
+        BuildHash([n; 16]),
+        BuildInfo {
+            last_start: time(1),
+            last_end: Some(time(2)),
+            input_set_digest: InputHash([0xab; 16]),
+            additional_inputs: vec!["dep.h".into()],
+            history: vec![ExecRecord {
+                start: time(1),
+                duration: Duration::from_secs(1),
+                succeeded: true,
+                exit_code: Some(0),
+                peak_memory: None,
+            }],
+        },
+    );
+    for output in outputs {
+        txn.set_file_info(
+            Path::new(output),
+            FileInfo {
+                last_seen: time(2),
+                generated_by: BuildHash([n; 16]),
+            },
+        );
+    }
+    txn.commit();
+}
+
+fn to_string(f: impl FnOnce(&mut Vec<u8>) -> anyhow::Result<()>) -> String {
+    let mut out = vec![];
+    f(&mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn dump_and_show() {
+    let db = InMemoryDb::default();
+    record(&db, 1, &["a.o"]);
+
+    let dumped = to_string(|out| dump(&db, out));
+    let json: serde_json::Value = serde_json::from_str(&dumped).unwrap();
+    assert_eq!(json["builds"][0]["hash"], "01".repeat(16));
+    assert_eq!(json["builds"][0]["last_end"], 2_000_000_000u64);
+    assert_eq!(json["builds"][0]["additional_inputs"][0], "dep.h");
+    assert_eq!(json["builds"][0]["history"][0]["exit_code"], 0);
+    assert_eq!(json["files"][0]["path"], "a.o");
+
+    let shown = to_string(|out| show(&db, Path::new("a.o"), out));
+    let json: serde_json::Value = serde_json::from_str(&shown).unwrap();
+    assert_eq!(json["file"]["generated_by"], "01".repeat(16));
+    assert_eq!(json["build"]["input_set_digest"], "ab".repeat(16));
+
+    assert!(show(&db, Path::new("missing.o"), &mut vec![]).is_err());
+}
+
+#[test]
+fn forget_removes_the_generating_build() {
+    let db = InMemoryDb::default();
+    record(&db, 1, &["a.o", "a.d"]);
+    record(&db, 2, &["b.o"]);
+
+    assert_eq!(forget(&db, Path::new("a.o")).unwrap(), 2);
+    let rd = db.begin_read();
+    assert!(rd.get_build_info(BuildHash([1; 16])).is_none());
+    assert!(rd.get_file_info(Path::new("a.d")).is_none());
+    assert!(rd.get_build_info(BuildHash([2; 16])).is_some());
+    assert!(rd.get_file_info(Path::new("b.o")).is_some());
+    drop(rd);
+
+    assert!(forget(&db, Path::new("a.o")).is_err());
+}
+
+#[test]
+fn open_detects_backends() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let redb = dir.join("redb.db");
+    let heed = dir.join("heed");
+    let sqlite = dir.join("sqlite.db");
+    let dumb = dir.join("dumb.db");
+    std::fs::create_dir(&heed).unwrap();
+    record(&n2o5_redb::ExecRedb::open(&redb).unwrap(), 1, &["redb.o"]);
+    record(&n2o5_heed::ExecHeedDb::open(&heed).unwrap(), 1, &["heed.o"]);
+    record(
+        &n2o5_sqlite::ExecSqlite::open(&sqlite).unwrap(),
+        1,
+        &["sqlite.o"],
+    );
+    record(&n2o5::db::dumb::DumbDb::new(&dumb).unwrap(), 1, &["dumb.o"]);
+
+    for (path, output) in [
+        (redb.clone(), "redb.o"),
+        (heed, "heed.o"),
+        (sqlite, "sqlite.o"),
+        (dumb.clone(), "dumb.o"),
+    ] {
+        let db = open(&path, None, "", false).unwrap();
+        assert!(db.begin_read().get_file_info(Path::new(output)).is_some());
+        drop(db);
+        let db = open_existing(&path, None, "").unwrap();
+        assert!(db.begin_read().get_file_info(Path::new(output)).is_some());
+    }
+    assert!(open(&dir.join("missing.db"), None, "", false).is_err());
+    assert!(open(&dumb, None, "other", false).is_err());
+    assert!(open_existing(&dir.join("missing.db"), None, "").is_err());
+    assert!(open_existing(&redb, None, "other").is_err());
+}
+
+#[test]
+fn open_existing_leaves_other_versions_alone() {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("dumb.db");
+    record(&n2o5::db::dumb::DumbDb::new(&path).unwrap(), 1, &["a.o"]);
+    // Bump the stored schema version, which follows the 16-byte magic header
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[16..24].copy_from_slice(&(n2o5::db::SCHEMA_VERSION + 1).to_le_bytes());
+    std::fs::write(&path, &bytes).unwrap();
+
+    let err = open_existing(&path, None, "").err().unwrap();
+    assert!(format!("{err:#}").contains("schema version"), "{err:#}");
+    assert_eq!(std::fs::read(&path).unwrap(), bytes);
+}
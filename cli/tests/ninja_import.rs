@@ -0,0 +1,188 @@
+use std::{
+    fs::File,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use n2o5::{
+    db::in_memory::InMemoryDb,
+    exec::{ExecConfig, Executor},
+    progress::noop::NoopProgress,
+    world::LOCAL_WORLD,
+};
+use n2o5_cli::ninja::{
+    convert::ninja_to_n2o5,
+    import::{ImportStats, command_hash, import, parse_deps, parse_log},
+    parser::{ParseSource, parse},
+};
+
+fn time(secs: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs)
+}
+
+fn nanos(t: SystemTime) -> u128 {
+    t.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos()
+}
+
+fn create(path: &Path, mtime: SystemTime) {
+    File::create(path).unwrap().set_modified(mtime).unwrap();
+}
+
+/// Encode a `.ninja_deps` file with the given records of output and inputs.
+fn deps_file(records: &[(&str, SystemTime, &[&str])]) -> Vec<u8> {
+    let mut data = b"# ninjadeps\n".to_vec();
+    data.extend(4u32.to_le_bytes());
+    let mut ids: Vec<String> = vec![];
+    let mut id_of = |data: &mut Vec<u8>, path: &str| {
+        if let Some(id) = ids.iter().position(|p| p == path) {
+            return id as u32;
+        }
+        let padded = path.len().div_ceil(4) * 4;
+        data.extend(((padded + 4) as u32).to_le_bytes());
+        data.extend(path.as_bytes());
+        data.resize(data.len() + padded - path.len(), 0);
+        data.extend((!(ids.len() as u32)).to_le_bytes());
+        ids.push(path.to_owned());
+        ids.len() as u32 - 1
+    };
+    for &(out, mtime, inputs) in records {
+        let out = id_of(&mut data, out);
+        let inputs = inputs
+            .iter()
+            .map(|input| id_of(&mut data, input))
+            .collect::<Vec<_>>();
+        let mtime = nanos(mtime) as u64;
+        data.extend((((3 + inputs.len()) * 4) as u32 | 0x8000_0000).to_le_bytes());
+        data.extend(out.to_le_bytes());
+        data.extend((mtime as u32).to_le_bytes());
+        data.extend(((mtime >> 32) as u32).to_le_bytes());
+        for input in inputs {
+            data.extend(input.to_le_bytes());
+        }
+    }
+    data
+}
+
+#[test]
+fn parse_logs() {
+    let log = parse_log(
+        "# ninja log v5\n\
+        0\t10\t1700000000000000000\ta.o\t1f2e3d\n\
+        5\t20\t0\tb.o\tabc\n\
+        12\t30\t1700000001000000000\ta.o\t4c\n\
+        1\t2\tgarbage\n",
+    )
+    .unwrap();
+    assert_eq!(log.entries.len(), 2);
+    assert_eq!(log.malformed_lines, 1);
+    let a = &log.entries["a.o"];
+    assert_eq!((a.start_ms, a.end_ms, a.command_hash), (12, 30, 0x4c));
+    assert_eq!(a.mtime, Some(time(1)));
+    assert_eq!(log.entries["b.o"].mtime, None);
+    assert!(parse_log("# ninja log v4\n").is_err());
+
+    let mut data = deps_file(&[
+        ("a.o", time(3), &["a.c", "a.h"]),
+        ("b.o", time(4), &["a.h"]),
+    ]);
+    let deps = parse_deps(&data).unwrap();
+    assert_eq!(deps.entries["a.o"].inputs, ["a.c", "a.h"]);
+    assert_eq!(deps.entries["a.o"].mtime, Some(time(3)));
+    assert_eq!(deps.entries["b.o"].inputs, ["a.h"]);
+
+    // A truncated last record is ignored
+    data.truncate(data.len() - 2);
+    let deps = parse_deps(&data).unwrap();
+    assert_eq!(deps.entries.len(), 1);
+}
+
+#[test]
+fn import_up_to_date_builds() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let p = |name: &str| dir.join(name).to_str().unwrap().to_owned();
+    let ninja = format!(
+        "rule cp\n  command = cp $in $out && touch $out.ran\n\
+        rule cc\n  command = cp $in $out && touch $out.ran\n  depfile = $out.d\n  deps = gcc\n\
+        build {a_o}: cp {a_c}\n\
+        build {b_o}: cp {b_c}\n\
+        build {c_o}: cc {c_c}\n\
+        build {d_o}: cc {d_c}\n\
+        build {e_o}: cp {e_c}\n",
+        a_o = p("a.o"),
+        a_c = p("a.c"),
+        b_o = p("b.o"),
+        b_c = p("b.c"),
+        c_o = p("c.o"),
+        c_c = p("c.c"),
+        d_o = p("d.o"),
+        d_c = p("d.c"),
+        e_o = p("e.o"),
+        e_c = p("e.c"),
+    );
+    let source = ParseSource::new_in_memory(ninja);
+    let parsed = parse(&source, source.main_file()).unwrap();
+    let converted = ninja_to_n2o5(&parsed).unwrap();
+
+    // Sources at 0, headers at 0 except d.h modified after the build at 5,
+    // outputs written at 2, and e.c modified after the build.
+    for name in ["a.c", "b.c", "c.c", "c.h", "d.c"] {
+        create(&dir.join(name), time(0));
+    }
+    create(&dir.join("d.h"), time(5));
+    create(&dir.join("e.c"), time(5));
+    for name in ["a.o", "b.o", "c.o", "d.o", "e.o"] {
+        create(&dir.join(name), time(2));
+    }
+
+    let hash = |build: usize| command_hash(&parsed.builds[build].command);
+    let mut log = "# ninja log v5\n".to_owned();
+    for (build, out, command_hash) in [
+        (0, "a.o", hash(0)),
+        // Built with a different command
+        (1, "b.o", hash(1) ^ 1),
+        (2, "c.o", hash(2)),
+        (3, "d.o", hash(3)),
+        (4, "e.o", hash(4)),
+    ] {
+        log += &format!(
+            "{}\t{}\t{}\t{}\t{command_hash:x}\n",
+            build * 10,
+            build * 10 + 5,
+            nanos(time(1)),
+            p(out)
+        );
+    }
+    let log = parse_log(&log).unwrap();
+    let deps = parse_deps(&deps_file(&[
+        (&p("c.o"), time(2), &[&p("c.c"), &p("c.h")]),
+        (&p("d.o"), time(2), &[&p("d.c"), &p("d.h")]),
+    ]))
+    .unwrap();
+
+    let db = InMemoryDb::default();
+    let stats = import(&parsed, &converted, &log, &deps, &db, &LOCAL_WORLD);
+    assert_eq!(
+        stats,
+        ImportStats {
+            imported: 2,
+            skipped: 3
+        }
+    );
+
+    let cfg = ExecConfig::default();
+    let mut exec = Executor::new(&cfg, &converted.graph, &db, &NoopProgress, &());
+    exec.want(converted.graph.nodes().map(|(id, _)| id));
+    exec.run().unwrap();
+
+    let ran = |name: &str| dir.join(format!("{name}.ran")).exists();
+    assert!(!ran("a.o"));
+    assert!(ran("b.o"));
+    assert!(!ran("c.o"));
+    assert!(ran("d.o"));
+    assert!(ran("e.o"));
+
+    // Importing again keeps the records written by n2o5
+    let stats = import(&parsed, &converted, &log, &deps, &db, &LOCAL_WORLD);
+    assert_eq!(stats.imported, 0);
+}
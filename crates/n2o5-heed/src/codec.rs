@@ -1,9 +1,9 @@
 //! Encoding and decoding of values
 
-use std::{borrow::Cow, os::unix::ffi::OsStrExt, path::Path};
+use std::{borrow::Cow, ffi::OsStr, path::Path};
 
 use heed::{BytesDecode, BytesEncode};
-use n2o5::db::{BuildHash, BuildInfo, FileInfo};
+use n2o5::db::{BuildHash, BuildInfo, BuildInfoV1, FileInfo};
 
 pub(crate) struct PathKey;
 
@@ -19,7 +19,10 @@ impl<'a> BytesDecode<'a> for PathKey {
     type DItem = &'a Path;
 
     fn bytes_decode(bytes: &'a [u8]) -> Result<Self::DItem, heed::BoxedError> {
-        Ok(Path::new(std::ffi::OsStr::from_bytes(bytes)))
+        // SAFETY: the key was written by `bytes_encode` on this platform
+        Ok(Path::new(unsafe {
+            OsStr::from_encoded_bytes_unchecked(bytes)
+        }))
     }
 }
 
@@ -63,6 +66,19 @@ impl<'a> BytesDecode<'a> for BuildInfoWrap {
     }
 }
 
+/// Decodes a build record of schema version 1, which lacks the history. Only
+/// used to upgrade old records.
+pub(crate) struct BuildInfoV1Wrap;
+
+impl<'a> BytesDecode<'a> for BuildInfoV1Wrap {
+    type DItem = BuildInfo;
+
+    fn bytes_decode(bytes: &'a [u8]) -> Result<Self::DItem, heed::BoxedError> {
+        let info: BuildInfoV1 = postcard::from_bytes(bytes)?;
+        Ok(info.into())
+    }
+}
+
 pub(crate) struct BuildHashKey;
 
 impl<'a> BytesEncode<'a> for BuildHashKey {
@@ -85,3 +101,10 @@ impl<'a> BytesDecode<'a> for BuildHashKey {
         Ok(BuildHash(arr))
     }
 }
+
+/// Key of the generated file index, the build hash followed by the path.
+pub(crate) fn generated_key(hash: &BuildHash, path: &Path) -> Vec<u8> {
+    let mut key = hash.0.to_vec();
+    key.extend_from_slice(path.as_os_str().as_encoded_bytes());
+    key
+}
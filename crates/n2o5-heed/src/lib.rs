@@ -2,71 +2,309 @@
 
 use std::path::Path;
 
-use heed::EnvOpenOptions;
-use n2o5::db::ExecDb;
+use heed::{
+    EnvOpenOptions,
+    byteorder::BigEndian,
+    types::{Bytes, Str, U64, Unit},
+};
+use n2o5::db::{BuildInfo, ExecDb, FileInfo, IntegrityReport, OpenExistingError};
+use tracing::warn;
 
-use crate::codec::{BuildHashKey, BuildInfoWrap, FileInfoWrap, PathKey};
+use crate::codec::{
+    BuildHashKey, BuildInfoV1Wrap, BuildInfoWrap, FileInfoWrap, PathKey, generated_key,
+};
 
 mod codec;
 mod rw;
 
 pub const FILE_INFO_DB_NAME: &str = "files";
 pub const BUILD_INFO_DB_NAME: &str = "builds";
+/// Reverse index from builds to the files they generated. Keys are the build
+/// hash followed by the path of the file.
+pub const GENERATED_DB_NAME: &str = "generated";
 
+/// Stores versions of the data in the environment
+pub const META_DB_NAME: &str = "meta";
+
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+const FORMAT_VERSION_KEY: &str = "format_version";
+
+/// The version of the database layout of this backend.
+///
+/// - 0: Only file and build databases. Environments from before versioning
+///   was introduced are treated as this version.
+/// - 1: Added the generated file index and the meta database.
+const FORMAT_VERSION: u64 = 1;
+
+/// The number of named databases used by each namespace.
+pub const DBS_PER_NAMESPACE: u32 = 4;
+
+/// The number of namespaces, including the default one, that
+/// [`ExecHeedDb::open`] makes room for.
+pub const DEFAULT_MAX_NAMESPACES: u32 = 16;
+
+type MetaDb = heed::Database<Str, U64<BigEndian>>;
+
+/// The names of the databases of a namespace. Databases of a named namespace
+/// are prefixed with its name and a slash, while the default namespace uses
+/// the bare names.
+pub(crate) struct Names {
+    pub(crate) files: String,
+    pub(crate) builds: String,
+    pub(crate) generated: String,
+    pub(crate) meta: String,
+}
+
+impl Names {
+    fn new(namespace: &str) -> Self {
+        let prefix = if namespace.is_empty() {
+            String::new()
+        } else {
+            format!("{namespace}/")
+        };
+        Self {
+            files: format!("{prefix}{FILE_INFO_DB_NAME}"),
+            builds: format!("{prefix}{BUILD_INFO_DB_NAME}"),
+            generated: format!("{prefix}{GENERATED_DB_NAME}"),
+            meta: format!("{prefix}{META_DB_NAME}"),
+        }
+    }
+}
+
+/// A namespace of an LMDB environment used as an [`ExecDb`].
+///
+/// An environment can hold several namespaces, each in its own set of named
+/// databases, so that multiple build graphs can share it without clobbering
+/// each other's file records. [`Self::new`] and [`Self::open`] use the default
+/// namespace, and [`Self::namespace`] gives access to the others.
+///
+/// Several processes can use an environment at once, and each transaction is
+/// atomic. However, two processes building the same outputs still race on the
+/// files and overwrite each other's records. Hold a [`n2o5::lock::BuildLock`]
+/// for the whole build to prevent this.
 pub struct ExecHeedDb {
     inner: heed::Env,
+    names: Names,
 }
 
 impl ExecHeedDb {
-    pub fn new(inner: heed::Env) -> Self {
-        Self { inner }
+    /// Use an existing LMDB environment, creating the databases if needed.
+    /// The environment must allow [`DBS_PER_NAMESPACE`] named databases for
+    /// each namespace in use.
+    ///
+    /// If the stored data is from an older version of this crate, it is
+    /// migrated. If it cannot be migrated, it is cleared.
+    pub fn new(inner: heed::Env) -> heed::Result<Self> {
+        Self::with_namespace(inner, "")
     }
 
+    /// Create or open an LMDB environment at the given directory, with room
+    /// for [`DEFAULT_MAX_NAMESPACES`] namespaces.
     pub fn open(path: impl AsRef<Path>) -> heed::Result<Self> {
+        Self::open_with_max_namespaces(path, DEFAULT_MAX_NAMESPACES)
+    }
+
+    /// Like [`Self::open`], but with room for the given number of namespaces,
+    /// including the default one.
+    pub fn open_with_max_namespaces(
+        path: impl AsRef<Path>,
+        max_namespaces: u32,
+    ) -> heed::Result<Self> {
+        let max_dbs = max_namespaces.saturating_mul(DBS_PER_NAMESPACE);
         // Create or open an LMDB environment with named databases
-        let env = unsafe { EnvOpenOptions::new().max_dbs(4).open(path)? };
+        let env = unsafe { EnvOpenOptions::new().max_dbs(max_dbs).open(path)? };
+        Self::new(env)
+    }
+
+    /// Open an existing LMDB environment at the given directory, like
+    /// [`Self::open`], and use the namespace with the given name as it is,
+    /// e.g. to inspect its records. The empty name refers to the default
+    /// namespace.
+    ///
+    /// Unlike [`Self::open`], this never creates the databases of the
+    /// namespace, and never migrates or clears the stored data. It fails if
+    /// the namespace has no databases, or if its data is from another
+    /// version.
+    pub fn open_existing(
+        path: impl AsRef<Path>,
+        namespace: &str,
+    ) -> Result<Self, OpenExistingError<heed::Error>> {
+        let max_dbs = DEFAULT_MAX_NAMESPACES * DBS_PER_NAMESPACE;
+        let env = unsafe { EnvOpenOptions::new().max_dbs(max_dbs).open(path)? };
+        let names = Names::new(namespace);
 
-        // Ensure DBs exist
+        let rtxn = env.read_txn()?;
+        if env
+            .open_database::<PathKey, FileInfoWrap>(&rtxn, Some(&names.files))?
+            .is_none()
         {
-            let mut wtxn = env.write_txn()?;
-            // Create if not existing; returns Ok(Some(db)) on open, Ok(None) if not found
-            env.create_database::<PathKey, FileInfoWrap>(&mut wtxn, Some(FILE_INFO_DB_NAME))?;
-            env.create_database::<BuildHashKey, BuildInfoWrap>(
-                &mut wtxn,
-                Some(BUILD_INFO_DB_NAME),
-            )?;
-            wtxn.commit()?;
+            return Err(OpenExistingError::Missing);
         }
+        // Environments without versions predate versioning, in which the
+        // records have the first schema version.
+        let meta: Option<MetaDb> = env.open_database(&rtxn, Some(&names.meta))?;
+        let (schema, format) = match meta {
+            Some(meta) => (
+                meta.get(&rtxn, SCHEMA_VERSION_KEY)?.unwrap_or(1),
+                meta.get(&rtxn, FORMAT_VERSION_KEY)?.unwrap_or(0),
+            ),
+            None => (1, 0),
+        };
+        drop(rtxn);
+        if schema != n2o5::db::SCHEMA_VERSION || format != FORMAT_VERSION {
+            return Err(OpenExistingError::VersionMismatch {
+                schema,
+                format,
+                expected_format: FORMAT_VERSION,
+            });
+        }
+        Ok(Self { inner: env, names })
+    }
+
+    /// Use the namespace with the given name in the same environment, creating
+    /// its databases if needed. The empty name refers to the default
+    /// namespace.
+    ///
+    /// Like [`Self::new`], the stored data of the namespace is migrated or
+    /// cleared if it is from an older version.
+    ///
+    /// Each namespace takes [`DBS_PER_NAMESPACE`] named databases of the
+    /// environment. If the environment has no room left, such as when more
+    /// than [`DEFAULT_MAX_NAMESPACES`] namespaces are used with
+    /// [`Self::open`], this fails with [`heed::MdbError::DbsFull`]. Use
+    /// [`Self::open_with_max_namespaces`] to make room for more.
+    pub fn namespace(&self, name: &str) -> heed::Result<Self> {
+        Self::with_namespace(self.inner.clone(), name)
+    }
+
+    fn with_namespace(inner: heed::Env, name: &str) -> heed::Result<Self> {
+        let this = Self {
+            inner,
+            names: Names::new(name),
+        };
+        this.upgrade()?;
+        Ok(this)
+    }
+
+    /// Scan through all records, removing the ones that cannot be decoded, and
+    /// rebuild the generated file index from the remaining file records.
+    ///
+    /// Reading a corrupted record never fails a build, since it is treated as
+    /// missing. This check is only needed to get rid of them, and to repair
+    /// the index entries of corrupted file records.
+    pub fn check_integrity(&self) -> heed::Result<IntegrityReport> {
+        let (env, names) = (&self.inner, &self.names);
+        let mut wtxn = env.write_txn()?;
+
+        let builds = env
+            .create_database::<BuildHashKey, BuildInfoWrap>(&mut wtxn, Some(&names.builds))?
+            .remap_data_type::<Bytes>();
+        let mut corrupted_builds = vec![];
+        for entry in builds.iter(&wtxn)? {
+            let (hash, bytes) = entry?;
+            if postcard::from_bytes::<BuildInfo>(bytes).is_err() {
+                corrupted_builds.push(hash);
+            }
+        }
+        for hash in &corrupted_builds {
+            warn!("Removing corrupted build record for {hash:?}");
+            builds.delete(&mut wtxn, hash)?;
+        }
+
+        let files = env
+            .create_database::<PathKey, FileInfoWrap>(&mut wtxn, Some(&names.files))?
+            .remap_data_type::<Bytes>();
+        let mut corrupted_files = vec![];
+        for entry in files.iter(&wtxn)? {
+            let (path, bytes) = entry?;
+            if postcard::from_bytes::<FileInfo>(bytes).is_err() {
+                corrupted_files.push(path.to_owned());
+            }
+        }
+        for path in &corrupted_files {
+            warn!("Removing corrupted file record for {path:?}");
+            files.delete(&mut wtxn, path)?;
+        }
+
+        env.create_database::<Bytes, Unit>(&mut wtxn, Some(&names.generated))?
+            .clear(&mut wtxn)?;
+        build_generated_index(env, names, &mut wtxn)?;
+        wtxn.commit()?;
 
-        Ok(Self { inner: env })
+        Ok(IntegrityReport {
+            corrupted_builds: corrupted_builds.len(),
+            corrupted_files: corrupted_files.len(),
+        })
+    }
+
+    /// Bring the stored data to the current version, or discard it if that's
+    /// not possible.
+    fn upgrade(&self) -> heed::Result<()> {
+        let (env, names) = (&self.inner, &self.names);
+        let mut wtxn = env.write_txn()?;
+
+        let fresh = env
+            .open_database::<PathKey, FileInfoWrap>(&wtxn, Some(&names.files))?
+            .is_none();
+        let meta: MetaDb = env.create_database(&mut wtxn, Some(&names.meta))?;
+        // Environments without versions predate versioning, in which the
+        // records have the first schema version.
+        let schema = meta.get(&wtxn, SCHEMA_VERSION_KEY)?.unwrap_or(1);
+        let format = meta.get(&wtxn, FORMAT_VERSION_KEY)?.unwrap_or(0);
+
+        create_databases(env, names, &mut wtxn)?;
+        if fresh {
+            // Nothing to upgrade
+        } else if schema > n2o5::db::SCHEMA_VERSION || format > FORMAT_VERSION {
+            warn!(
+                "Database has schema version {schema} and format version {format}, \
+                which is incompatible with schema version {} and format version \
+                {FORMAT_VERSION}. Resetting the database.",
+                n2o5::db::SCHEMA_VERSION,
+            );
+            clear_databases(env, names, &mut wtxn)?;
+        } else {
+            if format < FORMAT_VERSION {
+                warn!("Migrating database from format version {format} to {FORMAT_VERSION}");
+                migrate(env, names, &mut wtxn, format)?;
+            }
+            if schema < n2o5::db::SCHEMA_VERSION {
+                warn!(
+                    "Upgrading database from schema version {schema} to {}",
+                    n2o5::db::SCHEMA_VERSION
+                );
+                upgrade_records(env, names, &mut wtxn, schema)?;
+            }
+        }
+        write_versions(env, names, &mut wtxn)?;
+
+        wtxn.commit()
     }
 }
 
 impl ExecDb for ExecHeedDb {
     fn get_schema_version(&self) -> u64 {
-        // Keep parity with redb backend for now
-        0
+        let rtxn = self
+            .inner
+            .read_txn()
+            .expect("Failed to begin read transaction");
+        let meta: MetaDb = self
+            .inner
+            .open_database(&rtxn, Some(&self.names.meta))
+            .expect("Failed to open meta database")
+            .expect("Meta database not found");
+        meta.get(&rtxn, SCHEMA_VERSION_KEY)
+            .expect("Failed to read from meta database")
+            .unwrap_or(n2o5::db::SCHEMA_VERSION)
     }
 
     fn reset(&self) {
-        // Clear both databases
         let mut wtxn = self
             .inner
             .write_txn()
             .expect("Failed to begin write transaction");
-        if let Ok(Some(db)) = self
-            .inner
-            .open_database::<PathKey, FileInfoWrap>(&wtxn, Some(FILE_INFO_DB_NAME))
-        {
-            db.clear(&mut wtxn).expect("Failed to clear files database");
-        }
-        if let Ok(Some(db)) = self
-            .inner
-            .open_database::<BuildHashKey, BuildInfoWrap>(&wtxn, Some(BUILD_INFO_DB_NAME))
-        {
-            db.clear(&mut wtxn)
-                .expect("Failed to clear builds database");
-        }
+        clear_databases(&self.inner, &self.names, &mut wtxn).expect("Failed to clear databases");
+        write_versions(&self.inner, &self.names, &mut wtxn).expect("Failed to write versions");
         wtxn.commit().expect("Failed to commit reset transaction");
     }
 
@@ -77,6 +315,7 @@ impl ExecDb for ExecHeedDb {
             .expect("Failed to begin read transaction");
         Box::new(rw::DbRead {
             env: &self.inner,
+            names: &self.names,
             txn,
         })
     }
@@ -88,7 +327,85 @@ impl ExecDb for ExecHeedDb {
             .expect("Failed to begin write transaction");
         Box::new(rw::DbWrite {
             env: &self.inner,
+            names: &self.names,
             txn,
         })
     }
 }
+
+/// Create all databases that don't exist yet.
+fn create_databases(env: &heed::Env, names: &Names, wtxn: &mut heed::RwTxn) -> heed::Result<()> {
+    env.create_database::<PathKey, FileInfoWrap>(wtxn, Some(&names.files))?;
+    env.create_database::<BuildHashKey, BuildInfoWrap>(wtxn, Some(&names.builds))?;
+    env.create_database::<Bytes, Unit>(wtxn, Some(&names.generated))?;
+    Ok(())
+}
+
+/// Store the current versions in the meta database.
+fn write_versions(env: &heed::Env, names: &Names, wtxn: &mut heed::RwTxn) -> heed::Result<()> {
+    let meta: MetaDb = env.create_database(wtxn, Some(&names.meta))?;
+    meta.put(wtxn, SCHEMA_VERSION_KEY, &n2o5::db::SCHEMA_VERSION)?;
+    meta.put(wtxn, FORMAT_VERSION_KEY, &FORMAT_VERSION)?;
+    Ok(())
+}
+
+/// Remove all records from the databases of the namespace.
+fn clear_databases(env: &heed::Env, names: &Names, wtxn: &mut heed::RwTxn) -> heed::Result<()> {
+    env.create_database::<PathKey, FileInfoWrap>(wtxn, Some(&names.files))?
+        .clear(wtxn)?;
+    env.create_database::<BuildHashKey, BuildInfoWrap>(wtxn, Some(&names.builds))?
+        .clear(wtxn)?;
+    env.create_database::<Bytes, Unit>(wtxn, Some(&names.generated))?
+        .clear(wtxn)?;
+    Ok(())
+}
+
+/// Migrate the databases from an older format version to the current one.
+fn migrate(env: &heed::Env, names: &Names, wtxn: &mut heed::RwTxn, from: u64) -> heed::Result<()> {
+    if from < 1 {
+        build_generated_index(env, names, wtxn)?;
+    }
+    Ok(())
+}
+
+/// Rewrite the records from an older schema version in the current one.
+fn upgrade_records(
+    env: &heed::Env,
+    names: &Names,
+    wtxn: &mut heed::RwTxn,
+    from: u64,
+) -> heed::Result<()> {
+    if from < 2 {
+        let builds =
+            env.create_database::<BuildHashKey, BuildInfoWrap>(wtxn, Some(&names.builds))?;
+        let old = builds.remap_data_type::<BuildInfoV1Wrap>();
+        // Records that cannot be decoded are left to the integrity check
+        let entries = old
+            .iter(wtxn)?
+            .filter_map(|entry| entry.ok())
+            .collect::<Vec<_>>();
+        for (hash, info) in entries {
+            builds.put(wtxn, &hash, &info)?;
+        }
+    }
+    Ok(())
+}
+
+/// Fill the generated file index from the file database.
+fn build_generated_index(
+    env: &heed::Env,
+    names: &Names,
+    wtxn: &mut heed::RwTxn,
+) -> heed::Result<()> {
+    let files = env.create_database::<PathKey, FileInfoWrap>(wtxn, Some(&names.files))?;
+    let generated = env.create_database::<Bytes, Unit>(wtxn, Some(&names.generated))?;
+    let entries = files
+        .iter(wtxn)?
+        .filter_map(|entry| entry.ok())
+        .map(|(path, info)| generated_key(&info.generated_by, path))
+        .collect::<Vec<_>>();
+    for key in entries {
+        generated.put(wtxn, &key, &())?;
+    }
+    Ok(())
+}
@@ -1,38 +1,112 @@
 //! DB Reader and writer
 
-use std::path::Path;
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
 
-use heed::WithTls;
+use heed::{
+    WithTls,
+    types::{Bytes, Unit},
+};
 use n2o5::db::{BuildHash, BuildInfo, DbReader, DbWriter, FileInfo};
+use tracing::warn;
 
-use crate::codec::{BuildHashKey, BuildInfoWrap, FileInfoWrap, PathKey};
-use crate::{BUILD_INFO_DB_NAME, FILE_INFO_DB_NAME};
+use crate::Names;
+use crate::codec::{BuildHashKey, BuildInfoWrap, FileInfoWrap, PathKey, generated_key};
 
 pub struct DbRead<'a> {
     pub(crate) env: &'a heed::Env,
+    pub(crate) names: &'a Names,
     pub(crate) txn: heed::RoTxn<'a, WithTls>,
 }
 
 impl<'a> DbReader for DbRead<'a> {
-    fn get_build_info(&self, hash: n2o5::db::BuildHash) -> Option<n2o5::db::BuildInfo> {
+    fn get_build_info(&self, hash: BuildHash) -> Option<BuildInfo> {
         let db = self
             .env
-            .open_database::<BuildHashKey, BuildInfoWrap>(&self.txn, Some(BUILD_INFO_DB_NAME))
+            .open_database::<BuildHashKey, BuildInfoWrap>(&self.txn, Some(&self.names.builds))
             .ok()??;
-        db.get(&self.txn, &hash).ok()?
+        match db.get(&self.txn, &hash) {
+            Ok(info) => info,
+            Err(e) => {
+                warn!("Cannot read build record for {hash:?}, treating as missing: {e}");
+                None
+            }
+        }
     }
 
     fn get_file_info(&self, path: &Path) -> Option<FileInfo> {
         let db = self
             .env
-            .open_database::<PathKey, FileInfoWrap>(&self.txn, Some(FILE_INFO_DB_NAME))
+            .open_database::<PathKey, FileInfoWrap>(&self.txn, Some(&self.names.files))
             .ok()??;
-        db.get(&self.txn, path).ok()?
+        match db.get(&self.txn, path) {
+            Ok(info) => info,
+            Err(e) => {
+                warn!("Cannot read file record for {path:?}, treating as missing: {e}");
+                None
+            }
+        }
+    }
+
+    fn builds(&self) -> Box<dyn Iterator<Item = (BuildHash, BuildInfo)> + '_> {
+        let Ok(Some(db)) = self
+            .env
+            .open_database::<BuildHashKey, BuildInfoWrap>(&self.txn, Some(&self.names.builds))
+        else {
+            return Box::new(std::iter::empty());
+        };
+        let iter = db
+            .iter(&self.txn)
+            .expect("Failed to iterate over build database");
+        Box::new(iter.filter_map(|entry| {
+            entry
+                .inspect_err(|e| warn!("Cannot read build record, skipping: {e}"))
+                .ok()
+        }))
+    }
+
+    fn files(&self) -> Box<dyn Iterator<Item = (PathBuf, FileInfo)> + '_> {
+        let Ok(Some(db)) = self
+            .env
+            .open_database::<PathKey, FileInfoWrap>(&self.txn, Some(&self.names.files))
+        else {
+            return Box::new(std::iter::empty());
+        };
+        let iter = db
+            .iter(&self.txn)
+            .expect("Failed to iterate over file database");
+        Box::new(iter.filter_map(|entry| {
+            let (path, info) = entry
+                .inspect_err(|e| warn!("Cannot read file record, skipping: {e}"))
+                .ok()?;
+            Some((path.to_owned(), info))
+        }))
+    }
+
+    fn files_generated_by(&self, hash: BuildHash) -> Box<dyn Iterator<Item = PathBuf> + '_> {
+        let Ok(Some(db)) = self
+            .env
+            .open_database::<Bytes, Unit>(&self.txn, Some(&self.names.generated))
+        else {
+            return Box::new(std::iter::empty());
+        };
+        let iter = db
+            .prefix_iter(&self.txn, &hash.0)
+            .expect("Failed to iterate over generated file index");
+        Box::new(iter.filter_map(|entry| {
+            let (key, ()) = entry.ok()?;
+            // SAFETY: the key was written from a path encoded on this platform
+            let path = unsafe { OsStr::from_encoded_bytes_unchecked(&key[16..]) };
+            Some(PathBuf::from(path))
+        }))
     }
 }
 
 pub struct DbWrite<'a> {
     pub(crate) env: &'a heed::Env<WithTls>,
+    pub(crate) names: &'a Names,
     pub(crate) txn: heed::RwTxn<'a>,
 }
 
@@ -40,7 +114,7 @@ impl<'a> DbWriter for DbWrite<'a> {
     fn set_build_info(&mut self, hash: BuildHash, info: BuildInfo) {
         let db = self
             .env
-            .open_database::<BuildHashKey, BuildInfoWrap>(&self.txn, Some(BUILD_INFO_DB_NAME))
+            .open_database::<BuildHashKey, BuildInfoWrap>(&self.txn, Some(&self.names.builds))
             .expect("Failed to open build database")
             .expect("Build database not found");
         db.put(&mut self.txn, &hash, &info)
@@ -50,7 +124,7 @@ impl<'a> DbWriter for DbWrite<'a> {
     fn invalidate_build(&mut self, hash: BuildHash) {
         let db = self
             .env
-            .open_database::<BuildHashKey, BuildInfoWrap>(&self.txn, Some(BUILD_INFO_DB_NAME))
+            .open_database::<BuildHashKey, BuildInfoWrap>(&self.txn, Some(&self.names.builds))
             .expect("Failed to open build database")
             .expect("Build database not found");
         db.delete(&mut self.txn, &hash)
@@ -60,19 +134,24 @@ impl<'a> DbWriter for DbWrite<'a> {
     fn set_file_info(&mut self, path: &Path, info: FileInfo) {
         let db = self
             .env
-            .open_database::<PathKey, FileInfoWrap>(&self.txn, Some(FILE_INFO_DB_NAME))
+            .open_database::<PathKey, FileInfoWrap>(&self.txn, Some(&self.names.files))
             .expect("Failed to open file database")
             .expect("File database not found");
+        self.unindex_file(path);
         db.put(&mut self.txn, path, &info)
             .expect("Failed to insert into file database");
+        self.generated_db()
+            .put(&mut self.txn, &generated_key(&info.generated_by, path), &())
+            .expect("Failed to insert into generated file index");
     }
 
     fn invalidate_file(&mut self, path: &Path) {
         let db = self
             .env
-            .open_database::<PathKey, FileInfoWrap>(&self.txn, Some(FILE_INFO_DB_NAME))
+            .open_database::<PathKey, FileInfoWrap>(&self.txn, Some(&self.names.files))
             .expect("Failed to open file database")
             .expect("File database not found");
+        self.unindex_file(path);
         db.delete(&mut self.txn, path)
             .expect("Failed to delete from file database");
     }
@@ -81,3 +160,27 @@ impl<'a> DbWriter for DbWrite<'a> {
         self.txn.commit().expect("Failed to commit transaction");
     }
 }
+
+impl DbWrite<'_> {
+    fn generated_db(&self) -> heed::Database<Bytes, Unit> {
+        self.env
+            .open_database::<Bytes, Unit>(&self.txn, Some(&self.names.generated))
+            .expect("Failed to open generated file index")
+            .expect("Generated file index not found")
+    }
+
+    /// Remove the index entry of the current record of `path`, if any.
+    fn unindex_file(&mut self, path: &Path) {
+        let db = self
+            .env
+            .open_database::<PathKey, FileInfoWrap>(&self.txn, Some(&self.names.files))
+            .expect("Failed to open file database")
+            .expect("File database not found");
+        let Ok(Some(old)) = db.get(&self.txn, path) else {
+            return;
+        };
+        self.generated_db()
+            .delete(&mut self.txn, &generated_key(&old.generated_by, path))
+            .expect("Failed to delete from generated file index");
+    }
+}
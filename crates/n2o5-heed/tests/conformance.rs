@@ -0,0 +1,35 @@
+//! Runs the shared [`n2o5::db::conformance`] suite against [`ExecHeedDb`].
+
+use std::path::Path;
+
+use n2o5_heed::ExecHeedDb;
+
+n2o5::db_conformance_tests!(|dir: &Path| ExecHeedDb::open(dir).unwrap());
+
+mod namespaced {
+    use super::*;
+
+    n2o5::db_conformance_tests!(|dir: &Path| {
+        ExecHeedDb::open(dir).unwrap().namespace("debug").unwrap()
+    });
+}
+
+#[test]
+fn namespace_isolation() {
+    n2o5::db::conformance::namespace_isolation(|dir: &Path| {
+        let db = ExecHeedDb::open(dir).unwrap();
+        let release = db.namespace("release").unwrap();
+        (db, release)
+    });
+}
+
+#[test]
+fn namespace_limit() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = ExecHeedDb::open_with_max_namespaces(dir.path(), 2).unwrap();
+    db.namespace("debug").unwrap();
+    assert!(matches!(
+        db.namespace("release"),
+        Err(heed::Error::Mdb(heed::MdbError::DbsFull))
+    ));
+}
@@ -0,0 +1,48 @@
+//! Opening environments written with other schema versions.
+
+use std::path::Path;
+
+use heed::{
+    EnvOpenOptions,
+    byteorder::BigEndian,
+    types::{Bytes, Str, U64},
+};
+use n2o5_heed::{BUILD_INFO_DB_NAME, ExecHeedDb, META_DB_NAME};
+
+#[test]
+fn schema_v1_records_are_upgraded() {
+    n2o5::db::conformance::upgraded_build_is_up_to_date(
+        |dir: &Path| ExecHeedDb::open(dir).unwrap(),
+        |dir, hash, old| {
+            let env = unsafe { EnvOpenOptions::new().max_dbs(4).open(dir).unwrap() };
+            let mut wtxn = env.write_txn().unwrap();
+            let builds = env
+                .create_database::<Bytes, Bytes>(&mut wtxn, Some(BUILD_INFO_DB_NAME))
+                .unwrap();
+            let bytes = postcard::to_stdvec(&old).unwrap();
+            builds.put(&mut wtxn, &hash.0, &bytes).unwrap();
+            let meta = env
+                .create_database::<Str, U64<BigEndian>>(&mut wtxn, Some(META_DB_NAME))
+                .unwrap();
+            meta.put(&mut wtxn, "schema_version", &1).unwrap();
+            wtxn.commit().unwrap();
+        },
+    );
+}
+
+#[test]
+fn open_existing_keeps_other_versions() {
+    n2o5::db::conformance::open_existing(
+        |dir: &Path| ExecHeedDb::open(dir).unwrap(),
+        |dir, namespace| ExecHeedDb::open_existing(dir, namespace),
+        |dir, version| {
+            let env = unsafe { EnvOpenOptions::new().max_dbs(4).open(dir).unwrap() };
+            let mut wtxn = env.write_txn().unwrap();
+            let meta = env
+                .create_database::<Str, U64<BigEndian>>(&mut wtxn, Some(META_DB_NAME))
+                .unwrap();
+            meta.put(&mut wtxn, "schema_version", &version).unwrap();
+            wtxn.commit().unwrap();
+        },
+    );
+}
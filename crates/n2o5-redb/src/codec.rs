@@ -2,7 +2,7 @@
 
 use std::{ffi::OsStr, path::Path};
 
-use n2o5::db::{BuildHash, BuildInfo, FileInfo};
+use n2o5::db::{BuildHash, BuildInfo, BuildInfoV1, FileInfo};
 use redb::{Key, TypeName, Value};
 
 #[derive(Debug)]
@@ -81,11 +81,12 @@ impl Value for BuildHashKey {
     }
 }
 
+/// Stores a [`FileInfo`]. Decodes to `None` if the record is corrupted.
 #[derive(Debug)]
 pub(crate) struct FileInfoValue;
 
 impl Value for FileInfoValue {
-    type SelfType<'a> = FileInfo;
+    type SelfType<'a> = Option<FileInfo>;
     type AsBytes<'a> = Vec<u8>;
 
     fn fixed_width() -> Option<usize> {
@@ -96,14 +97,18 @@ impl Value for FileInfoValue {
     where
         Self: 'a,
     {
-        postcard::from_bytes(data).expect("Failed to deserialize FileInfo")
+        postcard::from_bytes(data).ok()
     }
 
     fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
     where
         Self: 'b,
     {
-        postcard::to_stdvec(value).expect("Failed to serialize FileInfo")
+        match value {
+            Some(value) => postcard::to_stdvec(value).expect("Failed to serialize FileInfo"),
+            // An empty record never decodes successfully
+            None => vec![],
+        }
     }
 
     fn type_name() -> TypeName {
@@ -111,11 +116,12 @@ impl Value for FileInfoValue {
     }
 }
 
+/// Stores a [`BuildInfo`]. Decodes to `None` if the record is corrupted.
 #[derive(Debug)]
 pub(crate) struct BuildInfoValue;
 
 impl Value for BuildInfoValue {
-    type SelfType<'a> = BuildInfo;
+    type SelfType<'a> = Option<BuildInfo>;
     type AsBytes<'a> = Vec<u8>;
 
     fn fixed_width() -> Option<usize> {
@@ -126,17 +132,55 @@ impl Value for BuildInfoValue {
     where
         Self: 'a,
     {
-        postcard::from_bytes(data).expect("Failed to deserialize BuildInfo")
+        postcard::from_bytes(data).ok()
     }
 
     fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
     where
         Self: 'b,
     {
-        postcard::to_stdvec(value).expect("Failed to serialize BuildInfo")
+        match value {
+            Some(value) => postcard::to_stdvec(value).expect("Failed to serialize BuildInfo"),
+            // An empty record never decodes successfully
+            None => vec![],
+        }
     }
 
     fn type_name() -> TypeName {
         TypeName::new(std::any::type_name::<BuildInfo>())
     }
 }
+
+/// Reads a build record of schema version 1, which lacks the history, from
+/// the same table as [`BuildInfoValue`]. Only used to upgrade old records.
+#[derive(Debug)]
+pub(crate) struct BuildInfoV1Value;
+
+impl Value for BuildInfoV1Value {
+    type SelfType<'a> = Option<BuildInfo>;
+    type AsBytes<'a> = Vec<u8>;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        postcard::from_bytes::<BuildInfoV1>(data)
+            .ok()
+            .map(Into::into)
+    }
+
+    fn as_bytes<'a, 'b: 'a>(_value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'b,
+    {
+        unreachable!("Build records are only written in the current version")
+    }
+
+    fn type_name() -> TypeName {
+        BuildInfoValue::type_name()
+    }
+}
@@ -1,49 +1,290 @@
 //! Redb-backed `ExecDb` implementation.
 
-use std::path::Path;
+use std::{path::Path, sync::Arc};
 
-use n2o5::db::{DbReader, DbWriter, ExecDb};
-use redb::{ReadableDatabase, TableDefinition};
+use n2o5::db::{DbReader, DbWriter, ExecDb, IntegrityReport, OpenExistingError};
+use redb::{
+    MultimapTableDefinition, ReadableDatabase, ReadableTable, TableDefinition, TableHandle,
+    WriteTransaction,
+};
+use tracing::warn;
 
 mod codec;
 mod rw;
 
-use crate::codec::{BuildHashKey, BuildInfoValue, FileInfoValue, PathKey};
+use crate::codec::{BuildHashKey, BuildInfoV1Value, BuildInfoValue, FileInfoValue, PathKey};
 use crate::rw::{Reader, Writer};
 
-pub(crate) static FILE_TABLE: TableDefinition<PathKey, FileInfoValue> =
-    TableDefinition::new("files");
-pub(crate) static BUILD_TABLE: TableDefinition<BuildHashKey, BuildInfoValue> =
-    TableDefinition::new("builds");
+/// The names of the tables of a namespace. Tables of a named namespace are
+/// prefixed with its name and a slash, while the default namespace uses the
+/// bare names.
+pub(crate) struct Tables {
+    files: String,
+    builds: String,
+    generated: String,
+    meta: String,
+}
+
+impl Tables {
+    fn new(namespace: &str) -> Self {
+        let prefix = if namespace.is_empty() {
+            String::new()
+        } else {
+            format!("{namespace}/")
+        };
+        Self {
+            files: format!("{prefix}files"),
+            builds: format!("{prefix}builds"),
+            generated: format!("{prefix}generated"),
+            meta: format!("{prefix}meta"),
+        }
+    }
+
+    pub(crate) fn files(&self) -> TableDefinition<'_, PathKey, FileInfoValue> {
+        TableDefinition::new(&self.files)
+    }
+
+    pub(crate) fn builds(&self) -> TableDefinition<'_, BuildHashKey, BuildInfoValue> {
+        TableDefinition::new(&self.builds)
+    }
+
+    /// Reverse index from builds to the files they generated
+    pub(crate) fn generated(&self) -> MultimapTableDefinition<'_, BuildHashKey, PathKey> {
+        MultimapTableDefinition::new(&self.generated)
+    }
+
+    /// Stores versions of the data in the namespace
+    pub(crate) fn meta(&self) -> TableDefinition<'_, &'static str, u64> {
+        TableDefinition::new(&self.meta)
+    }
+}
 
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+const FORMAT_VERSION_KEY: &str = "format_version";
+
+/// The version of the table layout of this backend.
+///
+/// - 0: Only file and build tables. Databases from before versioning was
+///   introduced are treated as this version.
+/// - 1: Added the generated file index and the meta table.
+const FORMAT_VERSION: u64 = 1;
+
+/// A namespace of a redb database used as an [`ExecDb`].
+///
+/// A database file can hold several namespaces, each with its own records, so
+/// that multiple build graphs can share it without clobbering each other's
+/// file records. [`Self::new`] and [`Self::open`] use the default namespace,
+/// and [`Self::namespace`] gives access to the others.
+///
+/// Only one process can open a database file at a time, and others fail with
+/// [`redb::DatabaseError::DatabaseAlreadyOpen`]. To wait for the other process
+/// instead, hold a [`n2o5::lock::BuildLock`] before opening the database.
 pub struct ExecRedb {
-    inner: redb::Database,
+    inner: Arc<redb::Database>,
+    tables: Tables,
 }
 
 impl ExecRedb {
+    /// Use an existing redb database, creating the tables if needed.
+    ///
+    /// If the stored data is from an older version of this crate, it is
+    /// migrated. If it cannot be migrated, it is cleared.
     pub fn new(inner: redb::Database) -> Self {
-        Self { inner }
+        Self::with_namespace(Arc::new(inner), "")
     }
 
     pub fn open(path: impl AsRef<Path>) -> Result<Self, redb::DatabaseError> {
         let db = redb::Database::create(path)?;
-        let txn = db
+        Ok(Self::new(db))
+    }
+
+    /// Open an existing database and use the namespace with the given name as
+    /// it is, e.g. to inspect its records. The empty name refers to the
+    /// default namespace.
+    ///
+    /// Unlike [`Self::open`], this never creates the database or the tables
+    /// of the namespace, and never migrates or clears the stored data. It
+    /// fails if the namespace has no tables, or if its data is from another
+    /// version.
+    pub fn open_existing(
+        path: impl AsRef<Path>,
+        namespace: &str,
+    ) -> Result<Self, OpenExistingError<redb::DatabaseError>> {
+        let this = Self {
+            inner: Arc::new(redb::Database::open(path)?),
+            tables: Tables::new(namespace),
+        };
+        let (schema, format) = {
+            let tables = &this.tables;
+            let txn = this
+                .inner
+                .begin_read()
+                .expect("Failed to begin read transaction");
+            let missing = !txn
+                .list_tables()
+                .expect("Failed to list tables")
+                .any(|table| {
+                    let name = table.name();
+                    name == tables.files || name == tables.builds || name == tables.meta
+                });
+            if missing {
+                return Err(OpenExistingError::Missing);
+            }
+            match txn.open_table(tables.meta()) {
+                Ok(meta) => read_meta(&meta),
+                // Databases from before versioning have no meta table
+                Err(redb::TableError::TableDoesNotExist(_)) => (1, 0),
+                Err(e) => panic!("Failed to open meta table: {e}"),
+            }
+        };
+        if schema != n2o5::db::SCHEMA_VERSION || format != FORMAT_VERSION {
+            return Err(OpenExistingError::VersionMismatch {
+                schema,
+                format,
+                expected_format: FORMAT_VERSION,
+            });
+        }
+        Ok(this)
+    }
+
+    /// Use the namespace with the given name in the same database, creating
+    /// its tables if needed. The empty name refers to the default namespace.
+    ///
+    /// Like [`Self::new`], the stored data of the namespace is migrated or
+    /// cleared if it is from an older version.
+    pub fn namespace(&self, name: &str) -> Self {
+        Self::with_namespace(self.inner.clone(), name)
+    }
+
+    fn with_namespace(inner: Arc<redb::Database>, name: &str) -> Self {
+        let this = Self {
+            inner,
+            tables: Tables::new(name),
+        };
+        this.upgrade();
+        this
+    }
+
+    /// Scan through all records, removing the ones that cannot be decoded, and
+    /// rebuild the generated file index from the remaining file records.
+    ///
+    /// Reading a corrupted record never fails a build, since it is treated as
+    /// missing. This check is only needed to get rid of them, and to repair
+    /// the index entries of corrupted file records.
+    pub fn check_integrity(&self) -> IntegrityReport {
+        let txn = self
+            .inner
+            .begin_write()
+            .expect("Failed to begin integrity check transaction");
+
+        let mut builds = txn
+            .open_table(self.tables.builds())
+            .expect("Failed to open build table");
+        let corrupted_builds = builds
+            .iter()
+            .expect("Failed to iterate over build table")
+            .map(|entry| entry.expect("Failed to read from build table"))
+            .filter(|(_, info)| info.value().is_none())
+            .map(|(hash, _)| *hash.value())
+            .collect::<Vec<_>>();
+        for hash in &corrupted_builds {
+            warn!("Removing corrupted build record for {hash:?}");
+            builds
+                .remove(hash)
+                .expect("Failed to remove from build table");
+        }
+        drop(builds);
+
+        let mut files = txn
+            .open_table(self.tables.files())
+            .expect("Failed to open file table");
+        let corrupted_files = files
+            .iter()
+            .expect("Failed to iterate over file table")
+            .map(|entry| entry.expect("Failed to read from file table"))
+            .filter(|(_, info)| info.value().is_none())
+            .map(|(path, _)| path.value().to_owned())
+            .collect::<Vec<_>>();
+        for path in &corrupted_files {
+            warn!("Removing corrupted file record for {path:?}");
+            files
+                .remove(path.as_path())
+                .expect("Failed to remove from file table");
+        }
+        drop(files);
+
+        txn.delete_multimap_table(self.tables.generated())
+            .expect("Failed to delete generated file index");
+        build_generated_index(&txn, &self.tables);
+        txn.commit()
+            .expect("Failed to commit integrity check transaction");
+
+        IntegrityReport {
+            corrupted_builds: corrupted_builds.len(),
+            corrupted_files: corrupted_files.len(),
+        }
+    }
+
+    /// Bring the stored data to the current version, or discard it if that's
+    /// not possible.
+    fn upgrade(&self) {
+        let txn = self
+            .inner
             .begin_write()
             .expect("Failed to begin initial transaction");
-        txn.open_table(FILE_TABLE)
-            .expect("Failed to create file table");
-        txn.open_table(BUILD_TABLE)
-            .expect("Failed to create build table");
-        txn.commit().expect("Failed to commit initial transaction");
 
-        Ok(Self { inner: db })
+        let tables = &self.tables;
+        let fresh = !txn
+            .list_tables()
+            .expect("Failed to list tables")
+            .any(|table| {
+                let name = table.name();
+                name == tables.files || name == tables.builds || name == tables.meta
+            });
+        let (schema, format) = read_versions(&txn, tables);
+        if fresh {
+            // Nothing to upgrade
+        } else if schema > n2o5::db::SCHEMA_VERSION || format > FORMAT_VERSION {
+            warn!(
+                "Database has schema version {schema} and format version {format}, \
+                which is incompatible with schema version {} and format version \
+                {FORMAT_VERSION}. Resetting the database.",
+                n2o5::db::SCHEMA_VERSION,
+            );
+            clear_tables(&txn, tables);
+        } else {
+            if format < FORMAT_VERSION {
+                warn!("Migrating database from format version {format} to {FORMAT_VERSION}");
+                migrate(&txn, tables, format);
+            }
+            if schema < n2o5::db::SCHEMA_VERSION {
+                warn!(
+                    "Upgrading database from schema version {schema} to {}",
+                    n2o5::db::SCHEMA_VERSION
+                );
+                upgrade_records(&txn, tables, schema);
+            }
+        }
+
+        create_tables(&txn, tables);
+        txn.commit().expect("Failed to commit initial transaction");
     }
 }
 
 impl ExecDb for ExecRedb {
     fn get_schema_version(&self) -> u64 {
-        // Keep parity with other backends
-        0
+        let txn = self
+            .inner
+            .begin_read()
+            .expect("Failed to begin read transaction");
+        let table = txn
+            .open_table(self.tables.meta())
+            .expect("Failed to open meta table");
+        table
+            .get(SCHEMA_VERSION_KEY)
+            .expect("Failed to read from meta table")
+            .map(|v| v.value())
+            .unwrap_or(n2o5::db::SCHEMA_VERSION)
     }
 
     fn reset(&self) {
@@ -51,18 +292,8 @@ impl ExecDb for ExecRedb {
             .inner
             .begin_write()
             .expect("Failed to begin reset transaction");
-
-        // Recreate tables by deleting and reopening them.
-        txn.delete_table(FILE_TABLE)
-            .expect("Failed to delete file table during reset");
-        txn.delete_table(BUILD_TABLE)
-            .expect("Failed to delete build table during reset");
-
-        txn.open_table(FILE_TABLE)
-            .expect("Failed to recreate file table during reset");
-        txn.open_table(BUILD_TABLE)
-            .expect("Failed to recreate build table during reset");
-
+        clear_tables(&txn, &self.tables);
+        create_tables(&txn, &self.tables);
         txn.commit().expect("Failed to commit reset transaction");
     }
 
@@ -71,7 +302,7 @@ impl ExecDb for ExecRedb {
             .inner
             .begin_read()
             .expect("Failed to begin read transaction");
-        Box::new(Reader::new(txn))
+        Box::new(Reader::new(txn, &self.tables))
     }
 
     fn begin_write<'w>(&'w self) -> Box<dyn DbWriter + 'w> {
@@ -79,6 +310,112 @@ impl ExecDb for ExecRedb {
             .inner
             .begin_write()
             .expect("Failed to begin write transaction");
-        Box::new(Writer::new(txn))
+        Box::new(Writer::new(txn, &self.tables))
+    }
+}
+
+/// Read the stored schema and format versions.
+fn read_versions(txn: &WriteTransaction, tables: &Tables) -> (u64, u64) {
+    let table = txn
+        .open_table(tables.meta())
+        .expect("Failed to open meta table");
+    read_meta(&table)
+}
+
+/// Read the schema and format versions from the meta table.
+fn read_meta(table: &impl ReadableTable<&'static str, u64>) -> (u64, u64) {
+    let get = |key| {
+        table
+            .get(key)
+            .expect("Failed to read from meta table")
+            .map(|v| v.value())
+    };
+    // Databases without versions predate versioning, in which the records have
+    // the first schema version.
+    (
+        get(SCHEMA_VERSION_KEY).unwrap_or(1),
+        get(FORMAT_VERSION_KEY).unwrap_or(0),
+    )
+}
+
+/// Create all tables that don't exist yet, and store the current versions.
+fn create_tables(txn: &WriteTransaction, tables: &Tables) {
+    txn.open_table(tables.files())
+        .expect("Failed to create file table");
+    txn.open_table(tables.builds())
+        .expect("Failed to create build table");
+    txn.open_multimap_table(tables.generated())
+        .expect("Failed to create generated file index");
+
+    let mut meta = txn
+        .open_table(tables.meta())
+        .expect("Failed to create meta table");
+    meta.insert(SCHEMA_VERSION_KEY, n2o5::db::SCHEMA_VERSION)
+        .expect("Failed to write schema version");
+    meta.insert(FORMAT_VERSION_KEY, FORMAT_VERSION)
+        .expect("Failed to write format version");
+}
+
+/// Delete all tables of the namespace.
+fn clear_tables(txn: &WriteTransaction, tables: &Tables) {
+    txn.delete_table(tables.files())
+        .expect("Failed to delete file table");
+    txn.delete_table(tables.builds())
+        .expect("Failed to delete build table");
+    txn.delete_multimap_table(tables.generated())
+        .expect("Failed to delete generated file index");
+    txn.delete_table(tables.meta())
+        .expect("Failed to delete meta table");
+}
+
+/// Migrate the tables from an older format version to the current one.
+fn migrate(txn: &WriteTransaction, tables: &Tables, from: u64) {
+    if from < 1 {
+        build_generated_index(txn, tables);
+    }
+}
+
+/// Rewrite the records from an older schema version in the current one.
+fn upgrade_records(txn: &WriteTransaction, tables: &Tables, from: u64) {
+    if from < 2 {
+        let old = TableDefinition::<BuildHashKey, BuildInfoV1Value>::new(&tables.builds);
+        let builds = txn
+            .open_table(old)
+            .expect("Failed to open build table")
+            .iter()
+            .expect("Failed to iterate over build table")
+            .map(|entry| entry.expect("Failed to read from build table"))
+            .map(|(hash, info)| (*hash.value(), info.value()))
+            .collect::<Vec<_>>();
+        let mut table = txn
+            .open_table(tables.builds())
+            .expect("Failed to open build table");
+        for (hash, info) in builds {
+            // Records that cannot be decoded are left to the integrity check
+            if let Some(info) = info {
+                table
+                    .insert(&hash, Some(info))
+                    .expect("Failed to insert into build table");
+            }
+        }
+    }
+}
+
+/// Fill the generated file index from the file table.
+fn build_generated_index(txn: &WriteTransaction, tables: &Tables) {
+    let files = txn
+        .open_table(tables.files())
+        .expect("Failed to open file table");
+    let mut index = txn
+        .open_multimap_table(tables.generated())
+        .expect("Failed to create generated file index");
+    for entry in files.iter().expect("Failed to iterate over file table") {
+        let (path, info) = entry.expect("Failed to read from file table");
+        let Some(info) = info.value() else {
+            continue;
+        };
+        index
+            .insert(&info.generated_by, path.value())
+            .expect("Failed to insert into generated file index");
     }
 }
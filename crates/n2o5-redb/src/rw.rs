@@ -1,49 +1,117 @@
 //! Read/write transaction adapters for redb.
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use n2o5::db::{BuildHash, BuildInfo, DbReader, DbWriter, FileInfo};
 use redb::{ReadTransaction, WriteTransaction};
+use tracing::warn;
 
-use crate::{BUILD_TABLE, FILE_TABLE};
+use crate::Tables;
 
-pub(crate) struct Reader {
+pub(crate) struct Reader<'r> {
     txn: ReadTransaction,
+    tables: &'r Tables,
 }
 
-impl Reader {
-    pub(crate) fn new(txn: ReadTransaction) -> Self {
-        Self { txn }
+impl<'r> Reader<'r> {
+    pub(crate) fn new(txn: ReadTransaction, tables: &'r Tables) -> Self {
+        Self { txn, tables }
     }
 }
 
-impl DbReader for Reader {
+impl DbReader for Reader<'_> {
     fn get_build_info(&self, hash: BuildHash) -> Option<BuildInfo> {
         let table = self
             .txn
-            .open_table(BUILD_TABLE)
+            .open_table(self.tables.builds())
             .expect("Failed to open build table");
         let guard = table.get(&hash).expect("Failed to read from build table")?;
-        Some(guard.value())
+        let info = guard.value();
+        if info.is_none() {
+            warn!("Corrupted build record for {hash:?}, treating as missing");
+        }
+        info
     }
 
     fn get_file_info(&self, path: &Path) -> Option<FileInfo> {
         let table = self
             .txn
-            .open_table(FILE_TABLE)
+            .open_table(self.tables.files())
             .expect("Failed to open file table");
         let guard = table.get(path).expect("Failed to read from file table")?;
-        Some(guard.value())
+        let info = guard.value();
+        if info.is_none() {
+            warn!("Corrupted file record for {path:?}, treating as missing");
+        }
+        info
+    }
+
+    fn builds(&self) -> Box<dyn Iterator<Item = (BuildHash, BuildInfo)> + '_> {
+        let table = self
+            .txn
+            .open_table(self.tables.builds())
+            .expect("Failed to open build table");
+        let range = table
+            .range::<&BuildHash>(..)
+            .expect("Failed to iterate over build table");
+        Box::new(range.filter_map(|entry| {
+            let (key, value) = entry.expect("Failed to read from build table");
+            let hash = *key.value();
+            let Some(info) = value.value() else {
+                warn!("Corrupted build record for {hash:?}, skipping");
+                return None;
+            };
+            Some((hash, info))
+        }))
+    }
+
+    fn files(&self) -> Box<dyn Iterator<Item = (PathBuf, FileInfo)> + '_> {
+        let table = self
+            .txn
+            .open_table(self.tables.files())
+            .expect("Failed to open file table");
+        let range = table
+            .range::<&Path>(..)
+            .expect("Failed to iterate over file table");
+        Box::new(range.filter_map(|entry| {
+            let (key, value) = entry.expect("Failed to read from file table");
+            let path = key.value();
+            let Some(info) = value.value() else {
+                warn!("Corrupted file record for {path:?}, skipping");
+                return None;
+            };
+            Some((path.to_owned(), info))
+        }))
+    }
+
+    fn files_generated_by(&self, hash: BuildHash) -> Box<dyn Iterator<Item = PathBuf> + '_> {
+        let index = self
+            .txn
+            .open_multimap_table(self.tables.generated())
+            .expect("Failed to open generated file index");
+        let values = index
+            .get(&hash)
+            .expect("Failed to read from generated file index");
+        Box::new(values.map(|entry| {
+            entry
+                .expect("Failed to read from generated file index")
+                .value()
+                .to_owned()
+        }))
     }
 }
 
-pub(crate) struct Writer {
+pub(crate) struct Writer<'w> {
     txn: Option<WriteTransaction>,
+    tables: &'w Tables,
 }
 
-impl Writer {
-    pub(crate) fn new(txn: WriteTransaction) -> Self {
-        Self { txn: Some(txn) }
+impl<'w> Writer<'w> {
+    pub(crate) fn new(txn: WriteTransaction, tables: &'w Tables) -> Self {
+        Self {
+            txn: Some(txn),
+            tables,
+        }
     }
 
     fn txn(&mut self) -> &mut WriteTransaction {
@@ -53,21 +121,23 @@ impl Writer {
     }
 }
 
-impl DbWriter for Writer {
+impl DbWriter for Writer<'_> {
     fn set_build_info(&mut self, hash: BuildHash, info: BuildInfo) {
+        let tables = self.tables;
         let txn = self.txn();
         let mut table = txn
-            .open_table(BUILD_TABLE)
+            .open_table(tables.builds())
             .expect("Failed to open build table");
         table
-            .insert(&hash, info)
+            .insert(&hash, Some(info))
             .expect("Failed to insert into build table");
     }
 
     fn invalidate_build(&mut self, hash: BuildHash) {
+        let tables = self.tables;
         let txn = self.txn();
         let mut table = txn
-            .open_table(BUILD_TABLE)
+            .open_table(tables.builds())
             .expect("Failed to open build table");
         table
             .remove(&hash)
@@ -75,23 +145,45 @@ impl DbWriter for Writer {
     }
 
     fn set_file_info(&mut self, path: &Path, info: FileInfo) {
+        let tables = self.tables;
         let txn = self.txn();
         let mut table = txn
-            .open_table(FILE_TABLE)
+            .open_table(tables.files())
             .expect("Failed to open file table");
-        table
-            .insert(path, info)
+        let mut index = txn
+            .open_multimap_table(tables.generated())
+            .expect("Failed to open generated file index");
+        let generated_by = info.generated_by;
+        let old = table
+            .insert(path, Some(info))
             .expect("Failed to insert into file table");
+        if let Some(old) = old.and_then(|old| old.value()) {
+            index
+                .remove(&old.generated_by, path)
+                .expect("Failed to remove from generated file index");
+        }
+        index
+            .insert(&generated_by, path)
+            .expect("Failed to insert into generated file index");
     }
 
     fn invalidate_file(&mut self, path: &Path) {
+        let tables = self.tables;
         let txn = self.txn();
         let mut table = txn
-            .open_table(FILE_TABLE)
+            .open_table(tables.files())
             .expect("Failed to open file table");
-        table
+        let mut index = txn
+            .open_multimap_table(tables.generated())
+            .expect("Failed to open generated file index");
+        let old = table
             .remove(path)
             .expect("Failed to remove from file table");
+        if let Some(old) = old.and_then(|old| old.value()) {
+            index
+                .remove(&old.generated_by, path)
+                .expect("Failed to remove from generated file index");
+        }
     }
 
     fn commit(self: Box<Self>) {
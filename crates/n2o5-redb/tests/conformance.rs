@@ -0,0 +1,26 @@
+//! Runs the shared [`n2o5::db::conformance`] suite against [`ExecRedb`].
+
+use std::path::Path;
+
+use n2o5_redb::ExecRedb;
+
+n2o5::db_conformance_tests!(|dir: &Path| ExecRedb::open(dir.join("n2o5.redb")).unwrap());
+
+mod namespaced {
+    use super::*;
+
+    n2o5::db_conformance_tests!(|dir: &Path| {
+        ExecRedb::open(dir.join("n2o5.redb"))
+            .unwrap()
+            .namespace("debug")
+    });
+}
+
+#[test]
+fn namespace_isolation() {
+    n2o5::db::conformance::namespace_isolation(|dir: &Path| {
+        let db = ExecRedb::open(dir.join("n2o5.redb")).unwrap();
+        let release = db.namespace("release");
+        (db, release)
+    });
+}
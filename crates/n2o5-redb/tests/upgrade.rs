@@ -0,0 +1,116 @@
+//! Opening databases written with other schema versions.
+
+use std::path::Path;
+
+use n2o5::db::{BuildHash, BuildInfo};
+use n2o5_redb::ExecRedb;
+use redb::{Key, TableDefinition, TypeName, Value};
+
+/// Raw bytes, stored under the type name of the given type.
+#[derive(Debug)]
+struct Raw<T: ?Sized>(std::marker::PhantomData<T>);
+
+impl<T: ?Sized + std::fmt::Debug + 'static> Value for Raw<T> {
+    type SelfType<'a> = &'a [u8];
+    type AsBytes<'a> = &'a [u8];
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        data
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'b,
+    {
+        value
+    }
+
+    fn type_name() -> TypeName {
+        TypeName::new(std::any::type_name::<T>())
+    }
+}
+
+/// A build hash key, stored like the one of the backend.
+#[derive(Debug)]
+struct HashKey;
+
+impl Value for HashKey {
+    type SelfType<'a> = [u8; 16];
+    type AsBytes<'a> = [u8; 16];
+
+    fn fixed_width() -> Option<usize> {
+        Some(16)
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        data.try_into().unwrap()
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'b,
+    {
+        *value
+    }
+
+    fn type_name() -> TypeName {
+        TypeName::new(std::any::type_name::<BuildHash>())
+    }
+}
+
+impl Key for HashKey {
+    fn compare(data1: &[u8], data2: &[u8]) -> std::cmp::Ordering {
+        data1.cmp(data2)
+    }
+}
+
+const BUILDS: TableDefinition<HashKey, Raw<BuildInfo>> = TableDefinition::new("builds");
+const META: TableDefinition<&str, u64> = TableDefinition::new("meta");
+
+#[test]
+fn schema_v1_records_are_upgraded() {
+    n2o5::db::conformance::upgraded_build_is_up_to_date(
+        |dir: &Path| ExecRedb::open(dir.join("n2o5.redb")).unwrap(),
+        |dir, hash, old| {
+            let db = redb::Database::open(dir.join("n2o5.redb")).unwrap();
+            let txn = db.begin_write().unwrap();
+            let bytes = postcard::to_stdvec(&old).unwrap();
+            txn.open_table(BUILDS)
+                .unwrap()
+                .insert(hash.0, bytes.as_slice())
+                .unwrap();
+            txn.open_table(META)
+                .unwrap()
+                .insert("schema_version", 1)
+                .unwrap();
+            txn.commit().unwrap();
+        },
+    );
+}
+
+#[test]
+fn open_existing_keeps_other_versions() {
+    n2o5::db::conformance::open_existing(
+        |dir: &Path| ExecRedb::open(dir.join("n2o5.redb")).unwrap(),
+        |dir, namespace| ExecRedb::open_existing(dir.join("n2o5.redb"), namespace),
+        |dir, version| {
+            let db = redb::Database::open(dir.join("n2o5.redb")).unwrap();
+            let txn = db.begin_write().unwrap();
+            txn.open_table(META)
+                .unwrap()
+                .insert("schema_version", version)
+                .unwrap();
+            txn.commit().unwrap();
+        },
+    );
+}
@@ -0,0 +1,114 @@
+//! Conversion between records and SQL values
+
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use n2o5::db::{BuildHash, BuildInfo, ExecRecord, FileInfo, InputHash};
+use rusqlite::{
+    Row, ToSql,
+    types::{FromSqlError, ToSqlOutput, ValueRef},
+};
+
+/// Paths are stored as text if they are valid UTF-8, so that they are
+/// readable in SQL tools, and as blobs of their encoded bytes otherwise.
+///
+/// The encoding of non-UTF-8 paths is platform-specific, so a database is only
+/// readable on the platform it was written on, as with the other backends.
+pub(crate) struct SqlPath<'a>(pub &'a Path);
+
+impl ToSql for SqlPath<'_> {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self.0.to_str() {
+            Some(s) => ToSqlOutput::Borrowed(ValueRef::Text(s.as_bytes())),
+            None => ToSqlOutput::Borrowed(ValueRef::Blob(self.0.as_os_str().as_encoded_bytes())),
+        })
+    }
+}
+
+pub(crate) fn path_from_sql(value: ValueRef<'_>) -> Result<PathBuf, FromSqlError> {
+    match value {
+        // SAFETY: the bytes were written by `SqlPath` from a path on this
+        // platform, and UTF-8 text is valid on every platform.
+        ValueRef::Text(bytes) | ValueRef::Blob(bytes) => {
+            Ok(unsafe { OsStr::from_encoded_bytes_unchecked(bytes) }.into())
+        }
+        _ => Err(FromSqlError::InvalidType),
+    }
+}
+
+/// Times are stored as nanoseconds since the Unix epoch.
+pub(crate) fn time_to_sql(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => i64::try_from(after.as_nanos()).unwrap_or(i64::MAX),
+        Err(before) => i64::try_from(before.duration().as_nanos()).map_or(i64::MIN, |n| -n),
+    }
+}
+
+pub(crate) fn time_from_sql(nanos: i64) -> SystemTime {
+    let duration = Duration::from_nanos(nanos.unsigned_abs());
+    if nanos >= 0 {
+        UNIX_EPOCH + duration
+    } else {
+        UNIX_EPOCH - duration
+    }
+}
+
+fn hash_from_sql(value: ValueRef<'_>) -> Result<[u8; 16], FromSqlError> {
+    let bytes = value.as_blob()?;
+    bytes.try_into().map_err(|_| FromSqlError::InvalidBlobSize {
+        expected_size: 16,
+        blob_size: bytes.len(),
+    })
+}
+
+pub(crate) fn build_hash_from_sql(value: ValueRef<'_>) -> Result<BuildHash, FromSqlError> {
+    hash_from_sql(value).map(BuildHash)
+}
+
+/// Durations are stored as nanoseconds.
+pub(crate) fn duration_to_sql(duration: Duration) -> i64 {
+    i64::try_from(duration.as_nanos()).unwrap_or(i64::MAX)
+}
+
+/// Decode a row of `SELECT last_start, last_end, input_set_digest FROM builds`.
+///
+/// The additional inputs and the history are stored in separate tables and
+/// left empty.
+pub(crate) fn build_from_row(row: &Row<'_>) -> Result<BuildInfo, FromSqlError> {
+    Ok(BuildInfo {
+        last_start: time_from_sql(row.get_ref_unwrap(0).as_i64()?),
+        last_end: row.get_ref_unwrap(1).as_i64_or_null()?.map(time_from_sql),
+        input_set_digest: InputHash(hash_from_sql(row.get_ref_unwrap(2))?),
+        additional_inputs: vec![],
+        history: vec![],
+    })
+}
+
+/// Decode a row of
+/// `SELECT start, duration, succeeded, exit_code, peak_memory FROM build_history`.
+pub(crate) fn exec_record_from_row(row: &Row<'_>) -> Result<ExecRecord, FromSqlError> {
+    Ok(ExecRecord {
+        start: time_from_sql(row.get_ref_unwrap(0).as_i64()?),
+        duration: Duration::from_nanos(row.get_ref_unwrap(1).as_i64()?.max(0) as u64),
+        succeeded: row.get_ref_unwrap(2).as_i64()? != 0,
+        exit_code: row
+            .get_ref_unwrap(3)
+            .as_i64_or_null()?
+            .map(|code| code as i32),
+        peak_memory: row
+            .get_ref_unwrap(4)
+            .as_i64_or_null()?
+            .map(|bytes| bytes as u64),
+    })
+}
+
+/// Decode a row of `SELECT last_seen, generated_by FROM files`.
+pub(crate) fn file_from_row(row: &Row<'_>) -> Result<FileInfo, FromSqlError> {
+    Ok(FileInfo {
+        last_seen: time_from_sql(row.get_ref_unwrap(0).as_i64()?),
+        generated_by: build_hash_from_sql(row.get_ref_unwrap(1))?,
+    })
+}
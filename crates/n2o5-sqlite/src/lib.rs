@@ -0,0 +1,391 @@
+//! SQLite-backed `ExecDb` implementation.
+//!
+//! Records are stored in plain tables, so the database can be inspected with
+//! ordinary SQL tools:
+//!
+//! - `builds(hash, last_start, last_end, input_set_digest)`
+//! - `build_inputs(build, position, path)` holds the additional inputs of
+//!   each build.
+//! - `build_history(build, position, start, duration, succeeded, exit_code,
+//!   peak_memory)` holds the recent executions of each build.
+//! - `files(path, last_seen, generated_by)`
+//! - `meta(key, value)` holds the versions of the stored data.
+//!
+//! Hashes are 16-byte blobs, times are nanoseconds since the Unix epoch, and
+//! durations are nanoseconds.
+//! Paths are text if they are valid UTF-8, or blobs of their raw bytes
+//! otherwise.
+//!
+//! Tables of a named namespace are prefixed with its name and a slash, e.g.
+//! `"debug/builds"`.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use n2o5::db::{DbReader, DbWriter, ExecDb, OpenExistingError};
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use tracing::warn;
+
+mod codec;
+mod rw;
+
+use crate::rw::{Reader, Writer};
+
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+const FORMAT_VERSION_KEY: &str = "format_version";
+
+/// The version of the table layout of this backend.
+///
+/// - 1: Initial layout.
+/// - 2: Added the build history table.
+const FORMAT_VERSION: u64 = 2;
+
+/// How long to wait for other processes holding the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The quoted names of the tables of a namespace, ready to be used in SQL.
+pub(crate) struct Tables {
+    prefix: String,
+    pub(crate) builds: String,
+    pub(crate) build_inputs: String,
+    pub(crate) build_history: String,
+    pub(crate) files: String,
+    pub(crate) meta: String,
+}
+
+impl Tables {
+    fn new(namespace: &str) -> Self {
+        let prefix = if namespace.is_empty() {
+            String::new()
+        } else {
+            format!("{namespace}/")
+        };
+        Self {
+            builds: quote(&format!("{prefix}builds")),
+            build_inputs: quote(&format!("{prefix}build_inputs")),
+            build_history: quote(&format!("{prefix}build_history")),
+            files: quote(&format!("{prefix}files")),
+            meta: quote(&format!("{prefix}meta")),
+            prefix,
+        }
+    }
+
+    fn create(&self) -> String {
+        let Self {
+            prefix,
+            builds,
+            build_inputs,
+            build_history,
+            files,
+            meta,
+        } = self;
+        let index = quote(&format!("{prefix}files_generated_by"));
+        format!(
+            "
+            CREATE TABLE IF NOT EXISTS {builds} (
+                hash BLOB PRIMARY KEY NOT NULL,
+                last_start INTEGER NOT NULL,
+                last_end INTEGER,
+                input_set_digest BLOB NOT NULL
+            ) WITHOUT ROWID;
+            CREATE TABLE IF NOT EXISTS {build_inputs} (
+                build BLOB NOT NULL,
+                position INTEGER NOT NULL,
+                path NOT NULL,
+                PRIMARY KEY (build, position)
+            ) WITHOUT ROWID;
+            CREATE TABLE IF NOT EXISTS {build_history} (
+                build BLOB NOT NULL,
+                position INTEGER NOT NULL,
+                start INTEGER NOT NULL,
+                duration INTEGER NOT NULL,
+                succeeded INTEGER NOT NULL,
+                exit_code INTEGER,
+                peak_memory INTEGER,
+                PRIMARY KEY (build, position)
+            ) WITHOUT ROWID;
+            CREATE TABLE IF NOT EXISTS {files} (
+                path PRIMARY KEY NOT NULL,
+                last_seen INTEGER NOT NULL,
+                generated_by BLOB NOT NULL
+            ) WITHOUT ROWID;
+            CREATE INDEX IF NOT EXISTS {index} ON {files} (generated_by);
+            CREATE TABLE IF NOT EXISTS {meta} (
+                key TEXT PRIMARY KEY NOT NULL,
+                value INTEGER NOT NULL
+            ) WITHOUT ROWID;
+            "
+        )
+    }
+
+    fn drop(&self) -> String {
+        let Self {
+            builds,
+            build_inputs,
+            build_history,
+            files,
+            meta,
+            ..
+        } = self;
+        format!(
+            "
+            DROP TABLE IF EXISTS {builds};
+            DROP TABLE IF EXISTS {build_inputs};
+            DROP TABLE IF EXISTS {build_history};
+            DROP TABLE IF EXISTS {files};
+            DROP TABLE IF EXISTS {meta};
+            "
+        )
+    }
+}
+
+/// Quote an identifier for use in SQL.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// A namespace of a SQLite database used as an [`ExecDb`].
+///
+/// A database can hold several namespaces, each in its own set of tables, so
+/// that multiple build graphs can share it without clobbering each other's
+/// file records. [`Self::open`] uses the default namespace, and
+/// [`Self::namespace`] gives access to the others.
+///
+/// Several processes can use a database at once, and each transaction is
+/// atomic. However, two processes building the same outputs still race on the
+/// files and overwrite each other's records. Hold a [`n2o5::lock::BuildLock`]
+/// for the whole build to prevent this.
+pub struct ExecSqlite {
+    path: PathBuf,
+    pub(crate) tables: Tables,
+    /// The only connection used for writing
+    writer: Mutex<Connection>,
+    /// Idle connections used for reading
+    readers: Mutex<Vec<Connection>>,
+}
+
+impl ExecSqlite {
+    /// Open or create the database at the given path in WAL mode, creating the
+    /// tables if needed.
+    ///
+    /// If the stored data is from an incompatible version of this crate, it is
+    /// cleared.
+    pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let writer = Self::connect(&path)?;
+        writer.pragma_update(None, "journal_mode", "WAL")?;
+        Self::with_namespace(path, writer, "")
+    }
+
+    /// Open an existing database and use the namespace with the given name as
+    /// it is, e.g. to inspect its records. The empty name refers to the
+    /// default namespace.
+    ///
+    /// Unlike [`Self::open`], this never creates the database or the tables
+    /// of the namespace, and never upgrades or clears the stored data. It
+    /// fails if the namespace has no tables, or if its data is from another
+    /// version.
+    pub fn open_existing(
+        path: impl AsRef<Path>,
+        namespace: &str,
+    ) -> Result<Self, OpenExistingError<rusqlite::Error>> {
+        let path = path.as_ref().to_owned();
+        let flags = OpenFlags::default().difference(OpenFlags::SQLITE_OPEN_CREATE);
+        let writer = Self::connect_with_flags(&path, flags)?;
+        let tables = Tables::new(namespace);
+        if !has_table(&writer, &format!("{}builds", tables.prefix))? {
+            return Err(OpenExistingError::Missing);
+        }
+        let (schema, format) = read_versions(&writer, &tables)?;
+        if schema != n2o5::db::SCHEMA_VERSION || format != FORMAT_VERSION {
+            return Err(OpenExistingError::VersionMismatch {
+                schema,
+                format,
+                expected_format: FORMAT_VERSION,
+            });
+        }
+        Ok(Self {
+            path,
+            tables,
+            writer: Mutex::new(writer),
+            readers: Mutex::new(vec![]),
+        })
+    }
+
+    /// Use the namespace with the given name in the same database, creating
+    /// its tables if needed. The empty name refers to the default namespace.
+    ///
+    /// The namespace has its own connections to the database. Like
+    /// [`Self::open`], its stored data is cleared if it is from an
+    /// incompatible version.
+    pub fn namespace(&self, name: &str) -> rusqlite::Result<Self> {
+        let writer = Self::connect(&self.path)?;
+        Self::with_namespace(self.path.clone(), writer, name)
+    }
+
+    fn with_namespace(path: PathBuf, writer: Connection, name: &str) -> rusqlite::Result<Self> {
+        let this = Self {
+            path,
+            tables: Tables::new(name),
+            writer: Mutex::new(writer),
+            readers: Mutex::new(vec![]),
+        };
+        this.upgrade()?;
+        Ok(this)
+    }
+
+    fn connect(path: &Path) -> rusqlite::Result<Connection> {
+        Self::connect_with_flags(path, OpenFlags::default())
+    }
+
+    fn connect_with_flags(path: &Path, flags: OpenFlags) -> rusqlite::Result<Connection> {
+        let conn = Connection::open_with_flags(path, flags)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        // Committed transactions survive process crashes in WAL mode, though
+        // not power loss.
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        Ok(conn)
+    }
+
+    /// Take an idle reading connection, or open a new one.
+    fn take_reader(&self) -> Connection {
+        let idle = self.readers.lock().unwrap().pop();
+        match idle {
+            Some(conn) => conn,
+            None => Self::connect(&self.path).expect("Failed to open read connection"),
+        }
+    }
+
+    /// Put a reading connection back to the pool.
+    pub(crate) fn return_reader(&self, conn: Connection) {
+        self.readers.lock().unwrap().push(conn);
+    }
+
+    fn lock_writer(&self) -> MutexGuard<'_, Connection> {
+        self.writer.lock().unwrap()
+    }
+
+    /// Bring the stored data to the current version, or discard it if that's
+    /// not possible.
+    fn upgrade(&self) -> rusqlite::Result<()> {
+        let mut conn = self.lock_writer();
+        let txn = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+
+        let tables = &self.tables;
+        let fresh = !has_table(&txn, &format!("{}builds", tables.prefix))?;
+        if !fresh {
+            let (schema, format) = read_versions(&txn, tables)?;
+            if !(1..=n2o5::db::SCHEMA_VERSION).contains(&schema)
+                || !(1..=FORMAT_VERSION).contains(&format)
+            {
+                warn!(
+                    "Database has schema version {schema} and format version {format}, \
+                    which is incompatible with schema version {} and format version \
+                    {FORMAT_VERSION}. Resetting the database.",
+                    n2o5::db::SCHEMA_VERSION,
+                );
+                txn.execute_batch(&tables.drop())?;
+            } else if schema < n2o5::db::SCHEMA_VERSION || format < FORMAT_VERSION {
+                // The build history table added by both is created below, and
+                // the builds without history rows have an empty history.
+                warn!(
+                    "Upgrading database from schema version {schema} and format \
+                    version {format} to {} and {FORMAT_VERSION}",
+                    n2o5::db::SCHEMA_VERSION,
+                );
+                if schema == 1 {
+                    // Schema version 1 only recorded successful builds, but
+                    // never when they ended
+                    txn.execute(
+                        &format!(
+                            "UPDATE {} SET last_end = last_start WHERE last_end IS NULL",
+                            tables.builds
+                        ),
+                        [],
+                    )?;
+                }
+            }
+        }
+
+        create_tables(&txn, tables)?;
+        txn.commit()
+    }
+}
+
+impl ExecDb for ExecSqlite {
+    fn get_schema_version(&self) -> u64 {
+        let conn = self.take_reader();
+        let version = conn
+            .query_row(
+                &format!("SELECT value FROM {} WHERE key = ?1", self.tables.meta),
+                [SCHEMA_VERSION_KEY],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()
+            .expect("Failed to read from meta table")
+            .map_or(n2o5::db::SCHEMA_VERSION, |v| v as u64);
+        self.return_reader(conn);
+        version
+    }
+
+    fn reset(&self) {
+        let mut conn = self.lock_writer();
+        let txn = conn
+            .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)
+            .expect("Failed to begin reset transaction");
+        txn.execute_batch(&self.tables.drop())
+            .expect("Failed to drop tables");
+        create_tables(&txn, &self.tables).expect("Failed to create tables");
+        txn.commit().expect("Failed to commit reset transaction");
+    }
+
+    fn begin_read<'r>(&'r self) -> Box<dyn DbReader + 'r> {
+        Box::new(Reader::new(self, self.take_reader()))
+    }
+
+    fn begin_write<'w>(&'w self) -> Box<dyn DbWriter + 'w> {
+        Box::new(Writer::new(self.lock_writer(), &self.tables))
+    }
+}
+
+/// Check whether a table with the given unquoted name exists.
+fn has_table(conn: &Connection, name: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT 1 FROM sqlite_schema WHERE type = 'table' AND name = ?1",
+        [name],
+        |_| Ok(()),
+    )
+    .optional()
+    .map(|row| row.is_some())
+}
+
+/// Read the stored schema and format versions. Missing versions are treated
+/// as zero, which is incompatible with every version.
+fn read_versions(conn: &Connection, tables: &Tables) -> rusqlite::Result<(u64, u64)> {
+    if !has_table(conn, &format!("{}meta", tables.prefix))? {
+        return Ok((0, 0));
+    }
+
+    let sql = format!("SELECT value FROM {} WHERE key = ?1", tables.meta);
+    let get = |key| {
+        conn.query_row(&sql, [key], |row| row.get::<_, i64>(0))
+            .optional()
+            .map(|v| v.map_or(0, |v| v as u64))
+    };
+    Ok((get(SCHEMA_VERSION_KEY)?, get(FORMAT_VERSION_KEY)?))
+}
+
+/// Create all tables that don't exist yet, and store the current versions.
+fn create_tables(conn: &Connection, tables: &Tables) -> rusqlite::Result<()> {
+    conn.execute_batch(&tables.create())?;
+    let mut stmt = conn.prepare(&format!(
+        "INSERT OR REPLACE INTO {} (key, value) VALUES (?1, ?2)",
+        tables.meta
+    ))?;
+    stmt.execute((SCHEMA_VERSION_KEY, n2o5::db::SCHEMA_VERSION as i64))?;
+    stmt.execute((FORMAT_VERSION_KEY, FORMAT_VERSION as i64))?;
+    Ok(())
+}
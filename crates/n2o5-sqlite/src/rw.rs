@@ -0,0 +1,378 @@
+//! Read/write transaction adapters for SQLite.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::MutexGuard,
+};
+
+use n2o5::db::{BuildHash, BuildInfo, DbReader, DbWriter, ExecRecord, FileInfo};
+use rusqlite::{Connection, OptionalExtension};
+use tracing::warn;
+
+use crate::{
+    ExecSqlite, Tables,
+    codec::{
+        SqlPath, build_from_row, build_hash_from_sql, duration_to_sql, exec_record_from_row,
+        file_from_row, path_from_sql, time_to_sql,
+    },
+};
+
+/// The columns of the build history table, in the order decoded by
+/// [`exec_record_from_row`].
+const HISTORY_COLUMNS: &str = "start, duration, succeeded, exit_code, peak_memory";
+
+/// Read the additional inputs of the given build.
+fn build_inputs(conn: &Connection, tables: &Tables, hash: BuildHash) -> Vec<PathBuf> {
+    let mut stmt = conn
+        .prepare_cached(&format!(
+            "SELECT path FROM {} WHERE build = ?1 ORDER BY position",
+            tables.build_inputs
+        ))
+        .expect("Failed to prepare build input query");
+    stmt.query_map([&hash.0], |row| Ok(path_from_sql(row.get_ref_unwrap(0))))
+        .expect("Failed to read from build input table")
+        .map(|entry| entry.expect("Failed to read from build input table"))
+        .filter_map(|path| {
+            path.inspect_err(|e| warn!("Corrupted input record of {hash:?}, skipping: {e}"))
+                .ok()
+        })
+        .collect()
+}
+
+/// Read the history of the given build.
+fn build_history(conn: &Connection, tables: &Tables, hash: BuildHash) -> Vec<ExecRecord> {
+    let mut stmt = conn
+        .prepare_cached(&format!(
+            "SELECT {HISTORY_COLUMNS} FROM {} WHERE build = ?1 ORDER BY position",
+            tables.build_history
+        ))
+        .expect("Failed to prepare build history query");
+    stmt.query_map([&hash.0], |row| Ok(exec_record_from_row(row)))
+        .expect("Failed to read from build history table")
+        .map(|entry| entry.expect("Failed to read from build history table"))
+        .filter_map(|record| {
+            record
+                .inspect_err(|e| warn!("Corrupted history record of {hash:?}, skipping: {e}"))
+                .ok()
+        })
+        .collect()
+}
+
+pub(crate) struct Reader<'r> {
+    db: &'r ExecSqlite,
+    /// Always `Some` until dropped
+    conn: Option<Connection>,
+}
+
+impl<'r> Reader<'r> {
+    pub(crate) fn new(db: &'r ExecSqlite, conn: Connection) -> Self {
+        conn.execute_batch("BEGIN DEFERRED")
+            .expect("Failed to begin read transaction");
+        // A deferred transaction only takes its snapshot on the first read.
+        conn.query_row("SELECT count(*) FROM sqlite_schema", [], |_| Ok(()))
+            .expect("Failed to begin read transaction");
+        Self {
+            db,
+            conn: Some(conn),
+        }
+    }
+
+    fn conn(&self) -> &Connection {
+        self.conn.as_ref().expect("connection taken")
+    }
+}
+
+impl Drop for Reader<'_> {
+    fn drop(&mut self) {
+        let conn = self.conn.take().expect("connection taken");
+        match conn.execute_batch("ROLLBACK") {
+            Ok(()) => self.db.return_reader(conn),
+            Err(e) => warn!("Failed to end read transaction: {e}"),
+        }
+    }
+}
+
+impl DbReader for Reader<'_> {
+    fn get_build_info(&self, hash: BuildHash) -> Option<BuildInfo> {
+        let mut stmt = self
+            .conn()
+            .prepare_cached(&format!(
+                "SELECT last_start, last_end, input_set_digest FROM {} WHERE hash = ?1",
+                self.db.tables.builds
+            ))
+            .expect("Failed to prepare build query");
+        let info = stmt
+            .query_row([&hash.0], |row| Ok(build_from_row(row)))
+            .optional()
+            .expect("Failed to read from build table")?;
+        let mut info = info
+            .inspect_err(|e| warn!("Corrupted build record for {hash:?}, treating as missing: {e}"))
+            .ok()?;
+        info.additional_inputs = build_inputs(self.conn(), &self.db.tables, hash);
+        info.history = build_history(self.conn(), &self.db.tables, hash);
+        Some(info)
+    }
+
+    fn get_file_info(&self, path: &Path) -> Option<FileInfo> {
+        let mut stmt = self
+            .conn()
+            .prepare_cached(&format!(
+                "SELECT last_seen, generated_by FROM {} WHERE path = ?1",
+                self.db.tables.files
+            ))
+            .expect("Failed to prepare file query");
+        let info = stmt
+            .query_row([SqlPath(path)], |row| Ok(file_from_row(row)))
+            .optional()
+            .expect("Failed to read from file table")?;
+        info.inspect_err(|e| warn!("Corrupted file record for {path:?}, treating as missing: {e}"))
+            .ok()
+    }
+
+    fn builds(&self) -> Box<dyn Iterator<Item = (BuildHash, BuildInfo)> + '_> {
+        let conn = self.conn();
+        let tables = &self.db.tables;
+
+        let mut inputs: HashMap<BuildHash, Vec<PathBuf>> = HashMap::new();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT build, path FROM {} ORDER BY build, position",
+                tables.build_inputs
+            ))
+            .expect("Failed to prepare build input query");
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(build_hash_from_sql(row.get_ref_unwrap(0))
+                    .and_then(|hash| Ok((hash, path_from_sql(row.get_ref_unwrap(1))?))))
+            })
+            .expect("Failed to iterate over build input table");
+        for entry in rows {
+            match entry.expect("Failed to read from build input table") {
+                Ok((hash, path)) => inputs.entry(hash).or_default().push(path),
+                Err(e) => warn!("Corrupted build input record, skipping: {e}"),
+            }
+        }
+
+        let mut history: HashMap<BuildHash, Vec<ExecRecord>> = HashMap::new();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {HISTORY_COLUMNS}, build FROM {} ORDER BY build, position",
+                tables.build_history
+            ))
+            .expect("Failed to prepare build history query");
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(build_hash_from_sql(row.get_ref_unwrap(5))
+                    .and_then(|hash| Ok((hash, exec_record_from_row(row)?))))
+            })
+            .expect("Failed to iterate over build history table");
+        for entry in rows {
+            match entry.expect("Failed to read from build history table") {
+                Ok((hash, record)) => history.entry(hash).or_default().push(record),
+                Err(e) => warn!("Corrupted build history record, skipping: {e}"),
+            }
+        }
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT last_start, last_end, input_set_digest, hash FROM {}",
+                tables.builds
+            ))
+            .expect("Failed to prepare build query");
+        let builds = stmt
+            .query_map([], |row| {
+                Ok(build_hash_from_sql(row.get_ref_unwrap(3))
+                    .and_then(|hash| Ok((hash, build_from_row(row)?))))
+            })
+            .expect("Failed to iterate over build table")
+            .map(|entry| entry.expect("Failed to read from build table"))
+            .filter_map(|entry| {
+                let (hash, mut info) = entry
+                    .inspect_err(|e| warn!("Corrupted build record, skipping: {e}"))
+                    .ok()?;
+                info.additional_inputs = inputs.remove(&hash).unwrap_or_default();
+                info.history = history.remove(&hash).unwrap_or_default();
+                Some((hash, info))
+            })
+            .collect::<Vec<_>>();
+        Box::new(builds.into_iter())
+    }
+
+    fn files(&self) -> Box<dyn Iterator<Item = (PathBuf, FileInfo)> + '_> {
+        let mut stmt = self
+            .conn()
+            .prepare(&format!(
+                "SELECT last_seen, generated_by, path FROM {}",
+                self.db.tables.files
+            ))
+            .expect("Failed to prepare file query");
+        let files = stmt
+            .query_map([], |row| {
+                Ok(path_from_sql(row.get_ref_unwrap(2))
+                    .and_then(|path| Ok((path, file_from_row(row)?))))
+            })
+            .expect("Failed to iterate over file table")
+            .map(|entry| entry.expect("Failed to read from file table"))
+            .filter_map(|entry| {
+                entry
+                    .inspect_err(|e| warn!("Corrupted file record, skipping: {e}"))
+                    .ok()
+            })
+            .collect::<Vec<_>>();
+        Box::new(files.into_iter())
+    }
+
+    fn files_generated_by(&self, hash: BuildHash) -> Box<dyn Iterator<Item = PathBuf> + '_> {
+        let mut stmt = self
+            .conn()
+            .prepare_cached(&format!(
+                "SELECT path FROM {} WHERE generated_by = ?1",
+                self.db.tables.files
+            ))
+            .expect("Failed to prepare file query");
+        let files = stmt
+            .query_map([&hash.0], |row| Ok(path_from_sql(row.get_ref_unwrap(0))))
+            .expect("Failed to iterate over file table")
+            .map(|entry| entry.expect("Failed to read from file table"))
+            .filter_map(|path| {
+                path.inspect_err(|e| warn!("Corrupted file record, skipping: {e}"))
+                    .ok()
+            })
+            .collect::<Vec<_>>();
+        Box::new(files.into_iter())
+    }
+}
+
+pub(crate) struct Writer<'w> {
+    conn: MutexGuard<'w, Connection>,
+    tables: &'w Tables,
+    committed: bool,
+}
+
+impl<'w> Writer<'w> {
+    pub(crate) fn new(conn: MutexGuard<'w, Connection>, tables: &'w Tables) -> Self {
+        conn.execute_batch("BEGIN IMMEDIATE")
+            .expect("Failed to begin write transaction");
+        Self {
+            conn,
+            tables,
+            committed: false,
+        }
+    }
+
+    fn execute(&self, sql: &str, params: impl rusqlite::Params) {
+        self.conn
+            .prepare_cached(sql)
+            .and_then(|mut stmt| stmt.execute(params))
+            .unwrap_or_else(|e| panic!("Failed to execute `{sql}`: {e}"));
+    }
+}
+
+impl Drop for Writer<'_> {
+    fn drop(&mut self) {
+        if !self.committed
+            && let Err(e) = self.conn.execute_batch("ROLLBACK")
+        {
+            warn!("Failed to roll back write transaction: {e}");
+        }
+    }
+}
+
+impl DbWriter for Writer<'_> {
+    fn set_build_info(&mut self, hash: BuildHash, info: BuildInfo) {
+        let tables = self.tables;
+        self.execute(
+            &format!(
+                "INSERT OR REPLACE INTO {} (hash, last_start, last_end, input_set_digest) \
+                VALUES (?1, ?2, ?3, ?4)",
+                tables.builds
+            ),
+            (
+                &hash.0,
+                time_to_sql(info.last_start),
+                info.last_end.map(time_to_sql),
+                &info.input_set_digest.0,
+            ),
+        );
+        self.execute(
+            &format!("DELETE FROM {} WHERE build = ?1", tables.build_inputs),
+            [&hash.0],
+        );
+        for (position, path) in info.additional_inputs.iter().enumerate() {
+            self.execute(
+                &format!(
+                    "INSERT INTO {} (build, position, path) VALUES (?1, ?2, ?3)",
+                    tables.build_inputs
+                ),
+                (&hash.0, position as i64, SqlPath(path)),
+            );
+        }
+        self.execute(
+            &format!("DELETE FROM {} WHERE build = ?1", tables.build_history),
+            [&hash.0],
+        );
+        for (position, record) in info.history.iter().enumerate() {
+            self.execute(
+                &format!(
+                    "INSERT INTO {} (build, position, {HISTORY_COLUMNS}) \
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    tables.build_history
+                ),
+                (
+                    &hash.0,
+                    position as i64,
+                    time_to_sql(record.start),
+                    duration_to_sql(record.duration),
+                    record.succeeded,
+                    record.exit_code,
+                    record.peak_memory.map(|bytes| bytes as i64),
+                ),
+            );
+        }
+    }
+
+    fn invalidate_build(&mut self, hash: BuildHash) {
+        let tables = self.tables;
+        self.execute(
+            &format!("DELETE FROM {} WHERE hash = ?1", tables.builds),
+            [&hash.0],
+        );
+        self.execute(
+            &format!("DELETE FROM {} WHERE build = ?1", tables.build_inputs),
+            [&hash.0],
+        );
+        self.execute(
+            &format!("DELETE FROM {} WHERE build = ?1", tables.build_history),
+            [&hash.0],
+        );
+    }
+
+    fn set_file_info(&mut self, path: &Path, info: FileInfo) {
+        self.execute(
+            &format!(
+                "INSERT OR REPLACE INTO {} (path, last_seen, generated_by) VALUES (?1, ?2, ?3)",
+                self.tables.files
+            ),
+            (
+                SqlPath(path),
+                time_to_sql(info.last_seen),
+                &info.generated_by.0,
+            ),
+        );
+    }
+
+    fn invalidate_file(&mut self, path: &Path) {
+        self.execute(
+            &format!("DELETE FROM {} WHERE path = ?1", self.tables.files),
+            [SqlPath(path)],
+        );
+    }
+
+    fn commit(mut self: Box<Self>) {
+        self.conn
+            .execute_batch("COMMIT")
+            .expect("Failed to commit transaction");
+        self.committed = true;
+    }
+}
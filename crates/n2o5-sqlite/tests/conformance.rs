@@ -0,0 +1,27 @@
+//! Runs the shared [`n2o5::db::conformance`] suite against [`ExecSqlite`].
+
+use std::path::Path;
+
+use n2o5_sqlite::ExecSqlite;
+
+n2o5::db_conformance_tests!(|dir: &Path| ExecSqlite::open(dir.join("n2o5.sqlite")).unwrap());
+
+mod namespaced {
+    use super::*;
+
+    n2o5::db_conformance_tests!(|dir: &Path| {
+        ExecSqlite::open(dir.join("n2o5.sqlite"))
+            .unwrap()
+            .namespace("debug")
+            .unwrap()
+    });
+}
+
+#[test]
+fn namespace_isolation() {
+    n2o5::db::conformance::namespace_isolation(|dir: &Path| {
+        let db = ExecSqlite::open(dir.join("n2o5.sqlite")).unwrap();
+        let release = db.namespace("release").unwrap();
+        (db, release)
+    });
+}
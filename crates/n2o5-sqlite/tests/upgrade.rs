@@ -0,0 +1,44 @@
+//! Opening databases written with other schema versions.
+
+use std::path::Path;
+
+use n2o5_sqlite::ExecSqlite;
+
+#[test]
+fn schema_v1_records_are_upgraded() {
+    n2o5::db::conformance::upgraded_build_is_up_to_date(
+        |dir: &Path| ExecSqlite::open(dir.join("n2o5.sqlite")).unwrap(),
+        |dir, hash, _old| {
+            // Turn it into the layout of schema version 1 and format version
+            // 1, which had no history table and never recorded the end of a
+            // build
+            let conn = rusqlite::Connection::open(dir.join("n2o5.sqlite")).unwrap();
+            conn.execute_batch(
+                "DROP TABLE build_history;
+                UPDATE meta SET value = 1 WHERE key IN ('schema_version', 'format_version');",
+            )
+            .unwrap();
+            conn.execute(
+                "UPDATE builds SET last_end = NULL WHERE hash = ?1",
+                [hash.0.as_slice()],
+            )
+            .unwrap();
+        },
+    );
+}
+
+#[test]
+fn open_existing_keeps_other_versions() {
+    n2o5::db::conformance::open_existing(
+        |dir: &Path| ExecSqlite::open(dir.join("n2o5.sqlite")).unwrap(),
+        |dir, namespace| ExecSqlite::open_existing(dir.join("n2o5.sqlite"), namespace),
+        |dir, version| {
+            let conn = rusqlite::Connection::open(dir.join("n2o5.sqlite")).unwrap();
+            conn.execute(
+                "UPDATE meta SET value = ?1 WHERE key = 'schema_version'",
+                [version as i64],
+            )
+            .unwrap();
+        },
+    );
+}
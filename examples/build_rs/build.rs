@@ -43,9 +43,6 @@ fn run() -> Result<(), DynError> {
     let config = BuildConfig::from_env()?;
 
     fs::create_dir_all(&config.out_dir)?;
-    if let Some(parent) = config.generated_c.parent() {
-        fs::create_dir_all(parent)?;
-    }
 
     let static_lib = config.out_dir.join(format!("lib{}.a", config.lib_name));
 
@@ -131,10 +128,6 @@ fn build_ctx(state: &dyn Any) -> &BuildContext {
 
 fn generate_demo_source(state: &dyn Any) -> Result<(), DynError> {
     let ctx = build_ctx(state);
-    if let Some(parent) = ctx.generated_c.parent() {
-        fs::create_dir_all(parent)?;
-    }
-
     fs::write(&ctx.generated_c, DEMO_SOURCE.as_bytes())?;
     println!("cargo:warning=wrote {}", ctx.generated_c.display());
     Ok(())
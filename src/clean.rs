@@ -0,0 +1,136 @@
+//! Removing build outputs, like `ninja -t clean` and `ninja -t cleandead`.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use tracing::{debug, warn};
+
+use crate::{
+    db::{DbWriter, ExecDb},
+    graph::{BuildGraph, BuildId, BuildMethod, hash_build},
+    world::World,
+};
+
+/// Remove the declared outputs of the given builds, and of all builds they
+/// transitively depend on. The records of these builds and their outputs are
+/// removed from the database as well.
+///
+/// Returns the number of files removed. If some files cannot be removed, the
+/// rest are still cleaned and committed, and the first error is returned.
+pub fn clean(
+    graph: &BuildGraph,
+    db: &dyn ExecDb,
+    world: &dyn World,
+    builds: impl IntoIterator<Item = BuildId>,
+) -> std::io::Result<usize> {
+    let mut visited = HashSet::new();
+    let mut dfs_stack: Vec<BuildId> = builds.into_iter().collect();
+    while let Some(build) = dfs_stack.pop() {
+        if !visited.insert(build) {
+            continue;
+        }
+        dfs_stack.extend(graph.build_dependencies(build));
+    }
+
+    clean_builds(graph, db, world, visited)
+}
+
+/// Remove the declared outputs of every build in the graph, and remove their
+/// records from the database.
+///
+/// Returns the number of files removed. If some files cannot be removed, the
+/// rest are still cleaned and committed, and the first error is returned.
+pub fn clean_all(graph: &BuildGraph, db: &dyn ExecDb, world: &dyn World) -> std::io::Result<usize> {
+    clean_builds(graph, db, world, graph.nodes().map(|(id, _)| id))
+}
+
+fn clean_builds(
+    graph: &BuildGraph,
+    db: &dyn ExecDb,
+    world: &dyn World,
+    builds: impl IntoIterator<Item = BuildId>,
+) -> std::io::Result<usize> {
+    let mut removed = 0;
+    let mut error = None;
+    let mut txn = db.begin_write();
+    for id in builds {
+        let build = graph.lookup_build(id).expect("invalid BuildId");
+        txn.invalidate_build(hash_build(build, graph));
+
+        // Phony builds don't create their outputs
+        if let BuildMethod::Phony = build.command {
+            continue;
+        }
+        for &out in &build.outs {
+            let path = graph.lookup_path(out).expect("invalid FileId");
+            remove_file(world, &mut *txn, path, &mut removed, &mut error);
+        }
+    }
+    // Keep the records of the files that were removed even if some failed
+    txn.commit();
+
+    error.map_or(Ok(removed), Err)
+}
+
+/// Remove files recorded in the database that were generated by builds which
+/// no longer exist in the graph, and remove their records from the database.
+///
+/// Files that are still part of the graph are kept, even if the build that
+/// generated them has changed. They will be handled by the next build.
+///
+/// Returns the number of files removed. If some files cannot be removed, the
+/// rest are still cleaned and committed, and the first error is returned.
+pub fn clean_dead(
+    graph: &BuildGraph,
+    db: &dyn ExecDb,
+    world: &dyn World,
+) -> std::io::Result<usize> {
+    let live_builds: HashSet<_> = graph
+        .nodes()
+        .map(|(_, build)| hash_build(build, graph))
+        .collect();
+
+    let txn = db.begin_read();
+    let dead_files: Vec<PathBuf> = txn
+        .files()
+        .filter(|(path, info)| {
+            !live_builds.contains(&info.generated_by) && graph.lookup_fileid(path).is_none()
+        })
+        .map(|(path, _)| path)
+        .collect();
+    drop(txn);
+
+    let mut removed = 0;
+    let mut error = None;
+    let mut txn = db.begin_write();
+    for path in &dead_files {
+        remove_file(world, &mut *txn, path, &mut removed, &mut error);
+    }
+    txn.commit();
+
+    error.map_or(Ok(removed), Err)
+}
+
+/// Remove a single file and its record, counting it in `removed` if it
+/// existed. If the removal fails, the record is kept and the first error is
+/// stored in `error`, so the remaining files can still be cleaned.
+fn remove_file(
+    world: &dyn World,
+    txn: &mut dyn DbWriter,
+    path: &Path,
+    removed: &mut usize,
+    error: &mut Option<std::io::Error>,
+) {
+    if world.exists(path) {
+        debug!("Removing {path:?}");
+        if let Err(e) = world.remove(path) {
+            warn!("Failed to remove {path:?}: {e}");
+            error.get_or_insert(e);
+            return;
+        }
+        *removed += 1;
+    }
+    txn.invalidate_file(path);
+}
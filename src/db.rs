@@ -3,17 +3,25 @@
 //! The `n2o5` crate only contains the two most simplest implementation, the
 //! [`in_memory`] database, and the [`dumb`] database. For advanced
 //! implementations that actually uses embedded database for better resistance,
-//! check out `n2o5-heed` and `n2o5-redb`.
+//! check out `n2o5-heed`, `n2o5-redb` and `n2o5-sqlite`.
 
+pub mod gc;
 pub mod in_memory;
+pub mod rooted;
 
 #[cfg(feature = "db-dumb")]
 pub mod dumb;
 
+#[cfg(feature = "db-conformance")]
+pub mod conformance;
+
+#[cfg(feature = "db-portable")]
+pub mod portable;
+
 use std::{
     fmt::Debug,
     path::{Path, PathBuf},
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
 #[cfg(feature = "serde")]
@@ -22,6 +30,20 @@ use serde::{Deserialize, Serialize};
 #[cfg(feature = "bincode")]
 use bincode::{Decode, Encode};
 
+/// The version of the record layout, i.e. [`BuildInfo`] and [`FileInfo`].
+///
+/// Backends store this version alongside their data. When opening a database
+/// with an older version, backends should upgrade its records, e.g. by
+/// decoding build records of version 1 as [`BuildInfoV1`]. Records of a newer
+/// version cannot be decoded, so backends should log a warning and
+/// [reset](ExecDb::reset) the database. Backends may also offer to open a
+/// database without either, failing with [`OpenExistingError`] if its version
+/// differs.
+///
+/// - 1: Initial layout.
+/// - 2: Added [`BuildInfo::history`].
+pub const SCHEMA_VERSION: u64 = 2;
+
 /// A hash that uniquely identifies a build command.
 ///
 /// Generate one with [`crate::graph::hash_build`].
@@ -31,17 +53,22 @@ use bincode::{Decode, Encode};
 #[repr(transparent)]
 pub struct BuildHash(#[cfg_attr(feature = "serde", serde(with = "serde_bytes"))] pub [u8; 16]);
 
-impl Debug for BuildHash {
+/// Formats the hash in hex, as shown by the database tools.
+impl std::fmt::Display for BuildHash {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "BuildHash(")?;
         for byte in &self.0 {
             write!(f, "{:02x}", byte)?;
         }
-        write!(f, ")")?;
         Ok(())
     }
 }
 
+impl Debug for BuildHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BuildHash({self})")
+    }
+}
+
 /// A hash of a build's input environments.
 ///
 /// Generate one with [`crate::graph::hash_input_set`].
@@ -63,7 +90,7 @@ impl Debug for InputHash {
 }
 
 /// The information associated with a specific file in the DB
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[cfg_attr(feature = "bincode", derive(Encode, Decode))]
 pub struct FileInfo {
@@ -74,13 +101,14 @@ pub struct FileInfo {
 }
 
 /// The information associated with a specific build in the DB
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[cfg_attr(feature = "bincode", derive(Encode, Decode))]
 pub struct BuildInfo {
     /// The last time this build was started
     pub last_start: SystemTime,
-    /// The last time this build was successfully completed
+    /// The last time this build was successfully completed. `None` if the
+    /// last run has failed, in which case the build is always outdated.
     pub last_end: Option<SystemTime>,
     /// The hash of the fixed input set (file, env var, etc.) to this build when
     /// it last ran.
@@ -88,14 +116,98 @@ pub struct BuildInfo {
     /// Additional inputs that was not part of the input set hash, but
     /// should be considered as dependencies for this build.
     pub additional_inputs: Vec<PathBuf>,
+    /// The most recent executions of this build, oldest first. The length is
+    /// limited by [`ExecConfig::history_len`](crate::exec::ExecConfig::history_len).
+    pub history: Vec<ExecRecord>,
+}
+
+/// The layout of [`BuildInfo`] in [schema version](SCHEMA_VERSION) 1, before
+/// the history was added. Converts to a [`BuildInfo`] of a successful build
+/// with an empty history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "bincode", derive(Encode, Decode))]
+pub struct BuildInfoV1 {
+    pub last_start: SystemTime,
+    pub last_end: Option<SystemTime>,
+    pub input_set_digest: InputHash,
+    pub additional_inputs: Vec<PathBuf>,
+}
+
+impl From<BuildInfoV1> for BuildInfo {
+    fn from(info: BuildInfoV1) -> Self {
+        Self {
+            last_start: info.last_start,
+            // Schema version 1 only recorded successful builds, but never
+            // when they ended
+            last_end: Some(info.last_end.unwrap_or(info.last_start)),
+            input_set_digest: info.input_set_digest,
+            additional_inputs: info.additional_inputs,
+            history: vec![],
+        }
+    }
+}
+
+/// The outcome of one execution of a build, kept in [`BuildInfo::history`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "bincode", derive(Encode, Decode))]
+pub struct ExecRecord {
+    /// When the build was started
+    pub start: SystemTime,
+    /// How long the build ran
+    pub duration: Duration,
+    /// Whether the build succeeded
+    pub succeeded: bool,
+    /// The exit code of the build command, if it ran a process that exited
+    /// normally
+    pub exit_code: Option<i32>,
+    /// The peak resident memory of the build command in bytes, if known
+    pub peak_memory: Option<u64>,
+}
+
+/// The result of an integrity check on a database backend.
+///
+/// Backends that may be corrupted on disk offer an integrity check that scans
+/// through all records and removes those that cannot be decoded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// The number of corrupted build records removed
+    pub corrupted_builds: usize,
+    /// The number of corrupted file records removed
+    pub corrupted_files: usize,
+}
+
+/// An error opening a database as it is, without creating, upgrading or
+/// resetting it, e.g. to inspect its records.
+///
+/// `E` is the error type of the backend.
+#[derive(Debug, thiserror::Error)]
+pub enum OpenExistingError<E> {
+    #[error(transparent)]
+    Backend(#[from] E),
+    #[error("The database has no data in this namespace")]
+    Missing,
+    #[error(
+        "The database has schema version {schema} and format version {format}, \
+        but only schema version {} and format version {expected_format} can be \
+        opened without upgrading or resetting it",
+        SCHEMA_VERSION
+    )]
+    VersionMismatch {
+        schema: u64,
+        format: u64,
+        expected_format: u64,
+    },
 }
 
 /// A trait for the database caching build and file information.
 pub trait ExecDb: Send + Sync {
-    /// Get the schema version of stored data.
+    /// Get the schema version of stored data. See [`SCHEMA_VERSION`].
     fn get_schema_version(&self) -> u64;
 
-    /// Destroy all stored data and reset to an empty state.
+    /// Destroy all stored data and reset to an empty state with the current
+    /// [`SCHEMA_VERSION`].
     ///
     /// This might be used on schema version mismatch.
     fn reset(&self);
@@ -107,10 +219,47 @@ pub trait ExecDb: Send + Sync {
     fn begin_write<'w>(&'w self) -> Box<dyn DbWriter + 'w>;
 }
 
+impl<T: ExecDb + ?Sized> ExecDb for &T {
+    fn get_schema_version(&self) -> u64 {
+        (**self).get_schema_version()
+    }
+
+    fn reset(&self) {
+        (**self).reset()
+    }
+
+    fn begin_read<'r>(&'r self) -> Box<dyn DbReader + 'r> {
+        (**self).begin_read()
+    }
+
+    fn begin_write<'w>(&'w self) -> Box<dyn DbWriter + 'w> {
+        (**self).begin_write()
+    }
+}
+
 /// Trait for reading from the build database.
 pub trait DbReader {
     fn get_build_info(&self, hash: BuildHash) -> Option<BuildInfo>;
     fn get_file_info(&self, path: &Path) -> Option<FileInfo>;
+
+    /// Iterate over all build records in the database, in no particular order.
+    fn builds(&self) -> Box<dyn Iterator<Item = (BuildHash, BuildInfo)> + '_>;
+
+    /// Iterate over all file records in the database, in no particular order.
+    fn files(&self) -> Box<dyn Iterator<Item = (PathBuf, FileInfo)> + '_>;
+
+    /// Iterate over the paths of all files recorded as generated by the given
+    /// build, in no particular order.
+    ///
+    /// The default implementation scans through all file records. Backends
+    /// should override it with an index when possible.
+    fn files_generated_by(&self, hash: BuildHash) -> Box<dyn Iterator<Item = PathBuf> + '_> {
+        Box::new(
+            self.files()
+                .filter(move |(_, info)| info.generated_by == hash)
+                .map(|(path, _)| path),
+        )
+    }
 }
 
 /// Trait for writing to the build database.
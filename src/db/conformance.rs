@@ -0,0 +1,609 @@
+//! A shared test suite for [`ExecDb`] implementations.
+//!
+//! Every test case takes a function that opens the database under test in a
+//! given directory. Opening the same directory again must give back the
+//! persisted data. The cases panic on failure, so they can be called from
+//! tests directly, but the easiest way is to generate all of them with
+//! [`db_conformance_tests!`](crate::db_conformance_tests):
+//!
+//! ```ignore
+//! n2o5::db_conformance_tests!(|dir: &std::path::Path| MyDb::open(dir.join("my.db")).unwrap());
+//! ```
+//!
+//! Backends that cannot pass some of the cases, such as ones whose readers
+//! block writers and thus cannot run [`transaction_isolation`], may list the
+//! cases to run after a semicolon.
+
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use tempfile::TempDir;
+
+use crate::{
+    db::{
+        BuildHash, BuildInfo, BuildInfoV1, ExecDb, ExecRecord, FileInfo, InputHash,
+        OpenExistingError, SCHEMA_VERSION, gc,
+    },
+    exec::{BuildStatusKind, ExecConfig, Executor, FailureDetail},
+    graph::{BuildCommand, BuildGraph, BuildId, BuildMethod, BuildNode, GraphBuilder, hash_build},
+    progress::{Progress, ProgressConfig, ProgressStatus},
+};
+
+/// Generate a `#[test]` function for each case in this module.
+///
+/// The argument is an expression of a function that opens the database under
+/// test in a given directory, which is evaluated once per test. Optionally,
+/// the names of the cases to generate can be listed after a semicolon.
+#[macro_export]
+macro_rules! db_conformance_tests {
+    ($open:expr) => {
+        $crate::db_conformance_tests!(
+            $open;
+            set_and_get,
+            overwrite,
+            invalidate,
+            uncommitted_writes_are_discarded,
+            transaction_isolation,
+            reset,
+            persistence,
+            concurrent_readers,
+            iteration,
+            collect_garbage,
+        );
+    };
+    ($open:expr; $($case:ident),* $(,)?) => {
+        $(
+            #[test]
+            fn $case() {
+                $crate::db::conformance::$case($open);
+            }
+        )*
+    };
+}
+
+/// Create a temporary directory for a case, which is removed on drop even if
+/// the case fails.
+fn temp_dir(case: &str) -> TempDir {
+    tempfile::Builder::new()
+        .prefix(&format!("n2o5-conformance-{case}-"))
+        .tempdir()
+        .expect("Failed to create temporary directory")
+}
+
+fn time(secs: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::new(secs, 123_456_789)
+}
+
+fn build_hash(n: u8) -> BuildHash {
+    BuildHash([n; 16])
+}
+
+fn build_info(n: u8) -> BuildInfo {
+    BuildInfo {
+        last_start: time(n as u64),
+        last_end: Some(time(n as u64 + 1)),
+        input_set_digest: InputHash([n; 16]),
+        additional_inputs: vec![format!("in{n}.h").into(), format!("dir/in{n}.c").into()],
+        history: vec![
+            ExecRecord {
+                start: time(n as u64),
+                duration: Duration::from_millis(n as u64 * 10),
+                succeeded: false,
+                exit_code: None,
+                peak_memory: None,
+            },
+            ExecRecord {
+                start: time(n as u64 + 1),
+                duration: Duration::from_millis(n as u64 * 20),
+                succeeded: true,
+                exit_code: Some(0),
+                peak_memory: Some((n as u64) << 20),
+            },
+        ],
+    }
+}
+
+fn file_info(n: u8) -> FileInfo {
+    FileInfo {
+        last_seen: time(n as u64),
+        generated_by: build_hash(n),
+    }
+}
+
+/// Records written in a committed transaction can be read back.
+pub fn set_and_get<D: ExecDb>(open: impl Fn(&Path) -> D) {
+    let dir = temp_dir("set_and_get");
+    let db = open(dir.path());
+    assert_eq!(db.get_schema_version(), SCHEMA_VERSION);
+
+    let unfinished = BuildInfo {
+        last_end: None,
+        additional_inputs: vec![],
+        ..build_info(2)
+    };
+    let mut txn = db.begin_write();
+    txn.set_build_info(build_hash(1), build_info(1));
+    txn.set_build_info(build_hash(2), unfinished.clone());
+    txn.set_file_info(Path::new("out/a.o"), file_info(1));
+    txn.commit();
+
+    let txn = db.begin_read();
+    assert_eq!(txn.get_build_info(build_hash(1)), Some(build_info(1)));
+    assert_eq!(txn.get_build_info(build_hash(2)), Some(unfinished));
+    assert_eq!(txn.get_build_info(build_hash(3)), None);
+    assert_eq!(txn.get_file_info(Path::new("out/a.o")), Some(file_info(1)));
+    assert_eq!(txn.get_file_info(Path::new("out/b.o")), None);
+}
+
+/// Setting a record again replaces the previous one.
+pub fn overwrite<D: ExecDb>(open: impl Fn(&Path) -> D) {
+    let dir = temp_dir("overwrite");
+    let db = open(dir.path());
+
+    let mut txn = db.begin_write();
+    txn.set_build_info(build_hash(1), build_info(1));
+    txn.set_file_info(Path::new("a.o"), file_info(1));
+    txn.commit();
+
+    let mut txn = db.begin_write();
+    txn.set_build_info(build_hash(1), build_info(2));
+    txn.set_file_info(Path::new("a.o"), file_info(2));
+    txn.commit();
+
+    let txn = db.begin_read();
+    assert_eq!(txn.get_build_info(build_hash(1)), Some(build_info(2)));
+    assert_eq!(txn.get_file_info(Path::new("a.o")), Some(file_info(2)));
+    assert_eq!(txn.files_generated_by(build_hash(1)).count(), 0);
+    assert_eq!(
+        txn.files_generated_by(build_hash(2)).collect::<Vec<_>>(),
+        [PathBuf::from("a.o")]
+    );
+}
+
+/// Invalidated records are gone, and invalidating missing records is fine.
+pub fn invalidate<D: ExecDb>(open: impl Fn(&Path) -> D) {
+    let dir = temp_dir("invalidate");
+    let db = open(dir.path());
+
+    let mut txn = db.begin_write();
+    txn.set_build_info(build_hash(1), build_info(1));
+    txn.set_build_info(build_hash(2), build_info(2));
+    txn.set_file_info(Path::new("a.o"), file_info(1));
+    txn.set_file_info(Path::new("b.o"), file_info(2));
+    txn.commit();
+
+    let mut txn = db.begin_write();
+    txn.invalidate_build(build_hash(1));
+    txn.invalidate_file(Path::new("a.o"));
+    txn.invalidate_build(build_hash(3));
+    txn.invalidate_file(Path::new("c.o"));
+    txn.commit();
+
+    let txn = db.begin_read();
+    assert_eq!(txn.get_build_info(build_hash(1)), None);
+    assert_eq!(txn.get_file_info(Path::new("a.o")), None);
+    assert_eq!(txn.files_generated_by(build_hash(1)).count(), 0);
+    assert_eq!(txn.get_build_info(build_hash(2)), Some(build_info(2)));
+    assert_eq!(txn.get_file_info(Path::new("b.o")), Some(file_info(2)));
+}
+
+/// Write transactions dropped without committing have no effect.
+pub fn uncommitted_writes_are_discarded<D: ExecDb>(open: impl Fn(&Path) -> D) {
+    let dir = temp_dir("uncommitted");
+    let db = open(dir.path());
+
+    let mut txn = db.begin_write();
+    txn.set_build_info(build_hash(1), build_info(1));
+    txn.commit();
+
+    let mut txn = db.begin_write();
+    txn.set_build_info(build_hash(2), build_info(2));
+    txn.set_file_info(Path::new("a.o"), file_info(2));
+    txn.invalidate_build(build_hash(1));
+    drop(txn);
+
+    let txn = db.begin_read();
+    assert_eq!(txn.get_build_info(build_hash(1)), Some(build_info(1)));
+    assert_eq!(txn.get_build_info(build_hash(2)), None);
+    assert_eq!(txn.get_file_info(Path::new("a.o")), None);
+}
+
+/// Read transactions see a snapshot from when they began, and are not blocked
+/// by writes committed during their lifetime.
+pub fn transaction_isolation<D: ExecDb>(open: impl Fn(&Path) -> D) {
+    let dir = temp_dir("isolation");
+    let db = open(dir.path());
+
+    let mut txn = db.begin_write();
+    txn.set_build_info(build_hash(1), build_info(1));
+    txn.commit();
+
+    let before = db.begin_read();
+    // Transactions may be bound to their thread, so write from another one
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            let mut txn = db.begin_write();
+            txn.set_build_info(build_hash(1), build_info(2));
+            txn.set_file_info(Path::new("a.o"), file_info(1));
+            txn.commit();
+        });
+    });
+
+    assert_eq!(before.get_build_info(build_hash(1)), Some(build_info(1)));
+    assert_eq!(before.get_file_info(Path::new("a.o")), None);
+    assert_eq!(before.files().count(), 0);
+    drop(before);
+
+    let after = db.begin_read();
+    assert_eq!(after.get_build_info(build_hash(1)), Some(build_info(2)));
+    assert_eq!(after.get_file_info(Path::new("a.o")), Some(file_info(1)));
+}
+
+/// Resetting removes all records and keeps the database usable.
+pub fn reset<D: ExecDb>(open: impl Fn(&Path) -> D) {
+    let dir = temp_dir("reset");
+    let db = open(dir.path());
+
+    let mut txn = db.begin_write();
+    txn.set_build_info(build_hash(1), build_info(1));
+    txn.set_file_info(Path::new("a.o"), file_info(1));
+    txn.commit();
+
+    db.reset();
+    assert_eq!(db.get_schema_version(), SCHEMA_VERSION);
+    let txn = db.begin_read();
+    assert_eq!(txn.get_build_info(build_hash(1)), None);
+    assert_eq!(txn.get_file_info(Path::new("a.o")), None);
+    assert_eq!(txn.builds().count(), 0);
+    assert_eq!(txn.files().count(), 0);
+    assert_eq!(txn.files_generated_by(build_hash(1)).count(), 0);
+    drop(txn);
+
+    let mut txn = db.begin_write();
+    txn.set_file_info(Path::new("b.o"), file_info(2));
+    txn.commit();
+    assert_eq!(
+        db.begin_read().get_file_info(Path::new("b.o")),
+        Some(file_info(2))
+    );
+}
+
+/// Committed records survive closing and reopening the database.
+pub fn persistence<D: ExecDb>(open: impl Fn(&Path) -> D) {
+    let dir = temp_dir("persistence");
+
+    let db = open(dir.path());
+    let mut txn = db.begin_write();
+    txn.set_build_info(build_hash(1), build_info(1));
+    txn.set_file_info(Path::new("a.o"), file_info(1));
+    txn.commit();
+    let mut txn = db.begin_write();
+    txn.set_build_info(build_hash(2), build_info(2));
+    drop(txn);
+    drop(db);
+
+    let db = open(dir.path());
+    assert_eq!(db.get_schema_version(), SCHEMA_VERSION);
+    let txn = db.begin_read();
+    assert_eq!(txn.get_build_info(build_hash(1)), Some(build_info(1)));
+    assert_eq!(txn.get_build_info(build_hash(2)), None);
+    assert_eq!(txn.get_file_info(Path::new("a.o")), Some(file_info(1)));
+    assert_eq!(
+        txn.files_generated_by(build_hash(1)).collect::<Vec<_>>(),
+        [PathBuf::from("a.o")]
+    );
+}
+
+/// Multiple read transactions can be open at the same time, from multiple
+/// threads.
+pub fn concurrent_readers<D: ExecDb>(open: impl Fn(&Path) -> D) {
+    let dir = temp_dir("concurrent_readers");
+    let db = open(dir.path());
+
+    let mut txn = db.begin_write();
+    for n in 0..8 {
+        txn.set_build_info(build_hash(n), build_info(n));
+    }
+    txn.commit();
+
+    let outer = db.begin_read();
+    let barrier = std::sync::Barrier::new(8);
+    std::thread::scope(|s| {
+        for n in 0..8 {
+            let db = &db;
+            let barrier = &barrier;
+            s.spawn(move || {
+                let txn = db.begin_read();
+                // Make sure all readers are open at once
+                barrier.wait();
+                assert_eq!(txn.get_build_info(build_hash(n)), Some(build_info(n)));
+                assert_eq!(txn.builds().count(), 8);
+            });
+        }
+    });
+    assert_eq!(outer.get_build_info(build_hash(0)), Some(build_info(0)));
+}
+
+/// Iterating over the records returns each record exactly once.
+pub fn iteration<D: ExecDb>(open: impl Fn(&Path) -> D) {
+    let dir = temp_dir("iteration");
+    let db = open(dir.path());
+
+    let mut txn = db.begin_write();
+    txn.set_build_info(build_hash(1), build_info(1));
+    txn.set_build_info(build_hash(2), build_info(2));
+    txn.set_file_info(Path::new("a.o"), file_info(1));
+    txn.set_file_info(Path::new("b.o"), file_info(1));
+    txn.set_file_info(Path::new("c.o"), file_info(2));
+    txn.commit();
+
+    let txn = db.begin_read();
+    let mut builds = txn.builds().collect::<Vec<_>>();
+    builds.sort_by_key(|(hash, _)| hash.0);
+    assert_eq!(
+        builds,
+        [
+            (build_hash(1), build_info(1)),
+            (build_hash(2), build_info(2))
+        ]
+    );
+
+    let mut files = txn.files().collect::<Vec<_>>();
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+    assert_eq!(
+        files,
+        [
+            (PathBuf::from("a.o"), file_info(1)),
+            (PathBuf::from("b.o"), file_info(1)),
+            (PathBuf::from("c.o"), file_info(2)),
+        ]
+    );
+
+    let mut generated = txn.files_generated_by(build_hash(1)).collect::<Vec<_>>();
+    generated.sort();
+    assert_eq!(generated, [PathBuf::from("a.o"), PathBuf::from("b.o")]);
+    assert_eq!(txn.files_generated_by(build_hash(3)).count(), 0);
+}
+
+/// Garbage collection removes the records that the graph does not refer to,
+/// and keeps the others.
+pub fn collect_garbage<D: ExecDb>(open: impl Fn(&Path) -> D) {
+    let dir = temp_dir("collect_garbage");
+    let db = open(dir.path());
+
+    let mut gb = GraphBuilder::new();
+    let input = gb.add_file("a.c");
+    let output = gb.add_file("a.o");
+    gb.add_build(BuildNode {
+        command: BuildMethod::SubCommand(BuildCommand {
+            executable: "cc".into(),
+            args: vec![],
+        }),
+        ins: vec![input],
+        outs: vec![output],
+        description: None,
+    });
+    let graph = gb.build().unwrap();
+    let live = hash_build(graph.nodes().next().unwrap().1, &graph);
+
+    let mut txn = db.begin_write();
+    txn.set_build_info(live, build_info(1));
+    txn.set_build_info(build_hash(2), build_info(2));
+    txn.set_file_info(
+        Path::new("a.o"),
+        FileInfo {
+            generated_by: live,
+            ..file_info(1)
+        },
+    );
+    txn.set_file_info(Path::new("old.o"), file_info(2));
+    txn.commit();
+
+    let stats = gc::collect_garbage(&db, &graph);
+    assert_eq!(
+        stats,
+        gc::GcStats {
+            builds: 1,
+            files: 1
+        }
+    );
+
+    let txn = db.begin_read();
+    assert_eq!(txn.get_build_info(live), Some(build_info(1)));
+    assert_eq!(txn.get_build_info(build_hash(2)), None);
+    assert!(txn.get_file_info(Path::new("a.o")).is_some());
+    assert_eq!(txn.get_file_info(Path::new("old.o")), None);
+    assert_eq!(txn.builds().count(), 1);
+    assert_eq!(txn.files().count(), 1);
+}
+
+/// Namespaces of the same database keep separate records.
+///
+/// Unlike the other cases, this one is not generated by
+/// [`db_conformance_tests!`](crate::db_conformance_tests), since it needs to
+/// open two namespaces of the same database, which backends do differently.
+/// The function opens the database in a given directory and returns two
+/// distinct namespaces of it.
+pub fn namespace_isolation<D: ExecDb>(open: impl Fn(&Path) -> (D, D)) {
+    let dir = temp_dir("namespace_isolation");
+
+    let (a, b) = open(dir.path());
+    let mut txn = a.begin_write();
+    txn.set_build_info(build_hash(1), build_info(1));
+    txn.set_file_info(Path::new("a.o"), file_info(1));
+    txn.commit();
+    let mut txn = b.begin_write();
+    txn.set_build_info(build_hash(2), build_info(2));
+    txn.set_file_info(Path::new("a.o"), file_info(2));
+    txn.commit();
+
+    let txn = a.begin_read();
+    assert_eq!(txn.get_build_info(build_hash(2)), None);
+    assert_eq!(txn.get_file_info(Path::new("a.o")), Some(file_info(1)));
+    assert_eq!(txn.files().count(), 1);
+    drop(txn);
+    let txn = b.begin_read();
+    assert_eq!(txn.get_build_info(build_hash(1)), None);
+    assert_eq!(txn.get_file_info(Path::new("a.o")), Some(file_info(2)));
+    assert_eq!(txn.files_generated_by(build_hash(1)).count(), 0);
+    drop(txn);
+    drop((a, b));
+
+    let (a, b) = open(dir.path());
+    a.reset();
+    assert_eq!(a.begin_read().builds().count(), 0);
+    let txn = b.begin_read();
+    assert_eq!(txn.get_build_info(build_hash(2)), Some(build_info(2)));
+    assert_eq!(txn.get_file_info(Path::new("a.o")), Some(file_info(2)));
+}
+
+/// A [`Progress`] that records the final status of each build.
+#[derive(Default)]
+struct RecordingProgress(Mutex<Vec<(BuildId, BuildStatusKind)>>);
+
+impl Progress for RecordingProgress {
+    fn prepare(&self, _config: &ProgressConfig) {}
+
+    fn build_started(&self, _graph: &BuildGraph, _id: BuildId, _status: &ProgressStatus) {}
+
+    fn stdout_line(&self, _graph: &BuildGraph, _id: BuildId, _chunk: &[u8]) {}
+
+    fn build_finished(
+        &self,
+        _graph: &BuildGraph,
+        id: BuildId,
+        kind: BuildStatusKind,
+        _failure: Option<&FailureDetail>,
+        _status: &ProgressStatus,
+    ) {
+        self.0.lock().unwrap().push((id, kind));
+    }
+
+    fn finish(&self) {}
+}
+
+/// A build upgraded from schema version 1 is up to date, as long as nothing
+/// has changed since it ran.
+///
+/// Like [`namespace_isolation`], this case is not generated by
+/// [`db_conformance_tests!`](crate::db_conformance_tests), since writing
+/// records of an old version is specific to each backend. After a build has
+/// run and the database is dropped, `downgrade` is called with the directory,
+/// the hash of the build and its record in the layout of schema version 1, as
+/// written by versions that did not record when a build ended. It should
+/// replace the record and mark the database as schema version 1.
+pub fn upgraded_build_is_up_to_date<D: ExecDb>(
+    open: impl Fn(&Path) -> D,
+    downgrade: impl Fn(&Path, BuildHash, BuildInfoV1),
+) {
+    let dir = temp_dir("upgraded_build_is_up_to_date");
+    let input = dir.path().join("in.txt");
+    let output = dir.path().join("out.txt");
+    std::fs::write(&input, "in").unwrap();
+
+    let mut gb = GraphBuilder::new();
+    let ins = vec![gb.add_file(&input)];
+    let outs = vec![gb.add_file(&output)];
+    let runs = Arc::new(AtomicUsize::new(0));
+    let (out, counter) = (output.clone(), runs.clone());
+    let id = gb.add_build(BuildNode {
+        command: BuildMethod::Callback(
+            "write".into(),
+            Box::new(move |_| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                Ok(std::fs::write(&out, "out")?)
+            }),
+        ),
+        ins,
+        outs,
+        description: None,
+    });
+    let graph = gb.build().unwrap();
+    let hash = hash_build(graph.lookup_build(id).unwrap(), &graph);
+
+    let run = |db: &D| {
+        let cfg = ExecConfig::default();
+        let progress = RecordingProgress::default();
+        let mut exec = Executor::new(&cfg, &graph, db, &progress, &());
+        exec.want([id]);
+        exec.run().unwrap();
+        progress.0.into_inner().unwrap()
+    };
+
+    let db = open(dir.path());
+    run(&db);
+    assert_eq!(runs.load(Ordering::Relaxed), 1);
+    let info = db.begin_read().get_build_info(hash).unwrap();
+    drop(db);
+
+    downgrade(
+        dir.path(),
+        hash,
+        BuildInfoV1 {
+            last_start: info.last_start,
+            last_end: None,
+            input_set_digest: info.input_set_digest,
+            additional_inputs: info.additional_inputs,
+        },
+    );
+
+    let db = open(dir.path());
+    assert_eq!(db.get_schema_version(), SCHEMA_VERSION);
+    assert_eq!(run(&db), [(id, BuildStatusKind::UpToDate)]);
+    assert_eq!(runs.load(Ordering::Relaxed), 1);
+}
+
+/// Opening a database as it is gives back its records, and fails without
+/// touching them if the namespace has no data or is from another version.
+///
+/// Like [`upgraded_build_is_up_to_date`], this case is not generated by
+/// [`db_conformance_tests!`](crate::db_conformance_tests), since changing the
+/// stored version is specific to each backend. `open_existing` opens the
+/// given namespace of the database in the directory without creating,
+/// upgrading or resetting it. After the database is dropped,
+/// `set_schema_version` is called with the directory and a version to store
+/// for the default namespace.
+pub fn open_existing<D: ExecDb, E: Debug>(
+    open: impl Fn(&Path) -> D,
+    open_existing: impl Fn(&Path, &str) -> Result<D, OpenExistingError<E>>,
+    set_schema_version: impl Fn(&Path, u64),
+) {
+    let dir = temp_dir("open_existing");
+    let check = |db: &D| {
+        let txn = db.begin_read();
+        assert_eq!(txn.get_build_info(build_hash(1)), Some(build_info(1)));
+        assert_eq!(txn.get_file_info(Path::new("a.o")), Some(file_info(1)));
+    };
+
+    let db = open(dir.path());
+    let mut txn = db.begin_write();
+    txn.set_build_info(build_hash(1), build_info(1));
+    txn.set_file_info(Path::new("a.o"), file_info(1));
+    txn.commit();
+    drop(db);
+
+    let db = open_existing(dir.path(), "").unwrap();
+    assert_eq!(db.get_schema_version(), SCHEMA_VERSION);
+    check(&db);
+    drop(db);
+    assert!(matches!(
+        open_existing(dir.path(), "other"),
+        Err(OpenExistingError::Missing)
+    ));
+
+    set_schema_version(dir.path(), SCHEMA_VERSION + 1);
+    assert!(matches!(
+        open_existing(dir.path(), ""),
+        Err(OpenExistingError::VersionMismatch { schema, .. }) if schema == SCHEMA_VERSION + 1
+    ));
+    // The records of the newer version are still there
+    set_schema_version(dir.path(), SCHEMA_VERSION);
+    check(&open_existing(dir.path(), "").unwrap());
+}
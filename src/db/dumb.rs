@@ -1,24 +1,35 @@
 //! Very simple, serialize-based DB
 
 use std::{
+    ffi::OsString,
     fs::File,
-    io::{Read, Seek, Write},
-    path::Path,
-    sync::{Arc, RwLock},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock, RwLockWriteGuard},
 };
 
+use xxhash_rust::xxh3::xxh3_64;
+
 use crate::{
     ExecDb,
-    db::in_memory::{self, DbInner, Reader, Writer},
+    db::{
+        BuildHash, BuildInfo, DbWriter, FileInfo, OpenExistingError, SCHEMA_VERSION,
+        in_memory::{self, DbInner, DbInnerV1, Reader, WriteOp, WriteOpV1},
+    },
 };
 
 /// File-backed im-memory [`ExecDb`] for small tasks and single runs.
 ///
-/// Loads the entire database into memory on open, holds an exclusive file lock
-/// for the lifetime of the instance, and persists the whole state back to disk
-/// on drop. Reads and writes during execution are in-memory only; there is no
-/// mid-run durability. If the on-disk data cannot be deserialized, a fresh
-/// empty database is used.
+/// Loads the entire database into memory on open, and holds an exclusive file
+/// lock for the lifetime of the instance. Reads during execution are
+/// in-memory only. Each committed write transaction is appended to a journal
+/// at the end of the file, so a crashed or killed process only loses the
+/// transactions it has not committed. The journal is replayed on open, and
+/// compacted back into a single snapshot on drop. The snapshot is written to
+/// a temporary file next to the database, named after it with `.tmp`
+/// appended, which then replaces the database file, so a crash during
+/// compaction leaves the previous file intact. If the on-disk data cannot be
+/// deserialized, a fresh empty database is used.
 ///
 /// Use when:
 /// - Small graphs and low write throughput (e.g. incremental builds in
@@ -27,19 +38,35 @@ use crate::{
 /// - Losing the build cache is acceptable.
 ///
 /// Avoid when:
-/// - You expect interrupts mid-run.
 /// - Your graphs are large, or you generate a lot of files.
+/// - You need to survive power loss. The journal is not synced to disk.
 pub struct DumbDb {
     inner: Arc<DumbDbInner>,
 }
 
 struct DumbDbInner {
-    file: File,
+    path: PathBuf,
+    /// The locked database file. Replaced by each compaction.
+    file: Mutex<File>,
     data: RwLock<in_memory::DbInner>,
 }
 
 const CFG: bincode::config::Configuration = bincode::config::standard();
-const MAGIC: &[u8; 16] = b"AZ50_NTO_0000000";
+/// The header every dumb database file starts with.
+pub const MAGIC: &[u8; 16] = b"AZ50_NTO_0000000";
+/// The version of the file layout following the magic header. The header is
+/// followed by the schema version and this version, both as little-endian
+/// `u64`s, and then the encoded data.
+///
+/// - 1: A single snapshot of the data.
+/// - 2: The snapshot is followed by journal records. Each record is the
+///   length of its payload as a little-endian `u32`, the XXH3 hash of the
+///   payload as a little-endian `u64`, and the payload, which is the encoded
+///   list of changes in one transaction.
+const FORMAT_VERSION: u64 = 2;
+
+/// Size of the length and checksum before each journal record
+const RECORD_HEADER_LEN: usize = 12;
 
 impl DumbDb {
     /// Open and read the database from the given file.
@@ -50,74 +77,306 @@ impl DumbDb {
     ///
     /// If the DB cache file is in an invalid state, such as from an
     /// incompatible previous version of this crate or corrupted, the file will
-    /// be unconditionally viewed as empty and cleared.
+    /// be unconditionally viewed as empty and cleared. A warning is logged in
+    /// this case. If only the end of the journal is damaged, e.g. by a crash
+    /// while appending to it, the damaged part is discarded. Data of an older
+    /// schema version is upgraded.
     pub fn new(path: impl AsRef<Path>) -> std::io::Result<DumbDb> {
+        let path = path.as_ref();
         // Open the file first for reading the contents, and then hold the FD
         // till the end to write. Holding the lock at the meantime.
-        let mut file = File::options()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(false)
-            .open(path)?;
-        file.lock()?;
+        let mut file = open_locked(path, true)?;
+
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
 
-        // Verify magic
-        let mut magic_buf = [0u8; 16];
-        let Ok(_) = file.read_exact(&mut magic_buf) else {
-            // Not enough bytes for magic. Just ignore it.
-            tracing::warn!("Magic header does not exist, using empty DB");
-            return Ok(Self::create(file, Default::default()));
+        let Some(snapshot) = read_snapshot(&bytes) else {
+            let data = DbInner::default();
+            let file = compact(path, &data)?;
+            return Ok(Self::create(path, file, data));
         };
-        if &magic_buf != MAGIC {
-            // Invalid magic.
-            tracing::warn!("Invalid DB magic header, using empty DB");
-            return Ok(Self::create(file, Default::default()));
+        Self::load(path, file, &bytes, snapshot)
+    }
+
+    /// Open and read an existing database as it is, e.g. to inspect its
+    /// records, blocking on the lock like [`Self::new`].
+    ///
+    /// Unlike [`Self::new`], this never creates, upgrades or clears the file.
+    /// It fails if the file is missing, cannot be read, or has data from
+    /// another schema version. Like [`Self::new`], a damaged end of the
+    /// journal is discarded, and the file is compacted on drop.
+    pub fn open_existing(
+        path: impl AsRef<Path>,
+    ) -> Result<DumbDb, OpenExistingError<std::io::Error>> {
+        let path = path.as_ref();
+        let mut file = open_locked(path, false)?;
+
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+
+        let (schema, format) = read_header(&bytes).map_err(invalid_data)?;
+        if schema != SCHEMA_VERSION || !(1..=FORMAT_VERSION).contains(&format) {
+            return Err(OpenExistingError::VersionMismatch {
+                schema,
+                format,
+                expected_format: FORMAT_VERSION,
+            });
+        }
+        let (data, snapshot_end) = decode_snapshot(&bytes, schema).map_err(invalid_data)?;
+        Ok(Self::load(
+            path,
+            file,
+            &bytes,
+            (data, schema, snapshot_end),
+        )?)
+    }
+
+    /// Replay the journal following the snapshot read from `bytes`, the
+    /// contents of `file`.
+    fn load(
+        path: &Path,
+        mut file: File,
+        bytes: &[u8],
+        (mut data, schema, snapshot_end): (DbInner, u64, usize),
+    ) -> std::io::Result<DumbDb> {
+        let journal = &bytes[snapshot_end..];
+        let journal_end = snapshot_end + replay_journal(&mut data, schema, journal);
+        if schema < SCHEMA_VERSION {
+            // Rewrite the upgraded data, so that new journal records are not
+            // appended to records of the old version.
+            tracing::warn!("Upgrading DB from schema version {schema} to {SCHEMA_VERSION}");
+            let file = compact(path, &data)?;
+            return Ok(Self::create(path, file, data));
         }
 
-        // An error in deserialization likely means it's corrupted for
-        // whatever reason. Just create a new one later.
-        let deserialized = bincode::decode_from_std_read(&mut file, CFG).unwrap_or_default();
+        // Drop the damaged tail of the journal, so new records are appended
+        // right after the last valid one.
+        if journal_end < bytes.len() {
+            file.set_len(journal_end as u64)?;
+        }
+        file.seek(SeekFrom::Start(journal_end as u64))?;
 
-        Ok(Self::create(file, deserialized))
+        Ok(Self::create(path, file, data))
     }
 
-    fn create(file: std::fs::File, data: DbInner) -> Self {
+    fn create(path: &Path, file: File, data: DbInner) -> Self {
         DumbDb {
             inner: Arc::new(DumbDbInner {
-                file,
+                path: path.to_owned(),
+                file: Mutex::new(file),
                 data: RwLock::new(data),
             }),
         }
     }
 }
 
+/// Open and lock the database file, creating it if needed and `create` is
+/// set.
+fn open_locked(path: &Path, create: bool) -> std::io::Result<File> {
+    loop {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(create)
+            .truncate(false)
+            .open(path)?;
+        file.lock()?;
+        // Another process may have compacted the database while we waited
+        // for the lock, replacing the file we have opened
+        if is_current(&file, path)? {
+            return Ok(file);
+        }
+    }
+}
+
+/// Whether `file` is still the file at `path`.
+#[cfg(unix)]
+fn is_current(file: &File, path: &Path) -> std::io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let opened = file.metadata()?;
+    match std::fs::metadata(path) {
+        Ok(current) => Ok(opened.dev() == current.dev() && opened.ino() == current.ino()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether `file` is still the file at `path`.
+#[cfg(not(unix))]
+fn is_current(_file: &File, _path: &Path) -> std::io::Result<bool> {
+    Ok(true)
+}
+
+/// Read the header and snapshot, returning the data upgraded to the current
+/// schema version, the stored schema version and where the journal starts.
+/// Returns `None` if the file should be viewed as empty.
+fn read_snapshot(bytes: &[u8]) -> Option<(DbInner, u64, usize)> {
+    let (schema, format) = match read_header(bytes) {
+        Ok(versions) => versions,
+        Err(e) => {
+            tracing::warn!("{e}, using empty DB");
+            return None;
+        }
+    };
+    // Format 1 files are valid format 2 files with an empty journal.
+    if !(1..=SCHEMA_VERSION).contains(&schema) || !(1..=FORMAT_VERSION).contains(&format) {
+        tracing::warn!(
+            "DB has schema version {schema} and format version {format}, \
+            expected {SCHEMA_VERSION} and {FORMAT_VERSION}; using empty DB"
+        );
+        return None;
+    }
+
+    // An error in deserialization likely means it's corrupted for
+    // whatever reason.
+    match decode_snapshot(bytes, schema) {
+        Ok((data, end)) => Some((data, schema, end)),
+        Err(e) => {
+            tracing::warn!("Failed to decode DB snapshot, using empty DB: {e}");
+            None
+        }
+    }
+}
+
+/// Verify the magic header, and return the stored schema and format
+/// versions.
+fn read_header(bytes: &[u8]) -> Result<(u64, u64), &'static str> {
+    // Verify magic
+    let Some(magic) = bytes.get(..16) else {
+        // Not enough bytes for magic.
+        return Err("Magic header does not exist");
+    };
+    if magic != MAGIC {
+        return Err("Invalid DB magic header");
+    }
+
+    let Some(version_buf) = bytes.get(16..32) else {
+        return Err("DB version header does not exist");
+    };
+    let schema = u64::from_le_bytes(version_buf[..8].try_into().unwrap());
+    let format = u64::from_le_bytes(version_buf[8..].try_into().unwrap());
+    Ok((schema, format))
+}
+
+/// An error for a file that is not a valid database.
+fn invalid_data(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+}
+
+/// Decode the snapshot following the header, written with the given schema
+/// version, returning the data upgraded to the current schema version and
+/// where the journal starts.
+fn decode_snapshot(
+    bytes: &[u8],
+    schema: u64,
+) -> Result<(DbInner, usize), bincode::error::DecodeError> {
+    let decoded = match schema {
+        1 => bincode::decode_from_slice::<DbInnerV1, _>(&bytes[32..], CFG)
+            .map(|(data, len)| (data.into(), len)),
+        _ => bincode::decode_from_slice::<DbInner, _>(&bytes[32..], CFG),
+    };
+    decoded.map(|(data, len)| (data, 32 + len))
+}
+
+/// Apply the journal records in `journal`, written with the given schema
+/// version, to `data`, stopping at the first damaged record. Returns the
+/// length of the valid part of the journal.
+fn replay_journal(data: &mut DbInner, schema: u64, journal: &[u8]) -> usize {
+    let mut pos = 0;
+    while pos < journal.len() {
+        let Some((ops, len)) = decode_record(schema, &journal[pos..]) else {
+            tracing::warn!(
+                "Discarding {} bytes of damaged DB journal",
+                journal.len() - pos
+            );
+            break;
+        };
+        for op in ops {
+            data.apply(op);
+        }
+        pos += len;
+    }
+    pos
+}
+
+/// Decode the journal record at the start of `bytes`, returning its changes
+/// and its total length.
+fn decode_record(schema: u64, bytes: &[u8]) -> Option<(Vec<WriteOp>, usize)> {
+    let header = bytes.get(..RECORD_HEADER_LEN)?;
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let checksum = u64::from_le_bytes(header[4..].try_into().unwrap());
+    let payload = bytes.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + len)?;
+    if xxh3_64(payload) != checksum {
+        return None;
+    }
+    let ops = match schema {
+        1 => {
+            let (ops, _) = bincode::decode_from_slice::<Vec<WriteOpV1>, _>(payload, CFG).ok()?;
+            ops.into_iter().map(Into::into).collect()
+        }
+        _ => bincode::decode_from_slice(payload, CFG).ok()?.0,
+    };
+    Some((ops, RECORD_HEADER_LEN + len))
+}
+
+/// Encode a journal record holding the given changes.
+fn encode_record(ops: &[WriteOp]) -> Vec<u8> {
+    let payload = bincode::encode_to_vec(ops, CFG).expect("Failed to encode DumbDb journal");
+    let len = u32::try_from(payload.len()).expect("DumbDb transaction too large");
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+    record.extend_from_slice(&len.to_le_bytes());
+    record.extend_from_slice(&xxh3_64(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+    record
+}
+
+/// Replace the database file at `path` with a snapshot of `data` and an
+/// empty journal. Returns the new file, locked and with the cursor at the end.
+///
+/// The snapshot is written and synced to a temporary file first, which is
+/// then renamed over the database file. The lock is taken on the new file
+/// before that, so other processes never see it unlocked.
+fn compact(path: &Path, data: &DbInner) -> std::io::Result<File> {
+    let mut tmp_path = OsString::from(path);
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut file = File::create(&tmp_path)?;
+    file.lock()?;
+    let mut buf = MAGIC.to_vec();
+    buf.extend_from_slice(&data.schema_version.to_le_bytes());
+    buf.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bincode::encode_into_std_write(data, &mut buf, CFG).map_err(std::io::Error::other)?;
+    file.write_all(&buf)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(file)
+}
+
 impl Drop for DumbDbInner {
     fn drop(&mut self) {
-        self.file
-            .rewind()
-            .expect("Failed to seek to start in DumbDb");
-        self.file
-            .set_len(0)
-            .expect("Failed to truncate DumbDb file");
-        let data = self.data.get_mut().expect("lock poisoned");
-
-        self.file
-            .write_all(MAGIC)
-            .expect("Failed to write magic header to DumbDB");
-        bincode::encode_into_std_write(&*data, &mut self.file, CFG)
-            .expect("Failed to write the new database file to DumbDb");
-        self.file.flush().expect("Failed to flush file");
+        // A poisoned lock means a panic in the middle of a transaction. The
+        // journal still holds everything committed before that.
+        let Ok(data) = self.data.get_mut() else {
+            return;
+        };
+        if let Err(e) = compact(&self.path, data) {
+            tracing::error!("Failed to compact DumbDb: {e}");
+        }
     }
 }
 
 impl ExecDb for DumbDb {
     fn get_schema_version(&self) -> u64 {
-        panic!("will remove this")
+        self.inner.data.read().unwrap().schema_version
     }
 
     fn reset(&self) {
-        panic!("will remove this")
+        let mut data = self.inner.data.write().unwrap();
+        data.reset();
+        let file = compact(&self.inner.path, &data).expect("Failed to reset DumbDb file");
+        *self.inner.file.lock().unwrap() = file;
     }
 
     fn begin_read<'r>(&'r self) -> Box<dyn super::DbReader + 'r> {
@@ -125,6 +384,55 @@ impl ExecDb for DumbDb {
     }
 
     fn begin_write<'w>(&'w self) -> Box<dyn super::DbWriter + 'w> {
-        Box::new(Writer(self.inner.data.write().unwrap()))
+        Box::new(Writer {
+            data: self.inner.data.write().unwrap(),
+            file: &self.inner.file,
+            ops: vec![],
+        })
+    }
+}
+
+/// Write transaction that appends its changes to the journal on commit.
+struct Writer<'w> {
+    data: RwLockWriteGuard<'w, DbInner>,
+    file: &'w Mutex<File>,
+    ops: Vec<WriteOp>,
+}
+
+impl DbWriter for Writer<'_> {
+    fn set_build_info(&mut self, hash: BuildHash, info: BuildInfo) {
+        self.ops.push(WriteOp::SetBuild(hash, info));
+    }
+
+    fn set_file_info(&mut self, path: &Path, info: FileInfo) {
+        self.ops.push(WriteOp::SetFile(PathBuf::from(path), info));
+    }
+
+    fn invalidate_build(&mut self, hash: BuildHash) {
+        self.ops.push(WriteOp::InvalidateBuild(hash));
+    }
+
+    fn invalidate_file(&mut self, path: &Path) {
+        self.ops.push(WriteOp::InvalidateFile(PathBuf::from(path)));
+    }
+
+    fn commit(self: Box<Self>) {
+        let Writer {
+            mut data,
+            file,
+            ops,
+        } = *self;
+        if ops.is_empty() {
+            return;
+        }
+
+        // A single write, so that a crash leaves at most one damaged record
+        file.lock()
+            .unwrap()
+            .write_all(&encode_record(&ops))
+            .expect("Failed to append to DumbDb journal");
+        for op in ops {
+            data.apply(op);
+        }
     }
 }
@@ -0,0 +1,69 @@
+//! Garbage collection of stale database records.
+
+use std::collections::HashSet;
+
+use tracing::debug;
+
+use crate::{
+    db::ExecDb,
+    graph::{BuildGraph, hash_build},
+};
+
+/// The number of records removed by [`collect_garbage`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcStats {
+    /// The number of build records removed
+    pub builds: usize,
+    /// The number of file records removed
+    pub files: usize,
+}
+
+/// Remove the records that are not relevant to the given build graph.
+///
+/// Every change to a build command leaves the record of its previous
+/// [`BuildHash`](super::BuildHash) behind. This function removes the records of
+/// all builds that are not part of `graph`, as well as the records of files
+/// that the graph does not refer to.
+///
+/// Note that files recorded in the database are no longer known to be
+/// generated after their records are removed. If you want to delete these files
+/// from disk, run [`crate::clean::clean_dead`] first.
+pub fn collect_garbage(db: &dyn ExecDb, graph: &BuildGraph) -> GcStats {
+    let live_builds: HashSet<_> = graph
+        .nodes()
+        .map(|(_, build)| hash_build(build, graph))
+        .collect();
+
+    let txn = db.begin_read();
+    let dead_builds: Vec<_> = txn
+        .builds()
+        .map(|(hash, _)| hash)
+        .filter(|hash| !live_builds.contains(hash))
+        .collect();
+    let dead_files: Vec<_> = txn
+        .files()
+        .map(|(path, _)| path)
+        .filter(|path| graph.lookup_fileid(path).is_none())
+        .collect();
+    drop(txn);
+
+    debug!(
+        builds = dead_builds.len(),
+        files = dead_files.len(),
+        "Removing stale database records"
+    );
+
+    let mut txn = db.begin_write();
+    for &hash in &dead_builds {
+        txn.invalidate_build(hash);
+    }
+    for path in &dead_files {
+        txn.invalidate_file(path);
+    }
+    txn.commit();
+
+    GcStats {
+        builds: dead_builds.len(),
+        files: dead_files.len(),
+    }
+}
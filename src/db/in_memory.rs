@@ -6,10 +6,12 @@ use std::{
     sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
 };
 
-use crate::db::{BuildHash, BuildInfo, DbReader, ExecDb, FileInfo};
+use crate::db::{BuildHash, BuildInfo, DbReader, ExecDb, FileInfo, SCHEMA_VERSION};
 
 use super::DbWriter;
 
+#[cfg(feature = "bincode")]
+use crate::db::BuildInfoV1;
 #[cfg(feature = "bincode")]
 use bincode::{Decode, Encode};
 
@@ -21,11 +23,7 @@ pub struct InMemoryDb {
 impl InMemoryDb {
     pub fn new() -> Self {
         Self {
-            inner: Arc::new(RwLock::new(DbInner {
-                schema_version: 1,
-                build_info: HashMap::new(),
-                file_info: HashMap::new(),
-            })),
+            inner: Arc::new(RwLock::new(DbInner::default())),
         }
     }
 }
@@ -36,17 +34,116 @@ impl Default for InMemoryDb {
     }
 }
 
-#[derive(Default)]
 #[cfg_attr(feature = "bincode", derive(Encode, Decode))]
 pub(super) struct DbInner {
-    schema_version: u64,
+    pub(super) schema_version: u64,
     build_info: HashMap<BuildHash, BuildInfo>,
     file_info: HashMap<PathBuf, FileInfo>,
 }
 
+impl Default for DbInner {
+    fn default() -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            build_info: HashMap::new(),
+            file_info: HashMap::new(),
+        }
+    }
+}
+
+impl DbInner {
+    pub(super) fn reset(&mut self) {
+        self.schema_version = SCHEMA_VERSION;
+        self.build_info.clear();
+        self.file_info.clear();
+    }
+
+    pub(super) fn apply(&mut self, op: WriteOp) {
+        match op {
+            WriteOp::SetBuild(hash, info) => {
+                self.build_info.insert(hash, info);
+            }
+            WriteOp::InvalidateBuild(hash) => {
+                self.build_info.remove(&hash);
+            }
+            WriteOp::SetFile(path, info) => {
+                self.file_info.insert(path, info);
+            }
+            WriteOp::InvalidateFile(path) => {
+                self.file_info.remove(&path);
+            }
+        }
+    }
+}
+
+/// The layout of [`DbInner`] in schema version 1, for upgrading stored data.
+#[cfg(feature = "bincode")]
+#[derive(Decode)]
+pub(super) struct DbInnerV1 {
+    _schema_version: u64,
+    build_info: HashMap<BuildHash, BuildInfoV1>,
+    file_info: HashMap<PathBuf, FileInfo>,
+}
+
+#[cfg(feature = "bincode")]
+impl From<DbInnerV1> for DbInner {
+    fn from(data: DbInnerV1) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            build_info: data
+                .build_info
+                .into_iter()
+                .map(|(hash, info)| (hash, info.into()))
+                .collect(),
+            file_info: data.file_info,
+        }
+    }
+}
+
+/// The layout of [`WriteOp`] in schema version 1, for upgrading stored data.
+#[cfg(feature = "bincode")]
+#[derive(Decode)]
+pub(super) enum WriteOpV1 {
+    SetBuild(BuildHash, BuildInfoV1),
+    InvalidateBuild(BuildHash),
+    SetFile(PathBuf, FileInfo),
+    InvalidateFile(PathBuf),
+}
+
+#[cfg(feature = "bincode")]
+impl From<WriteOpV1> for WriteOp {
+    fn from(op: WriteOpV1) -> Self {
+        match op {
+            WriteOpV1::SetBuild(hash, info) => WriteOp::SetBuild(hash, info.into()),
+            WriteOpV1::InvalidateBuild(hash) => WriteOp::InvalidateBuild(hash),
+            WriteOpV1::SetFile(path, info) => WriteOp::SetFile(path, info),
+            WriteOpV1::InvalidateFile(path) => WriteOp::InvalidateFile(path),
+        }
+    }
+}
+
+/// A single change made in a write transaction.
+#[cfg_attr(feature = "bincode", derive(Encode, Decode))]
+pub(super) enum WriteOp {
+    SetBuild(BuildHash, BuildInfo),
+    InvalidateBuild(BuildHash),
+    SetFile(PathBuf, FileInfo),
+    InvalidateFile(PathBuf),
+}
+
 pub struct Reader<'r>(pub(super) RwLockReadGuard<'r, DbInner>);
 
-pub struct Writer<'w>(pub(super) RwLockWriteGuard<'w, DbInner>);
+/// Write transaction. Changes are buffered and only applied on commit.
+pub struct Writer<'w> {
+    data: RwLockWriteGuard<'w, DbInner>,
+    ops: Vec<WriteOp>,
+}
+
+impl<'w> Writer<'w> {
+    pub(super) fn new(data: RwLockWriteGuard<'w, DbInner>) -> Self {
+        Self { data, ops: vec![] }
+    }
+}
 
 impl ExecDb for InMemoryDb {
     fn get_schema_version(&self) -> u64 {
@@ -54,9 +151,7 @@ impl ExecDb for InMemoryDb {
     }
 
     fn reset(&self) {
-        let mut inner = self.inner.write().unwrap();
-        inner.build_info.clear();
-        inner.file_info.clear();
+        self.inner.write().unwrap().reset();
     }
 
     fn begin_read<'r>(&'r self) -> Box<dyn super::DbReader + 'r> {
@@ -64,7 +159,7 @@ impl ExecDb for InMemoryDb {
     }
 
     fn begin_write<'w>(&'w self) -> Box<dyn DbWriter + 'w> {
-        Box::new(Writer(self.inner.write().unwrap()))
+        Box::new(Writer::new(self.inner.write().unwrap()))
     }
 }
 
@@ -76,26 +171,47 @@ impl<'r> DbReader for Reader<'r> {
     fn get_file_info(&self, path: &std::path::Path) -> Option<FileInfo> {
         self.0.file_info.get(path).cloned()
     }
+
+    fn builds(&self) -> Box<dyn Iterator<Item = (BuildHash, BuildInfo)> + '_> {
+        Box::new(
+            self.0
+                .build_info
+                .iter()
+                .map(|(hash, info)| (*hash, info.clone())),
+        )
+    }
+
+    fn files(&self) -> Box<dyn Iterator<Item = (PathBuf, FileInfo)> + '_> {
+        Box::new(
+            self.0
+                .file_info
+                .iter()
+                .map(|(path, info)| (path.clone(), info.clone())),
+        )
+    }
 }
 
 impl<'w> DbWriter for Writer<'w> {
     fn set_build_info(&mut self, hash: BuildHash, info: BuildInfo) {
-        self.0.build_info.insert(hash, info);
+        self.ops.push(WriteOp::SetBuild(hash, info));
     }
 
     fn set_file_info(&mut self, path: &Path, info: FileInfo) {
-        self.0.file_info.insert(path.into(), info);
+        self.ops.push(WriteOp::SetFile(path.into(), info));
     }
 
     fn invalidate_build(&mut self, hash: BuildHash) {
-        self.0.build_info.remove(&hash);
+        self.ops.push(WriteOp::InvalidateBuild(hash));
     }
 
     fn invalidate_file(&mut self, path: &std::path::Path) {
-        self.0.file_info.remove(path);
+        self.ops.push(WriteOp::InvalidateFile(path.into()));
     }
 
     fn commit(self: Box<Self>) {
-        // No-op for in-memory DB
+        let Writer { mut data, ops } = *self;
+        for op in ops {
+            data.apply(op);
+        }
     }
 }
@@ -0,0 +1,271 @@
+//! Backend-neutral export and import of database records
+//!
+//! An export holds all [`BuildInfo`] and [`FileInfo`] records of a database,
+//! so that a database created on one machine can seed another one that uses a
+//! different backend. Exports are streams of records, either as JSON lines or
+//! as consecutive [`postcard`] values. Both start with a header recording the
+//! [`SCHEMA_VERSION`] they were written with. Exports of older schema versions
+//! are upgraded on import, e.g. builds of version 1 get an empty history.
+//!
+//! Paths can be rewritten on import with [`PathRewrite`] for checkouts at a
+//! different location. Note that build hashes include the paths of outputs and
+//! inputs, so they only stay valid if the graph hashes paths relative to a
+//! root, see [`GraphBuilder::set_root`](crate::graph::GraphBuilder::set_root).
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::{BuildHash, BuildInfo, BuildInfoV1, ExecDb, FileInfo, SCHEMA_VERSION};
+
+/// The version of the export format itself.
+pub const FORMAT_VERSION: u64 = 1;
+
+/// The encoding of an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One JSON object per line
+    JsonLines,
+    /// Consecutive postcard values
+    Postcard,
+}
+
+/// Replace the leading `from` components of a path with `to`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathRewrite {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+impl PathRewrite {
+    pub fn new(from: impl Into<PathBuf>, to: impl Into<PathBuf>) -> Self {
+        Self {
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+
+    /// Parse a rewrite written as `FROM=TO`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (from, to) = s.split_once('=')?;
+        Some(Self::new(from, to))
+    }
+}
+
+/// The number of records exported or imported.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PortableStats {
+    /// The number of build records
+    pub builds: usize,
+    /// The number of file records
+    pub files: usize,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PortableError {
+    #[error("Failed to read or write the export: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Malformed JSON record: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Malformed postcard record: {0}")]
+    Postcard(#[from] postcard::Error),
+    #[error("The export does not start with a header")]
+    MissingHeader,
+    #[error("Unsupported export format version {0}")]
+    UnsupportedFormat(u64),
+    #[error(
+        "The export has schema version {found}, but only versions 1 to {expected} are supported"
+    )]
+    SchemaMismatch { found: u64, expected: u64 },
+}
+
+#[derive(Serialize, Deserialize)]
+enum Record {
+    Header {
+        format_version: u64,
+        schema_version: u64,
+    },
+    Build(BuildHash, BuildInfo),
+    File(PathBuf, FileInfo),
+}
+
+/// The records of schema version 1, whose builds have no history. The
+/// encoding of the other records is unchanged.
+#[derive(Deserialize)]
+enum RecordV1 {
+    Header {
+        format_version: u64,
+        schema_version: u64,
+    },
+    Build(BuildHash, BuildInfoV1),
+    File(PathBuf, FileInfo),
+}
+
+impl From<RecordV1> for Record {
+    fn from(record: RecordV1) -> Self {
+        match record {
+            RecordV1::Header {
+                format_version,
+                schema_version,
+            } => Record::Header {
+                format_version,
+                schema_version,
+            },
+            RecordV1::Build(hash, info) => Record::Build(hash, info.into()),
+            RecordV1::File(path, info) => Record::File(path, info),
+        }
+    }
+}
+
+/// Write all records of `db` to `out`.
+pub fn export(
+    db: &dyn ExecDb,
+    format: Format,
+    out: &mut dyn Write,
+) -> Result<PortableStats, PortableError> {
+    let mut write = |record: &Record| -> Result<(), PortableError> {
+        match format {
+            Format::JsonLines => {
+                serde_json::to_writer(&mut *out, record)?;
+                out.write_all(b"\n")?;
+            }
+            Format::Postcard => {
+                postcard::to_io(record, &mut *out)?;
+            }
+        }
+        Ok(())
+    };
+
+    write(&Record::Header {
+        format_version: FORMAT_VERSION,
+        schema_version: SCHEMA_VERSION,
+    })?;
+    let mut stats = PortableStats::default();
+    let rd = db.begin_read();
+    for (hash, info) in rd.builds() {
+        write(&Record::Build(hash, info))?;
+        stats.builds += 1;
+    }
+    for (path, info) in rd.files() {
+        write(&Record::File(path, info))?;
+        stats.files += 1;
+    }
+    out.flush()?;
+    Ok(stats)
+}
+
+/// Read an export from `input` and write its records into `db`, applying the
+/// first matching rewrite to each path.
+///
+/// Existing records with the same keys are overwritten. The whole export is
+/// decoded before anything is written, so a malformed export leaves `db`
+/// untouched. Exports of a newer schema version are rejected.
+pub fn import(
+    db: &dyn ExecDb,
+    format: Format,
+    input: &mut dyn Read,
+    rewrites: &[PathRewrite],
+) -> Result<PortableStats, PortableError> {
+    let records = match format {
+        Format::JsonLines => read_json_lines(input)?,
+        Format::Postcard => read_postcard(input)?,
+    };
+
+    // Concatenated exports are fine, as long as every part is compatible,
+    // which the readers check at each header
+    if !matches!(records.first(), Some(Record::Header { .. })) {
+        return Err(PortableError::MissingHeader);
+    }
+
+    let rewrite = |path: PathBuf| -> PathBuf {
+        for rw in rewrites {
+            if let Ok(rest) = path.strip_prefix(&rw.from) {
+                return rw.to.join(rest);
+            }
+        }
+        path
+    };
+
+    let mut stats = PortableStats::default();
+    let mut txn = db.begin_write();
+    for record in records {
+        match record {
+            Record::Header { .. } => {}
+            Record::Build(hash, mut info) => {
+                info.additional_inputs = info.additional_inputs.into_iter().map(rewrite).collect();
+                txn.set_build_info(hash, info);
+                stats.builds += 1;
+            }
+            Record::File(path, info) => {
+                txn.set_file_info(&rewrite(path), info);
+                stats.files += 1;
+            }
+        }
+    }
+    txn.commit();
+    Ok(stats)
+}
+
+/// Check a record for a header, returning the schema version of the records
+/// that follow it.
+fn check_header(record: &Record, schema: u64) -> Result<u64, PortableError> {
+    let &Record::Header {
+        format_version,
+        schema_version,
+    } = record
+    else {
+        return Ok(schema);
+    };
+    if format_version != FORMAT_VERSION {
+        return Err(PortableError::UnsupportedFormat(format_version));
+    }
+    if !(1..=SCHEMA_VERSION).contains(&schema_version) {
+        return Err(PortableError::SchemaMismatch {
+            found: schema_version,
+            expected: SCHEMA_VERSION,
+        });
+    }
+    Ok(schema_version)
+}
+
+fn read_json_lines(input: &mut dyn Read) -> Result<Vec<Record>, PortableError> {
+    let mut records = vec![];
+    let mut schema = SCHEMA_VERSION;
+    for line in BufReader::new(input).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = match schema {
+            1 => serde_json::from_str::<RecordV1>(&line)?.into(),
+            _ => serde_json::from_str(&line)?,
+        };
+        schema = check_header(&record, schema)?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+fn read_postcard(input: &mut dyn Read) -> Result<Vec<Record>, PortableError> {
+    let mut data = vec![];
+    input.read_to_end(&mut data)?;
+    let mut rest = data.as_slice();
+    let mut records = vec![];
+    let mut schema = SCHEMA_VERSION;
+    while !rest.is_empty() {
+        let (record, tail) = match schema {
+            1 => {
+                let (record, tail) = postcard::take_from_bytes::<RecordV1>(rest)?;
+                (record.into(), tail)
+            }
+            _ => postcard::take_from_bytes(rest)?,
+        };
+        schema = check_header(&record, schema)?;
+        records.push(record);
+        rest = tail;
+    }
+    Ok(records)
+}
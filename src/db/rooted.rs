@@ -0,0 +1,162 @@
+//! Storing paths relative to a root directory
+
+use std::path::{Path, PathBuf};
+
+use crate::db::{BuildHash, BuildInfo, DbReader, DbWriter, ExecDb, FileInfo};
+
+/// An [`ExecDb`] wrapper that stores paths relative to a root directory, so
+/// that the database stays valid when the directory is moved or copied.
+///
+/// Paths under the root are stored relative to it, and resolved against the
+/// current root when read back. This applies to file records and the
+/// additional inputs of builds. Paths outside the root are stored unchanged.
+/// Relative paths are treated as relative to the root, so the graph should
+/// use absolute paths.
+///
+/// Build hashes include the paths of outputs and inputs, so they change when
+/// the root moves as well. Set the same root on the graph with
+/// [`GraphBuilder::set_root`](crate::graph::GraphBuilder::set_root) to hash
+/// these paths relative to it. Absolute paths in command arguments still
+/// change the hashes.
+pub struct RootedDb<D> {
+    inner: D,
+    root: PathBuf,
+}
+
+impl<D: ExecDb> RootedDb<D> {
+    pub fn new(inner: D, root: impl Into<PathBuf>) -> Self {
+        Self {
+            inner,
+            root: root.into(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+/// Turn a path into the key stored in the database.
+fn to_stored<'a>(root: &Path, path: &'a Path) -> &'a Path {
+    path.strip_prefix(root).unwrap_or(path)
+}
+
+/// Turn a key stored in the database back into a path.
+fn from_stored(root: &Path, path: PathBuf) -> PathBuf {
+    if path.is_relative() {
+        root.join(path)
+    } else {
+        path
+    }
+}
+
+impl<D: ExecDb> ExecDb for RootedDb<D> {
+    fn get_schema_version(&self) -> u64 {
+        self.inner.get_schema_version()
+    }
+
+    fn reset(&self) {
+        self.inner.reset()
+    }
+
+    fn begin_read<'r>(&'r self) -> Box<dyn DbReader + 'r> {
+        Box::new(Reader {
+            inner: self.inner.begin_read(),
+            root: &self.root,
+        })
+    }
+
+    fn begin_write<'w>(&'w self) -> Box<dyn DbWriter + 'w> {
+        Box::new(Writer {
+            inner: self.inner.begin_write(),
+            root: &self.root,
+        })
+    }
+}
+
+struct Reader<'r> {
+    inner: Box<dyn DbReader + 'r>,
+    root: &'r Path,
+}
+
+impl Reader<'_> {
+    fn resolve_build(&self, mut info: BuildInfo) -> BuildInfo {
+        for path in &mut info.additional_inputs {
+            *path = from_stored(self.root, std::mem::take(path));
+        }
+        info
+    }
+}
+
+impl DbReader for Reader<'_> {
+    fn get_build_info(&self, hash: BuildHash) -> Option<BuildInfo> {
+        let info = self.inner.get_build_info(hash)?;
+        Some(self.resolve_build(info))
+    }
+
+    fn get_file_info(&self, path: &Path) -> Option<FileInfo> {
+        self.inner.get_file_info(to_stored(self.root, path))
+    }
+
+    fn builds(&self) -> Box<dyn Iterator<Item = (BuildHash, BuildInfo)> + '_> {
+        Box::new(
+            self.inner
+                .builds()
+                .map(|(hash, info)| (hash, self.resolve_build(info))),
+        )
+    }
+
+    fn files(&self) -> Box<dyn Iterator<Item = (PathBuf, FileInfo)> + '_> {
+        Box::new(
+            self.inner
+                .files()
+                .map(|(path, info)| (from_stored(self.root, path), info)),
+        )
+    }
+
+    fn files_generated_by(&self, hash: BuildHash) -> Box<dyn Iterator<Item = PathBuf> + '_> {
+        Box::new(
+            self.inner
+                .files_generated_by(hash)
+                .map(|path| from_stored(self.root, path)),
+        )
+    }
+}
+
+struct Writer<'w> {
+    inner: Box<dyn DbWriter + 'w>,
+    root: &'w Path,
+}
+
+impl DbWriter for Writer<'_> {
+    fn set_build_info(&mut self, hash: BuildHash, mut info: BuildInfo) {
+        for path in &mut info.additional_inputs {
+            *path = to_stored(self.root, path).to_owned();
+        }
+        self.inner.set_build_info(hash, info);
+    }
+
+    fn invalidate_build(&mut self, hash: BuildHash) {
+        self.inner.invalidate_build(hash);
+    }
+
+    fn set_file_info(&mut self, path: &Path, info: FileInfo) {
+        self.inner.set_file_info(to_stored(self.root, path), info);
+    }
+
+    fn invalidate_file(&mut self, path: &Path) {
+        self.inner.invalidate_file(to_stored(self.root, path));
+    }
+
+    fn commit(self: Box<Self>) {
+        self.inner.commit();
+    }
+}
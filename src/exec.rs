@@ -3,34 +3,79 @@
 use std::{
     any::Any,
     collections::HashMap,
+    error::Error,
+    panic::{self, AssertUnwindSafe},
     path::PathBuf,
     sync::{Arc, mpsc},
+    time::{Duration, Instant},
 };
 
 use indexmap::IndexSet;
 use petgraph::visit::Walker;
 use rayon::Scope;
-use tracing::{debug, info, warn};
+use tracing::{debug, error, info, warn};
 
 use crate::{
-    db::{BuildHash, BuildInfo, ExecDb, InputHash},
-    graph::{BuildGraph, BuildId, BuildNode, FileId, hash_build, hash_input_set},
+    db::{BuildHash, BuildInfo, DbReader, DbWriter, ExecDb, ExecRecord, InputHash},
+    graph::{BuildGraph, BuildId, BuildMethod, BuildNode, FileId, hash_build, hash_input_set},
     progress::{Progress, ProgressConfig, ProgressStatus},
-    world::{LOCAL_WORLD, World},
+    world::{LOCAL_WORLD, OutputSink, World},
 };
 
 #[derive(Debug)]
 pub struct ExecConfig {
     /// The maximum amount of actions that can execute in parallel.
     pub parallelism: usize,
+    /// What to do when a build succeeds without producing all of its
+    /// declared outputs.
+    pub missing_output: MissingOutputPolicy,
+    /// Whether to delete the declared outputs of a build that has failed, so
+    /// that no partially written files are left behind. This includes builds
+    /// whose execution returned an error or panicked.
+    ///
+    /// Running builds are never cancelled. When a build fails, the ones still
+    /// running finish before [`Executor::run`] returns, and their outputs are
+    /// removed if they fail too. Nothing can be removed if the process is
+    /// killed, but the outputs written since are newer than their records, so
+    /// their builds run again.
+    pub remove_failed_outputs: bool,
+    /// The maximum number of finished builds whose database updates are
+    /// written together in a single transaction. `1` writes each build in its
+    /// own transaction.
+    pub db_batch_size: usize,
+    /// The maximum time the database update of a finished build may wait for
+    /// others to be batched with.
+    pub db_batch_delay: Duration,
+    /// The number of most recent executions kept in the
+    /// [history](BuildInfo::history) of each build. `0` disables the history.
+    pub history_len: usize,
 }
 
 impl Default for ExecConfig {
     fn default() -> Self {
-        Self { parallelism: 1 }
+        Self {
+            parallelism: 1,
+            missing_output: MissingOutputPolicy::default(),
+            remove_failed_outputs: false,
+            db_batch_size: 256,
+            db_batch_delay: Duration::from_millis(100),
+            history_len: 16,
+        }
     }
 }
 
+/// The action to take when a successful build has not created some of its
+/// declared outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissingOutputPolicy {
+    /// Fail the build node. This prevents the build from being recorded as
+    /// up-to-date and then rebuilt on every subsequent run.
+    #[default]
+    Error,
+    /// Log a warning and treat the build as succeeded, like ninja does.
+    Warn,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BuildStatusKind {
     /// The build hasn't been checked yet
@@ -58,11 +103,51 @@ impl BuildStatusKind {
         )
     }
 
-    fn is_successful(self) -> bool {
+    /// Whether the build has finished and its dependents can run.
+    pub fn is_successful(self) -> bool {
         matches!(self, BuildStatusKind::UpToDate | BuildStatusKind::Succeeded)
     }
 }
 
+/// Why a build has failed, as reported to [`Progress::build_finished`].
+///
+/// Fields that do not apply to a failure are `None`. A build whose command
+/// exited unsuccessfully only has the exit status, while e.g. a build that
+/// could not start because of a missing input only has that input.
+#[derive(Debug, Default)]
+pub struct FailureDetail {
+    /// The exit code of the build command, if it exited normally
+    pub exit_code: Option<i32>,
+    /// The signal that terminated the build command
+    pub signal: Option<i32>,
+    /// The input file that does not exist and is not generated by any build
+    pub missing_input: Option<PathBuf>,
+    /// The error returned by a callback, or other reasons the build has failed
+    pub error: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl std::fmt::Display for FailureDetail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        if let Some(path) = &self.missing_input {
+            parts.push(format!("missing input {path:?}"));
+        }
+        if let Some(code) = self.exit_code {
+            parts.push(format!("exited with code {code}"));
+        }
+        if let Some(signal) = self.signal {
+            parts.push(format!("terminated by signal {signal}"));
+        }
+        if let Some(e) = &self.error {
+            parts.push(e.to_string());
+        }
+        if parts.is_empty() {
+            parts.push("unknown reason".to_owned());
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
 #[derive(Debug, Clone)]
 struct BuildStatus {
     kind: BuildStatusKind,
@@ -73,7 +158,6 @@ struct BuildStatus {
 
 /// Some internal shared state that is passed to each build task.
 struct SharedState<'a> {
-    #[allow(unused)] // might be used later
     cfg: &'a ExecConfig,
     graph: &'a BuildGraph,
     world: &'a dyn World,
@@ -126,6 +210,11 @@ pub struct Executor<'a> {
     /// Number of nodes that has failed
     failed: usize,
 
+    /// Database updates of finished builds that are not yet written
+    db_batch: Vec<DbUpdate>,
+    /// When the oldest update in [`Self::db_batch`] was queued
+    db_batch_since: Option<Instant>,
+
     build_started: bool,
 }
 
@@ -185,6 +274,9 @@ impl<'a> Executor<'a> {
             finished: 0,
             failed: 0,
 
+            db_batch: vec![],
+            db_batch_since: None,
+
             build_started: false,
         }
     }
@@ -239,10 +331,13 @@ impl<'a> Executor<'a> {
                     BuildStatusKind::UpToDate | BuildStatusKind::Succeeded => {
                         self.finished -= 1;
                     }
-                    BuildStatusKind::Failed | BuildStatusKind::Skipped => {
+                    BuildStatusKind::Failed => {
                         self.finished -= 1;
                         self.failed -= 1;
                     }
+                    BuildStatusKind::Skipped => {
+                        self.finished -= 1;
+                    }
                 }
             }
         }
@@ -262,18 +357,41 @@ impl<'a> Executor<'a> {
 
         let state = self.state.clone();
         let (tx, mut rx) = mpsc::channel::<BuildNodeResult>();
-        state
-            .pool
-            .in_place_scope(|pool| self.run_inner(pool, tx, &mut rx))?;
-        // Gracefully retain the receiver until all senders are dropped, so that
-        // threads in the pool can finish sending messages.
-        // TODO: collect and process any remaining messages
-        drop(rx);
-
-        // Finish progress
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            state
+                .pool
+                .in_place_scope(|pool| self.run_inner(pool, tx, &mut rx))
+        }));
+
+        // The scope waits for all builds, including those still running when
+        // the run loop exits early, so their results are all in the channel by
+        // now. Their database updates are written together with the pending
+        // ones, so that none are lost on errors or panics.
+        let mut result = match result {
+            Ok(result) => result,
+            Err(payload) => {
+                for msg in rx.try_iter() {
+                    self.db_batch.extend(msg.db_update);
+                }
+                self.flush_db();
+                self.state.progress.finish();
+                panic::resume_unwind(payload);
+            }
+        };
+        for msg in rx.try_iter() {
+            debug!(?msg, "Build finished after the run loop");
+            let finished = self.build_finished(msg);
+            if result.is_ok() {
+                result = finished;
+            }
+        }
+        self.flush_db();
+
+        // Finish progress, even if the build stopped on an error, so that
+        // reporters can close what they have started
         self.state.progress.finish();
 
-        Ok(())
+        result
     }
 
     fn run_inner<'scope>(
@@ -321,21 +439,73 @@ impl<'a> Executor<'a> {
                 );
             }
 
-            // Wait for some build to finish
-            let msg = rx
-                .recv()
-                .expect("We have a tx in hand, so rx should not close");
-            debug!(?msg, "Build finished");
+            // Wait for some build to finish, but no longer than the pending
+            // database updates may wait
+            let msg = match self.db_batch_since {
+                None => rx
+                    .recv()
+                    .expect("We have a tx in hand, so rx should not close"),
+                Some(since) => {
+                    let timeout = (since + self.state.cfg.db_batch_delay)
+                        .saturating_duration_since(Instant::now());
+                    match rx.recv_timeout(timeout) {
+                        Ok(msg) => msg,
+                        Err(mpsc::RecvTimeoutError::Timeout) => {
+                            self.flush_db();
+                            continue;
+                        }
+                        Err(mpsc::RecvTimeoutError::Disconnected) => {
+                            unreachable!("We have a tx in hand, so rx should not close")
+                        }
+                    }
+                }
+            };
 
-            // Process finished build
+            // Process the finished build, and any others that finished in the
+            // meantime
+            debug!(?msg, "Build finished");
             self.build_finished(msg)?;
+            while let Ok(msg) = rx.try_recv() {
+                debug!(?msg, "Build finished");
+                self.build_finished(msg)?;
+            }
+
+            if self.db_batch.len() >= self.state.cfg.db_batch_size
+                || self
+                    .db_batch_since
+                    .is_some_and(|since| since.elapsed() >= self.state.cfg.db_batch_delay)
+            {
+                self.flush_db();
+            }
         }
 
         Ok(())
     }
 
+    /// Write all pending database updates in a single transaction.
+    fn flush_db(&mut self) {
+        self.db_batch_since = None;
+        if self.db_batch.is_empty() {
+            return;
+        }
+
+        debug!(count = self.db_batch.len(), "Writing database updates");
+        let graph = self.state.graph;
+        let mut txn = self.state.db.begin_write();
+        for update in self.db_batch.drain(..) {
+            let build = graph.lookup_build(update.id).expect("Node should exist");
+            write_build(&mut *txn, graph, build, update.build_hash, update.info);
+        }
+        txn.commit();
+    }
+
     fn build_finished(&mut self, msg: BuildNodeResult) -> Result<(), std::io::Error> {
         let id = msg.id;
+        if let Some(update) = msg.db_update {
+            self.db_batch.push(update);
+            self.db_batch_since.get_or_insert_with(Instant::now);
+        }
+        let failure = msg.failure;
         let stat = match msg.result {
             Ok(res) => res,
             Err(e) => {
@@ -363,6 +533,7 @@ impl<'a> Executor<'a> {
         }
         build.kind = stat;
 
+        let mut skipped = vec![];
         match stat {
             BuildStatusKind::Fresh => panic!("Build cannot be fresh after running"),
             BuildStatusKind::Started => panic!("Build cannot be started after running"),
@@ -395,8 +566,9 @@ impl<'a> Executor<'a> {
             BuildStatusKind::Failed | BuildStatusKind::Skipped => {
                 self.failed += 1;
                 // Mark skipped for all transitive dependents
-                let dfs = petgraph::visit::Dfs::new(&self.state.graph.graph, id);
-                for node in dfs.iter(&self.state.graph.graph).skip(1) {
+                let dependents = petgraph::visit::Reversed(&self.state.graph.graph);
+                let dfs = petgraph::visit::Dfs::new(dependents, id);
+                for node in dfs.iter(dependents).skip(1) {
                     let Some(dep) = self.builds.get_mut(&node) else {
                         // The node is not tracked, so we don't care about it
                         continue;
@@ -406,16 +578,24 @@ impl<'a> Executor<'a> {
                     }
                     dep.kind = BuildStatusKind::Skipped;
                     self.finished += 1;
-                    self.failed += 1;
+                    skipped.push(node);
                 }
             }
         }
 
         // Report node completion with final progress status
         let status = self.status();
-        self.state
-            .progress
-            .build_finished(self.state.graph, id, stat.is_successful(), &status);
+        let progress = self.state.progress;
+        progress.build_finished(self.state.graph, id, stat, failure.as_ref(), &status);
+        for node in skipped {
+            progress.build_finished(
+                self.state.graph,
+                node,
+                BuildStatusKind::Skipped,
+                None,
+                &status,
+            );
+        }
 
         Ok(())
     }
@@ -449,7 +629,28 @@ impl<'a> Executor<'a> {
         self.builds.get_mut(&node).expect("Build should exist").kind = BuildStatusKind::Started;
         self.running += 1;
 
-        pool.spawn(move |_p| run_build(state, node, tx));
+        pool.spawn(move |_p| {
+            let report = tx.clone();
+            if let Err(payload) =
+                panic::catch_unwind(AssertUnwindSafe(|| run_build(state.clone(), node, tx)))
+            {
+                // The build may have stopped in the middle of writing its
+                // outputs
+                if state.cfg.remove_failed_outputs {
+                    let build = state.graph.lookup_build(node).expect("Node should exist");
+                    remove_outputs(state.world, state.graph, build);
+                }
+                // Wake up the run loop, which would otherwise wait for this
+                // build forever. The scope passes on the panic once it ends.
+                let _ = report.send(BuildNodeResult {
+                    id: node,
+                    result: Err(std::io::Error::other(format!("Build {node:?} panicked"))),
+                    failure: None,
+                    db_update: None,
+                });
+                panic::resume_unwind(payload);
+            }
+        });
     }
 }
 
@@ -458,6 +659,21 @@ struct BuildNodeResult {
     id: BuildId,
     /// The result of the build. Only `Err` if an error on our side fails it.
     result: std::io::Result<BuildStatusKind>,
+    /// Why the build has failed, if it has
+    failure: Option<FailureDetail>,
+    /// The change to the database caused by the build, if any
+    db_update: Option<DbUpdate>,
+}
+
+/// A change to the database from a finished build. These are written in
+/// batches by the executor, see [`ExecConfig::db_batch_size`].
+#[derive(Debug)]
+struct DbUpdate {
+    id: BuildId,
+    build_hash: BuildHash,
+    /// The new record of the build. If the build has failed, i.e. `last_end`
+    /// is not set, the records of its outputs are removed.
+    info: BuildInfo,
 }
 
 #[derive(Debug)]
@@ -468,21 +684,19 @@ enum NodeInputKind {
     CannotRead(PathBuf, std::io::Error),
 }
 
-/// Determine if the node is up-to-date by checking its associated files
+/// Determine if the node is up-to-date by checking its associated files.
+///
+/// `build_info` is the stored record of the build, read through `txn`.
 #[tracing::instrument(skip_all)]
 fn stat_node(
-    db: &dyn ExecDb,
+    txn: Box<dyn DbReader + '_>,
     world: &dyn World,
     graph: &BuildGraph,
     node: &BuildNode,
     build_hash: BuildHash,
     input_hash: InputHash,
+    build_info: Option<&BuildInfo>,
 ) -> NodeInputKind {
-    let txn = db.begin_read();
-
-    // Get metadata of build
-    let build_info = txn.get_build_info(build_hash);
-
     // Check if input files are up-to-date
     //
     // We need to check if any input file is:
@@ -491,7 +705,7 @@ fn stat_node(
     //
     // Input checking is done first is because missing inputs is a hard error,
     // while outdated inputs only means we need to rebuild.
-    let mtime_should_before = build_info.as_ref().map(|x| x.last_start);
+    let mtime_should_before = build_info.map(|x| x.last_start);
     for &file in &node.ins {
         let path = graph.lookup_path(file).expect("File should exist");
         if !world.exists(path) {
@@ -516,6 +730,10 @@ fn stat_node(
         debug!("Outdated: no build info for build {build_hash:?}");
         return NodeInputKind::Outdated; // Never built before
     };
+    if build_info.last_end.is_none() {
+        debug!("Outdated: last run of build {build_hash:?} did not succeed");
+        return NodeInputKind::Outdated;
+    }
     let mtime_should_before = build_info.last_start;
 
     // We need the build to run when any output is:
@@ -596,52 +814,104 @@ fn stat_node(
 
 #[tracing::instrument(skip_all)]
 fn write_build(
-    db: &dyn ExecDb,
+    txn: &mut dyn DbWriter,
     graph: &BuildGraph,
-    world: &dyn World,
     build: &BuildNode,
     build_hash: BuildHash,
-    input_hash: InputHash,
+    build_info: BuildInfo,
 ) {
-    let mut txn = db.begin_write();
-
-    let now = world.now();
-
-    // Write build info
-    let build_info = BuildInfo {
-        last_start: now,
-        last_end: None,
-        input_set_digest: input_hash,
-        additional_inputs: vec![], // TODO: detect such inputs
-    };
+    let last_end = build_info.last_end;
     txn.set_build_info(build_hash, build_info);
 
-    // Write info for outputs
+    // Write info for outputs, or forget them if the build has failed
     for &out in &build.outs {
         let path = graph.lookup_path(out).expect("File should exist");
-        let file_info = crate::db::FileInfo {
-            last_seen: now,
-            generated_by: build_hash,
-        };
-        txn.set_file_info(path, file_info);
+        match last_end {
+            Some(now) => {
+                let file_info = crate::db::FileInfo {
+                    last_seen: now,
+                    generated_by: build_hash,
+                };
+                txn.set_file_info(path, file_info);
+            }
+            None => txn.invalidate_file(path),
+        }
     }
-
-    txn.commit();
 }
 
-fn invalidate_build(db: &dyn ExecDb, graph: &BuildGraph, build: &BuildNode, build_hash: BuildHash) {
-    let mut txn = db.begin_write();
+/// Append an execution to the stored history of a build, keeping only the
+/// most recent `len` ones.
+fn push_history(mut history: Vec<ExecRecord>, record: ExecRecord, len: usize) -> Vec<ExecRecord> {
+    history.push(record);
+    let excess = history.len().saturating_sub(len);
+    history.drain(..excess);
+    history
+}
 
-    // Invalidate build info
-    txn.invalidate_build(build_hash);
+/// Create the output directories of a build before it runs.
+fn prepare_outputs(
+    world: &dyn World,
+    graph: &BuildGraph,
+    id: BuildId,
+    build: &BuildNode,
+) -> std::io::Result<()> {
+    // Phony builds never create anything
+    if let BuildMethod::Phony = build.command {
+        return Ok(());
+    }
+    world.prepare_outputs(graph, id)
+}
 
-    // Invalidate info for outputs
+/// Remove the declared outputs of a failed build.
+fn remove_outputs(world: &dyn World, graph: &BuildGraph, build: &BuildNode) {
+    if let BuildMethod::Phony = build.command {
+        return;
+    }
     for &out in &build.outs {
         let path = graph.lookup_path(out).expect("File should exist");
-        txn.invalidate_file(path);
+        if let Err(e) = world.remove(path) {
+            warn!("Failed to remove output {path:?} of failed build: {e}");
+        }
     }
+}
 
-    txn.commit();
+/// Check that a build reported as successful has created all its declared
+/// outputs. Returns a missing output if the build should be considered failed
+/// instead.
+fn verify_outputs(
+    cfg: &ExecConfig,
+    world: &dyn World,
+    graph: &BuildGraph,
+    build: &BuildNode,
+) -> Option<PathBuf> {
+    // Phony builds never create anything
+    if let BuildMethod::Phony = build.command {
+        return None;
+    }
+
+    let mut missing = None;
+    for &out in &build.outs {
+        let path = graph.lookup_path(out).expect("File should exist");
+        if world.exists(path) {
+            continue;
+        }
+        match cfg.missing_output {
+            MissingOutputPolicy::Error => {
+                error!(
+                    "Build `{}` succeeded but did not create its output {path:?}",
+                    build.human_readable()
+                );
+                missing.get_or_insert_with(|| path.to_owned());
+            }
+            MissingOutputPolicy::Warn => {
+                warn!(
+                    "Build `{}` succeeded but did not create its output {path:?}",
+                    build.human_readable()
+                );
+            }
+        }
+    }
+    missing
 }
 
 /// Runs the build node
@@ -657,33 +927,81 @@ fn run_build(state: Arc<SharedState<'_>>, id: BuildId, report: mpsc::Sender<Buil
     let build_id = hash_build(build, graph);
     let input_hash = hash_input_set(id, graph);
 
-    let node_stat = stat_node(db, state.world, graph, build, build_id, input_hash);
-
+    let txn = db.begin_read();
+    let build_info = txn.get_build_info(build_id);
+    let node_stat = stat_node(
+        txn,
+        state.world,
+        graph,
+        build,
+        build_id,
+        input_hash,
+        build_info.as_ref(),
+    );
+
+    let mut db_update = None;
+    let mut failure = None;
     let result_kind = match node_stat {
         NodeInputKind::UpToDate => Ok(BuildStatusKind::UpToDate),
         NodeInputKind::CannotRead(path_buf, error) => Err(std::io::Error::other(format!(
             "Cannot read input file {path_buf:?}: {error}"
         ))),
-        NodeInputKind::Missing(_id) => {
-            info!("Missing input file for build {id:?}, skipping");
-            Ok(BuildStatusKind::Failed) // TODO: report missing file
+        NodeInputKind::Missing(file) => {
+            let path = graph.lookup_path(file).expect("File should exist");
+            info!("Missing input file {path:?} for build {id:?}, skipping");
+            failure = Some(FailureDetail {
+                missing_input: Some(path.to_owned()),
+                ..Default::default()
+            });
+            Ok(BuildStatusKind::Failed)
         }
         NodeInputKind::Outdated => {
-            let build_result = state.world.execute(state.user_state, graph, id);
-            match &build_result {
-                Ok(BuildStatusKind::Succeeded) => {
-                    write_build(db, graph, state.world, build, build_id, input_hash);
-                }
-                Ok(BuildStatusKind::UpToDate) => {
-                    // This should not happen, but we allow it.
-                    warn!(
-                        "Build {:?} returned UpToDate when it was Outdated. This is unexpected.",
-                        id
-                    );
-                    write_build(db, graph, state.world, build, build_id, input_hash);
-                }
+            let start = state.world.now();
+            let output = |chunk: &[u8]| state.progress.stdout_line(graph, id, chunk);
+            let output = state
+                .progress
+                .captures_output()
+                .then_some(&output as OutputSink);
+            let outcome = prepare_outputs(state.world, graph, id, build)
+                .and_then(|_| state.world.execute(state.user_state, graph, id, output));
+            let end = state.world.now();
+            let (mut build_result, mut detail, peak_memory) = match outcome {
+                Ok(outcome) => (
+                    Ok(outcome.status),
+                    FailureDetail {
+                        exit_code: outcome.exit_code,
+                        signal: outcome.signal,
+                        missing_input: None,
+                        error: outcome.error,
+                    },
+                    outcome.peak_memory,
+                ),
+                Err(e) => (Err(e), FailureDetail::default(), None),
+            };
+            let exit_code = detail.exit_code;
+            if let Ok(BuildStatusKind::UpToDate) = build_result {
+                // This should not happen, but we allow it.
+                warn!(
+                    "Build {:?} returned UpToDate when it was Outdated. This is unexpected.",
+                    id
+                );
+            }
+            if let Ok(BuildStatusKind::Succeeded | BuildStatusKind::UpToDate) = build_result
+                && let Some(path) = verify_outputs(state.cfg, state.world, graph, build)
+            {
+                build_result = Ok(BuildStatusKind::Failed);
+                detail.error = Some(format!("Build did not create its output {path:?}").into());
+            }
+            let succeeded = match &build_result {
+                Ok(BuildStatusKind::Succeeded | BuildStatusKind::UpToDate) => true,
                 Ok(BuildStatusKind::Failed) | Err(_) => {
-                    invalidate_build(db, graph, build, build_id);
+                    if state.cfg.remove_failed_outputs {
+                        remove_outputs(state.world, graph, build);
+                    }
+                    if build_result.is_ok() {
+                        failure = Some(detail);
+                    }
+                    false
                 }
                 Ok(other) => {
                     panic!(
@@ -691,7 +1009,31 @@ fn run_build(state: Arc<SharedState<'_>>, id: BuildId, report: mpsc::Sender<Buil
                         id, other
                     );
                 }
-            }
+            };
+
+            let record = ExecRecord {
+                start,
+                duration: end.duration_since(start).unwrap_or_default(),
+                succeeded,
+                exit_code,
+                peak_memory,
+            };
+            let info = BuildInfo {
+                last_start: start,
+                last_end: succeeded.then_some(end),
+                input_set_digest: input_hash,
+                additional_inputs: vec![], // TODO: detect such inputs
+                history: push_history(
+                    build_info.map(|info| info.history).unwrap_or_default(),
+                    record,
+                    state.cfg.history_len,
+                ),
+            };
+            db_update = Some(DbUpdate {
+                id,
+                build_hash: build_id,
+                info,
+            });
             build_result
         }
     };
@@ -700,6 +1042,8 @@ fn run_build(state: Arc<SharedState<'_>>, id: BuildId, report: mpsc::Sender<Buil
         .send(BuildNodeResult {
             id,
             result: result_kind,
+            failure,
+            db_update,
         })
         .expect("Failed to send build result");
 }
@@ -24,6 +24,7 @@ pub struct BuildGraph {
     nodes: Vec<BuildNode>,
     files: IndexSet<PathBuf>,
     pub(crate) graph: DiGraphMap<BuildId, ()>,
+    root: Option<PathBuf>,
 }
 
 impl BuildGraph {
@@ -60,6 +61,12 @@ impl BuildGraph {
     pub fn node_count(&self) -> usize {
         self.nodes.len()
     }
+
+    /// The root directory that paths are hashed relative to, if any. See
+    /// [`GraphBuilder::set_root`].
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
 }
 
 /// The builder to build a [`BuildGraph`].
@@ -100,6 +107,15 @@ pub struct FileId(usize);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct BuildId(usize);
 
+impl BuildId {
+    /// The index of the build in its graph. It is only meaningful within the
+    /// graph the build belongs to; use [`hash_build`] to identify builds
+    /// across graphs.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
 impl GraphBuilder {
     /// Create a new, empty build graph.
     pub fn new() -> Self {
@@ -141,6 +157,15 @@ impl GraphBuilder {
         build_id
     }
 
+    /// Set the root directory of the build.
+    ///
+    /// Paths under the root are hashed relative to it in [`hash_build`] and
+    /// [`hash_input_set`], so that moving the whole directory does not change
+    /// the hashes. Use it together with [`crate::db::rooted::RootedDb`].
+    pub fn set_root(&mut self, root: impl Into<PathBuf>) {
+        self.graph.root = Some(root.into());
+    }
+
     /// Add a build dependency edge, where `dependent` relies on the finish of
     /// `dependency` to start.
     pub fn add_build_dep(&mut self, dependent: BuildId, dependency: BuildId) {
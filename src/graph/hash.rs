@@ -1,6 +1,6 @@
 //! Hashing identity of builds and their input sets.
 
-use std::hash::Hasher;
+use std::{hash::Hasher, path::Path};
 
 use xxhash_rust::xxh3::{Xxh3, xxh3_128};
 
@@ -13,14 +13,16 @@ use crate::{
 ///
 /// The hash is independent of the actual layout of the graph or the build,
 /// e.g. the [`FileId`]s used to represent files. However, it is still
-/// sensitive to the order of output files.
+/// sensitive to the order of output files. Paths under the
+/// [root](BuildGraph::root) of the graph are hashed relative to it.
 pub fn hash_build(node: &BuildNode, graph: &BuildGraph) -> BuildHash {
     let mut hasher = Xxh3::new();
 
     match &node.command {
         BuildMethod::SubCommand(build_command) => {
             hasher.write(b"subcmd\0");
-            hasher.write(build_command.executable.as_os_str().as_encoded_bytes());
+            let executable = relative_to_root(graph, &build_command.executable);
+            hasher.write(executable.as_os_str().as_encoded_bytes());
             hasher.write(&[0]);
             for arg in &build_command.args {
                 hasher.write(arg.as_encoded_bytes());
@@ -38,6 +40,7 @@ pub fn hash_build(node: &BuildNode, graph: &BuildGraph) -> BuildHash {
     hasher.write(b"out\0");
     for &file_id in &node.outs {
         let path = graph.lookup_path(file_id).expect("invalid FileId");
+        let path = relative_to_root(graph, path);
         hasher.write(path.as_os_str().as_encoded_bytes());
         hasher.write(&[0]);
     }
@@ -49,7 +52,8 @@ pub fn hash_build(node: &BuildNode, graph: &BuildGraph) -> BuildHash {
 /// Hash the input set of a build node.
 ///
 /// This hash is order-independent, to mitigate the difference layout of the
-/// graph between runs.
+/// graph between runs. Like [`hash_build`], paths are hashed relative to the
+/// root of the graph.
 pub fn hash_input_set(build_id: BuildId, graph: &BuildGraph) -> InputHash {
     let mut acc = Acc::default();
     let build = graph.lookup_build(build_id).expect("invalid BuildId");
@@ -57,6 +61,7 @@ pub fn hash_input_set(build_id: BuildId, graph: &BuildGraph) -> InputHash {
     // Fixed inputs
     for &file_id in &build.ins {
         let path = graph.lookup_path(file_id).expect("invalid FileId");
+        let path = relative_to_root(graph, path);
         let h = xxh3_128(path.as_os_str().as_encoded_bytes());
         acc.accumulate(h);
     }
@@ -66,6 +71,7 @@ pub fn hash_input_set(build_id: BuildId, graph: &BuildGraph) -> InputHash {
         let dep = graph.lookup_build(dep).expect("invalid BuildId");
         for &out in &dep.outs {
             let path = graph.lookup_path(out).expect("invalid FileId");
+            let path = relative_to_root(graph, path);
             let h = xxh3_128(path.as_os_str().as_encoded_bytes());
             acc.accumulate(h);
         }
@@ -74,6 +80,14 @@ pub fn hash_input_set(build_id: BuildId, graph: &BuildGraph) -> InputHash {
     InputHash(acc.finalize())
 }
 
+/// Strip the root of the graph from the path, if it is under it.
+fn relative_to_root<'a>(graph: &BuildGraph, path: &'a Path) -> &'a Path {
+    graph
+        .root()
+        .and_then(|root| path.strip_prefix(root).ok())
+        .unwrap_or(path)
+}
+
 /// The accumulator for collecting an order-independent hash of input files
 #[derive(Default)]
 struct Acc {
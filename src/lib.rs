@@ -1,6 +1,8 @@
+pub mod clean;
 pub mod db;
 pub mod exec;
 pub mod graph;
+pub mod lock;
 pub mod progress;
 pub mod shape;
 pub mod world;
@@ -0,0 +1,114 @@
+//! Locking a build directory against concurrent builds
+//!
+//! The executor assumes it is the only one writing the outputs of a graph and
+//! its database records. Two processes building overlapping targets in the
+//! same directory would race on both. Hold a [`BuildLock`] for the whole build
+//! to rule this out.
+
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// An exclusive lock on a build directory, held until dropped.
+///
+/// The lock is an OS file lock on the given lock file, so it is released even
+/// if the process is killed. The file stays in place afterwards, and holds the
+/// process ID of the last owner for error messages.
+#[derive(Debug)]
+pub struct BuildLock {
+    _file: File,
+    path: PathBuf,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LockError {
+    #[error("Failed to open the lock file: {0}")]
+    Io(#[from] io::Error),
+    #[error("The build directory is locked by {}", describe_holder(*pid))]
+    Held {
+        /// The process holding the lock, if known
+        pid: Option<u32>,
+    },
+}
+
+fn describe_holder(pid: Option<u32>) -> String {
+    match pid {
+        Some(pid) => format!("another process (pid {pid})"),
+        None => "another process".to_owned(),
+    }
+}
+
+impl BuildLock {
+    /// Acquire the lock, blocking until it is available.
+    pub fn acquire(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::acquire_or_wait(path, |_| {})
+    }
+
+    /// Acquire the lock, or fail with [`LockError::Held`] if another process
+    /// holds it.
+    pub fn try_acquire(path: impl AsRef<Path>) -> Result<Self, LockError> {
+        let path = path.as_ref();
+        let mut file = open(path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Self::locked(file, path)?),
+            Err(std::fs::TryLockError::WouldBlock) => Err(LockError::Held {
+                pid: read_pid(&mut file),
+            }),
+            Err(std::fs::TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+
+    /// Acquire the lock. If another process holds it, call `on_wait` with its
+    /// process ID if known, e.g. to tell the user, and then block until the
+    /// lock is available.
+    pub fn acquire_or_wait(
+        path: impl AsRef<Path>,
+        on_wait: impl FnOnce(Option<u32>),
+    ) -> io::Result<Self> {
+        match Self::try_acquire(&path) {
+            Ok(lock) => Ok(lock),
+            Err(LockError::Io(e)) => Err(e),
+            Err(LockError::Held { pid }) => {
+                on_wait(pid);
+                let path = path.as_ref();
+                let file = open(path)?;
+                file.lock()?;
+                Self::locked(file, path)
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn locked(mut file: File, path: &Path) -> io::Result<Self> {
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write!(file, "{}", std::process::id())?;
+        file.flush()?;
+        Ok(Self {
+            _file: file,
+            path: path.to_owned(),
+        })
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+/// Read the process ID of the current owner. This fails on platforms where
+/// locks prevent reading, which is fine as it is only informative.
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut s = String::new();
+    file.read_to_string(&mut s).ok()?;
+    s.trim().parse().ok()
+}
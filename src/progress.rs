@@ -4,7 +4,14 @@
 pub mod dumb;
 #[cfg(feature = "progress-fancy")]
 pub mod fancy;
+pub mod format;
+#[cfg(feature = "progress-json")]
+pub mod json;
 pub mod noop;
+pub mod summary;
+pub mod tee;
+#[cfg(feature = "progress-trace")]
+pub mod trace;
 
 pub use noop::{NOOP_PROGRESS, NoopProgress};
 
@@ -14,7 +21,18 @@ pub use dumb::DumbConsoleProgress;
 #[cfg(feature = "progress-fancy")]
 pub use fancy::FancyConsoleProgress;
 
-use crate::{BuildGraph, BuildId};
+#[cfg(feature = "progress-json")]
+pub use json::JsonProgress;
+
+pub use tee::Tee;
+
+#[cfg(feature = "progress-trace")]
+pub use trace::ChromeTraceProgress;
+
+use crate::{
+    BuildGraph, BuildId,
+    exec::{BuildStatusKind, FailureDetail},
+};
 
 /// Trait for reporting build progress and capturing output.
 ///
@@ -30,22 +48,76 @@ pub trait Progress: Send + Sync {
     /// Callback when a build starts.
     fn build_started(&self, graph: &BuildGraph, id: BuildId, status: &ProgressStatus);
 
-    /// Callback when a chunk of stdout is produced by a build.
+    /// Whether the output of build commands should be captured and passed to
+    /// [`Self::stdout_line`]. If not, build commands write to the stdout and
+    /// stderr of this process directly, and may e.g. detect a terminal there.
+    fn captures_output(&self) -> bool {
+        true
+    }
+
+    /// Callback when a chunk of output is produced by a build. The output is
+    /// passed on as it is produced, in chunks of whole lines where possible,
+    /// before the build is reported as finished.
     fn stdout_line(&self, graph: &BuildGraph, id: BuildId, chunk: &[u8]);
 
-    /// Callback when a build finishes.
+    /// Callback when a build finishes with the given final status, which is
+    /// one of [`UpToDate`], [`Succeeded`], [`Failed`] or [`Skipped`].
+    ///
+    /// Builds that are skipped because a dependency has failed never start,
+    /// and are reported right after the failed build. `failure` is set for
+    /// failed builds.
+    ///
+    /// [`UpToDate`]: BuildStatusKind::UpToDate
+    /// [`Succeeded`]: BuildStatusKind::Succeeded
+    /// [`Failed`]: BuildStatusKind::Failed
+    /// [`Skipped`]: BuildStatusKind::Skipped
     fn build_finished(
         &self,
         graph: &BuildGraph,
         id: BuildId,
-        success: bool,
+        kind: BuildStatusKind,
+        failure: Option<&FailureDetail>,
         status: &ProgressStatus,
     );
 
-    /// Called when a progress session has finished
+    /// Called when a progress session has finished, including when it stopped
+    /// early on an error or a panic. Builds may still be running then.
     fn finish(&self);
 }
 
+impl<P: Progress + ?Sized> Progress for Box<P> {
+    fn prepare(&self, config: &ProgressConfig) {
+        (**self).prepare(config)
+    }
+
+    fn build_started(&self, graph: &BuildGraph, id: BuildId, status: &ProgressStatus) {
+        (**self).build_started(graph, id, status)
+    }
+
+    fn captures_output(&self) -> bool {
+        (**self).captures_output()
+    }
+
+    fn stdout_line(&self, graph: &BuildGraph, id: BuildId, chunk: &[u8]) {
+        (**self).stdout_line(graph, id, chunk)
+    }
+
+    fn build_finished(
+        &self,
+        graph: &BuildGraph,
+        id: BuildId,
+        kind: BuildStatusKind,
+        failure: Option<&FailureDetail>,
+        status: &ProgressStatus,
+    ) {
+        (**self).build_finished(graph, id, kind, failure, status)
+    }
+
+    fn finish(&self) {
+        (**self).finish()
+    }
+}
+
 /// A config for the progress reporter.
 #[derive(Clone, Debug)]
 pub struct ProgressConfig {
@@ -67,3 +139,32 @@ pub struct ProgressStatus {
     /// The number of builds that explicitly failed, not counting skipped ones.
     pub failed: usize,
 }
+
+/// Describe a failed build for console reporters.
+#[cfg(any(feature = "progress-dumb", feature = "progress-fancy"))]
+fn failure_message(graph: &BuildGraph, id: BuildId, failure: Option<&FailureDetail>) -> String {
+    let build = graph.lookup_build(id).expect("invalid build id");
+    match failure {
+        Some(failure) => format!("FAILED: {} ({failure})", build.human_readable()),
+        None => format!("FAILED: {}", build.human_readable()),
+    }
+}
+
+/// The result machine-readable reporters give builds that were still running
+/// when the session finished.
+#[cfg(any(feature = "progress-json", feature = "progress-trace"))]
+const INTERRUPTED: &str = "interrupted";
+
+/// Name a final build status for machine-readable reporters.
+#[cfg(any(feature = "progress-json", feature = "progress-trace"))]
+fn result_name(kind: BuildStatusKind) -> &'static str {
+    match kind {
+        BuildStatusKind::UpToDate => "up_to_date",
+        BuildStatusKind::Succeeded => "succeeded",
+        BuildStatusKind::Failed => "failed",
+        BuildStatusKind::Skipped => "skipped",
+        BuildStatusKind::Fresh | BuildStatusKind::Started => {
+            unreachable!("build finished with status {kind:?}")
+        }
+    }
+}
@@ -2,12 +2,51 @@
 
 use std::io::Write;
 
-use crate::progress::Progress;
+use crate::{
+    exec::{BuildStatusKind, FailureDetail},
+    progress::{
+        Progress,
+        format::{StatusFormat, StatusLine},
+        summary::BuildSummary,
+    },
+};
 
-pub struct DumbConsoleProgress;
+/// Prints a line for each started build, and a summary of the failed builds
+/// and the number of builds of each outcome at the end.
+pub struct DumbConsoleProgress {
+    status_line: StatusLine,
+    summary: BuildSummary,
+}
+
+impl DumbConsoleProgress {
+    /// The status format used by [`Self::new`].
+    pub const DEFAULT_FORMAT: &str = "[%s/%t] ";
+
+    /// Prefix the line of each started build with [`Self::DEFAULT_FORMAT`].
+    pub fn new() -> Self {
+        let format = StatusFormat::parse(Self::DEFAULT_FORMAT).expect("invalid status format");
+        Self::with_format(format)
+    }
+
+    /// Prefix the line of each started build with the given status format.
+    pub fn with_format(format: StatusFormat) -> Self {
+        Self {
+            status_line: StatusLine::new(format),
+            summary: BuildSummary::new(),
+        }
+    }
+}
+
+impl Default for DumbConsoleProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Progress for DumbConsoleProgress {
-    fn prepare(&self, _config: &super::ProgressConfig) {}
+    fn prepare(&self, config: &super::ProgressConfig) {
+        self.status_line.prepare(config);
+    }
 
     fn build_started(
         &self,
@@ -15,23 +54,35 @@ impl Progress for DumbConsoleProgress {
         id: crate::BuildId,
         status: &super::ProgressStatus,
     ) {
-        print!("[{}/{}] ", status.started + 1, status.total);
+        print!("{}", self.status_line.render_started(status));
         let cmd = graph.lookup_build(id).expect("invalid build id");
         println!("{}", cmd.human_readable());
     }
 
-    fn stdout_line(&self, _graph: &crate::BuildGraph, _id: crate::BuildId, chunk: &[u8]) {
+    fn stdout_line(&self, _graph: &crate::BuildGraph, id: crate::BuildId, chunk: &[u8]) {
+        self.summary.record_output(id, chunk);
         std::io::stdout().write_all(chunk).unwrap();
     }
 
     fn build_finished(
         &self,
-        _graph: &crate::BuildGraph,
-        _id: crate::BuildId,
-        _success: bool,
+        graph: &crate::BuildGraph,
+        id: crate::BuildId,
+        kind: BuildStatusKind,
+        failure: Option<&FailureDetail>,
         _status: &super::ProgressStatus,
     ) {
+        self.status_line.build_finished();
+        self.summary.record_finished(graph, id, kind, failure);
+        if kind == BuildStatusKind::Failed {
+            println!("{}", super::failure_message(graph, id, failure));
+        }
     }
 
-    fn finish(&self) {}
+    fn finish(&self) {
+        if self.summary.has_failures() {
+            println!();
+        }
+        self.summary.write(&mut std::io::stdout().lock()).unwrap();
+    }
 }
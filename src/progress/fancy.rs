@@ -1,29 +1,151 @@
 //! Fancy console progress bar
 
-use std::io::Write;
+use std::{
+    io::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
-use indicatif::{ProgressBar, ProgressStyle};
+use indexmap::IndexMap;
+use indicatif::{MultiProgress, ProgressBar, ProgressState, ProgressStyle};
 
-use crate::progress::Progress;
+use crate::{
+    BuildId,
+    exec::{BuildStatusKind, FailureDetail},
+    progress::{
+        Progress,
+        format::{StatusFormat, StatusLine},
+        summary::BuildSummary,
+    },
+};
 
+/// Shows a progress bar with a line for each running build, and a summary of
+/// the failed builds and the number of builds of each outcome at the end.
+///
+/// Each running build is shown with its elapsed time, which is highlighted
+/// once the build has run for longer than [`Self::LONG_RUNNING`]. At most
+/// [`ProgressConfig::max_threads`](super::ProgressConfig::max_threads) builds
+/// are shown, and fewer if the terminal is too short for them; the rest are
+/// counted in a last line. If the terminal cannot fit any of them, only the
+/// most recently started build is shown next to the progress bar.
 pub struct FancyConsoleProgress {
-    progress: indicatif::ProgressBar,
+    multi: MultiProgress,
+    progress: ProgressBar,
+    jobs: Mutex<Jobs>,
+    status_line: StatusLine,
+    summary: BuildSummary,
+}
+
+struct Jobs {
+    /// The maximum number of lines for running builds
+    max_lines: usize,
+    running: IndexMap<BuildId, Job>,
+    /// The line counting running builds that are not shown
+    overflow: Option<ProgressBar>,
+}
+
+struct Job {
+    description: String,
+    start: Instant,
+    line: Option<ProgressBar>,
 }
 
 impl FancyConsoleProgress {
+    /// The status format used by [`Self::new`].
+    pub const DEFAULT_FORMAT: &str = "%s/%t: ";
+
+    /// How long a build runs before its elapsed time is highlighted.
+    pub const LONG_RUNNING: Duration = Duration::from_secs(10);
+
+    /// Lines kept free of running builds: the progress bar, the line counting
+    /// hidden builds, and the line the cursor is left on.
+    const RESERVED_LINES: usize = 3;
+
     pub fn new() -> Self {
-        Self {
-            progress: ProgressBar::no_length().with_style(
-                ProgressStyle::with_template("[{bar:30}] {pos}/{len}: {wide_msg}")
+        let format = StatusFormat::parse(Self::DEFAULT_FORMAT).expect("invalid status format");
+        Self::with_format(format)
+    }
+
+    /// Show the given status format between the progress bar and the running
+    /// build.
+    pub fn with_format(format: StatusFormat) -> Self {
+        let multi = MultiProgress::new();
+        let progress = multi.add(
+            ProgressBar::no_length().with_style(
+                ProgressStyle::with_template("[{bar:30}] {prefix}{wide_msg}")
                     .expect("invalid progress style")
                     .progress_chars("=> "),
             ),
+        );
+        Self {
+            multi,
+            progress,
+            jobs: Mutex::new(Jobs {
+                max_lines: 1,
+                running: IndexMap::new(),
+                overflow: None,
+            }),
+            status_line: StatusLine::new(format),
+            summary: BuildSummary::new(),
         }
     }
 
-    fn update_progress(&self, status: &super::ProgressStatus) {
-        self.progress.set_length(status.total as u64);
-        self.progress.set_position((status.started + 1) as u64);
+    fn update_progress(&self, started: usize, total: usize, status_line: String) {
+        self.progress.set_length(total as u64);
+        self.progress.set_position(started as u64);
+        self.progress.set_prefix(status_line);
+    }
+
+    /// Add a line for a running build, keeping it above the overflow line.
+    fn show_job(&self, overflow: Option<&ProgressBar>, job: &mut Job) {
+        let start = job.start;
+        let style = ProgressStyle::with_template("  {job_elapsed} {wide_msg}")
+            .expect("invalid progress style")
+            .with_key(
+                "job_elapsed",
+                move |_: &ProgressState, w: &mut dyn std::fmt::Write| {
+                    let elapsed = start.elapsed();
+                    let text = format!("{:>4}s", elapsed.as_secs());
+                    let _ = if elapsed >= Self::LONG_RUNNING {
+                        write!(w, "{}", console::style(text).yellow().bold())
+                    } else {
+                        write!(w, "{}", console::style(text).dim())
+                    };
+                },
+            );
+        let line = ProgressBar::new_spinner()
+            .with_style(style)
+            .with_message(job.description.clone());
+        let line = match overflow {
+            Some(overflow) => self.multi.insert_before(overflow, line),
+            None => self.multi.add(line),
+        };
+        line.enable_steady_tick(Duration::from_millis(500));
+        job.line = Some(line);
+    }
+
+    /// Show, update or remove the line counting hidden running builds.
+    fn update_overflow(&self, jobs: &mut Jobs) {
+        let hidden = jobs.running.values().filter(|j| j.line.is_none()).count();
+        match (&jobs.overflow, hidden) {
+            (Some(overflow), 0) => {
+                overflow.finish_and_clear();
+                self.multi.remove(overflow);
+                jobs.overflow = None;
+            }
+            (Some(overflow), n) => overflow.set_message(format!("... and {n} more")),
+            (None, 0) => {}
+            (None, n) => {
+                let overflow = ProgressBar::new_spinner()
+                    .with_style(
+                        ProgressStyle::with_template("  {msg}").expect("invalid progress style"),
+                    )
+                    .with_message(format!("... and {n} more"));
+                let overflow = self.multi.add(overflow);
+                overflow.tick();
+                jobs.overflow = Some(overflow);
+            }
+        }
     }
 }
 
@@ -34,37 +156,104 @@ impl Default for FancyConsoleProgress {
 }
 
 impl Progress for FancyConsoleProgress {
-    fn prepare(&self, _config: &super::ProgressConfig) {}
+    fn prepare(&self, config: &super::ProgressConfig) {
+        self.status_line.prepare(config);
+        let threads = config.max_threads.unwrap_or(1).max(1);
+        let fit = console::Term::stderr()
+            .size_checked()
+            .map_or(usize::MAX, |(rows, _)| {
+                (rows as usize).saturating_sub(Self::RESERVED_LINES)
+            });
+        self.jobs.lock().unwrap().max_lines = threads.min(fit);
+    }
 
     fn build_started(
         &self,
         graph: &crate::BuildGraph,
-        id: crate::BuildId,
+        id: BuildId,
         status: &super::ProgressStatus,
     ) {
-        self.update_progress(status);
+        let status_line = self.status_line.render_started(status);
+        self.update_progress(status.started + 1, status.total, status_line);
         let cmd = graph.lookup_build(id).expect("invalid build id");
-        self.progress.set_message(cmd.human_readable().to_string());
+        let description = cmd.human_readable().to_string();
+
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.max_lines == 0 {
+            self.progress.set_message(description);
+            return;
+        }
+        let mut job = Job {
+            description,
+            start: Instant::now(),
+            line: None,
+        };
+        let shown = jobs.running.values().filter(|j| j.line.is_some()).count();
+        if shown < jobs.max_lines {
+            self.show_job(jobs.overflow.as_ref(), &mut job);
+        }
+        jobs.running.insert(id, job);
+        self.update_overflow(&mut jobs);
     }
 
-    fn stdout_line(&self, _graph: &crate::BuildGraph, _id: crate::BuildId, chunk: &[u8]) {
-        self.progress.suspend(|| {
+    fn stdout_line(&self, _graph: &crate::BuildGraph, id: BuildId, chunk: &[u8]) {
+        self.summary.record_output(id, chunk);
+        self.multi.suspend(|| {
             std::io::stdout().write_all(chunk).unwrap();
-            println!()
+            if !chunk.ends_with(b"\n") {
+                println!()
+            }
         })
     }
 
     fn build_finished(
         &self,
-        _graph: &crate::BuildGraph,
-        _id: crate::BuildId,
-        _success: bool,
+        graph: &crate::BuildGraph,
+        id: BuildId,
+        kind: BuildStatusKind,
+        failure: Option<&FailureDetail>,
         status: &super::ProgressStatus,
     ) {
-        self.update_progress(status);
+        self.status_line.build_finished();
+        self.summary.record_finished(graph, id, kind, failure);
+        if kind == BuildStatusKind::Failed {
+            let message = super::failure_message(graph, id, failure);
+            self.multi.suspend(|| println!("{message}"));
+        }
+
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(line) = jobs.running.shift_remove(&id).and_then(|j| j.line) {
+            line.finish_and_clear();
+            self.multi.remove(&line);
+            // Show the longest running build that is not shown yet
+            let Jobs {
+                running, overflow, ..
+            } = &mut *jobs;
+            if let Some(job) = running.values_mut().find(|j| j.line.is_none()) {
+                self.show_job(overflow.as_ref(), job);
+            }
+        }
+        self.update_overflow(&mut jobs);
+        drop(jobs);
+
+        let status_line = self.status_line.render(status);
+        self.update_progress(status.started, status.total, status_line);
     }
 
     fn finish(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        for line in jobs.running.drain(..).filter_map(|(_, j)| j.line) {
+            line.finish_and_clear();
+        }
+        if let Some(overflow) = jobs.overflow.take() {
+            overflow.finish_and_clear();
+        }
+        drop(jobs);
         self.progress.finish_and_clear();
+        let _ = self.multi.clear();
+        if self.summary.has_failures() {
+            println!();
+        }
+        self.summary.write(&mut std::io::stdout().lock()).unwrap();
     }
 }
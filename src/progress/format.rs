@@ -0,0 +1,202 @@
+//! Status lines in the format of ninja's `NINJA_STATUS`
+//!
+//! A [`StatusFormat`] is a string with placeholders that are replaced with the
+//! current progress:
+//!
+//! - `%s`: The number of started builds.
+//! - `%t`: The total number of builds to run.
+//! - `%r`: The number of running builds.
+//! - `%u`: The number of builds yet to start.
+//! - `%f`: The number of finished builds.
+//! - `%o`: The overall rate of finished builds per second.
+//! - `%c`: The current rate of finished builds per second, averaged over as
+//!   many builds as may run in parallel.
+//! - `%p`: The percentage of finished builds.
+//! - `%e`: The elapsed time in seconds.
+//! - `%%`: A plain `%`.
+
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::progress::{ProgressConfig, ProgressStatus};
+
+/// A parsed status format. See the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusFormat {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Started,
+    Total,
+    Running,
+    Unstarted,
+    Finished,
+    OverallRate,
+    CurrentRate,
+    Percentage,
+    Elapsed,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unknown placeholder %{0} in status format")]
+pub struct StatusFormatError(pub char);
+
+impl StatusFormat {
+    /// The format used by ninja if `NINJA_STATUS` is not set.
+    pub const NINJA_DEFAULT: &str = "[%f/%t] ";
+
+    pub fn parse(format: &str) -> Result<Self, StatusFormatError> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+            let part = match chars.next() {
+                // A trailing `%` is kept as is
+                None | Some('%') => {
+                    literal.push('%');
+                    continue;
+                }
+                Some('s') => Part::Started,
+                Some('t') => Part::Total,
+                Some('r') => Part::Running,
+                Some('u') => Part::Unstarted,
+                Some('f') => Part::Finished,
+                Some('o') => Part::OverallRate,
+                Some('c') => Part::CurrentRate,
+                Some('p') => Part::Percentage,
+                Some('e') => Part::Elapsed,
+                Some(other) => return Err(StatusFormatError(other)),
+            };
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(part);
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Self { parts })
+    }
+
+    /// Render the status line. `current_rate` is the rate for `%c`, if known.
+    pub fn render(
+        &self,
+        status: &ProgressStatus,
+        elapsed: Duration,
+        current_rate: Option<f64>,
+    ) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            // Writing to a string cannot fail
+            let _ = match part {
+                Part::Literal(s) => write!(out, "{s}"),
+                Part::Started => write!(out, "{}", status.started),
+                Part::Total => write!(out, "{}", status.total),
+                Part::Running => write!(out, "{}", status.started - status.done),
+                Part::Unstarted => write!(out, "{}", status.total - status.started),
+                Part::Finished => write!(out, "{}", status.done),
+                Part::OverallRate => {
+                    let secs = elapsed.as_secs_f64();
+                    write_rate(&mut out, (secs > 0.0).then(|| status.done as f64 / secs))
+                }
+                Part::CurrentRate => write_rate(&mut out, current_rate),
+                Part::Percentage => {
+                    let percent = (status.done * 100).checked_div(status.total).unwrap_or(0);
+                    write!(out, "{percent:3}%")
+                }
+                Part::Elapsed => write!(out, "{:.3}", elapsed.as_secs_f64()),
+            };
+        }
+        out
+    }
+}
+
+fn write_rate(out: &mut String, rate: Option<f64>) -> std::fmt::Result {
+    match rate {
+        Some(rate) => write!(out, "{rate:.1}"),
+        None => write!(out, "?"),
+    }
+}
+
+/// A [`StatusFormat`] together with the timing of the current build, for
+/// console reporters.
+#[derive(Debug)]
+pub struct StatusLine {
+    format: StatusFormat,
+    clock: Mutex<Clock>,
+}
+
+#[derive(Debug)]
+struct Clock {
+    start: Instant,
+    /// When the most recent builds finished, up to `window` of them
+    finished: VecDeque<Instant>,
+    window: usize,
+}
+
+impl StatusLine {
+    pub fn new(format: StatusFormat) -> Self {
+        Self {
+            format,
+            clock: Mutex::new(Clock {
+                start: Instant::now(),
+                finished: VecDeque::new(),
+                window: 1,
+            }),
+        }
+    }
+
+    /// Restart the clock for a new build. Call from
+    /// [`Progress::prepare`](super::Progress::prepare).
+    pub fn prepare(&self, config: &ProgressConfig) {
+        let mut clock = self.clock.lock().unwrap();
+        clock.start = Instant::now();
+        clock.finished.clear();
+        clock.window = config.max_threads.unwrap_or(1).max(1);
+    }
+
+    /// Record that a build has finished. Call from
+    /// [`Progress::build_finished`](super::Progress::build_finished).
+    pub fn build_finished(&self) {
+        let mut clock = self.clock.lock().unwrap();
+        if clock.finished.len() > clock.window {
+            clock.finished.pop_front();
+        }
+        clock.finished.push_back(Instant::now());
+    }
+
+    /// Render the status line for a build that is starting. The status passed
+    /// to [`Progress::build_started`](super::Progress::build_started) does not
+    /// count the build yet, so it is counted here.
+    pub fn render_started(&self, status: &ProgressStatus) -> String {
+        self.render(&ProgressStatus {
+            started: status.started + 1,
+            ..status.clone()
+        })
+    }
+
+    /// Render the status line for the given progress.
+    pub fn render(&self, status: &ProgressStatus) -> String {
+        let clock = self.clock.lock().unwrap();
+        let current_rate = match (clock.finished.front(), clock.finished.back()) {
+            (Some(first), Some(last)) if last > first => {
+                let span = last.duration_since(*first).as_secs_f64();
+                Some((clock.finished.len() - 1) as f64 / span)
+            }
+            _ => None,
+        };
+        self.format
+            .render(status, clock.start.elapsed(), current_rate)
+    }
+}
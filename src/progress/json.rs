@@ -0,0 +1,266 @@
+//! Machine-readable progress as JSON lines
+//!
+//! [`JsonProgress`] writes one JSON object per line for each progress event.
+//! The kind of event is in its `event` field:
+//!
+//! - `prepare`: A build session starts, with `max_threads`.
+//! - `build_started`: A build starts, with `build` and `status`.
+//! - `output`: A running build produced `output`, decoded as lossy UTF-8.
+//!   Output is written as it is produced, usually a few whole lines at a time.
+//! - `build_finished`: A build finished, with `build`, `result` (one of
+//!   `up_to_date`, `succeeded`, `failed` or `skipped`), `failure` and
+//!   `status`. Builds that were still running when the session finished, e.g.
+//!   after another build failed, are finished with the result `interrupted`
+//!   and the last `status`, so that every started build is closed.
+//! - `finish`: The build session has finished.
+//!
+//! Every event has a `time`, the seconds since `prepare`. A `build` has the
+//! `hash` of the build as in [`hash_build`], which identifies the build across
+//! runs, and its `id` within this run, along with its `description` and
+//! `outputs`. A `status` has the counters of [`ProgressStatus`]. A `failure`
+//! has the fields of [`FailureDetail`] and a human-readable `message`.
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap},
+    io::Write,
+    sync::Mutex,
+    time::Instant,
+};
+
+use serde::Serialize;
+
+use crate::{
+    BuildGraph, BuildId,
+    exec::{BuildStatusKind, FailureDetail},
+    graph::hash_build,
+    progress::{Progress, ProgressConfig, ProgressStatus},
+};
+
+/// Writes progress events as JSON lines. See the [module documentation](self).
+///
+/// Each event is flushed as soon as it is written. Errors writing events are
+/// logged and otherwise ignored, so that they do not interrupt the build.
+pub struct JsonProgress<W> {
+    out: Mutex<W>,
+    state: Mutex<State>,
+}
+
+struct State {
+    start: Instant,
+    /// The view of each build reported so far, which does not change within a
+    /// session
+    builds: HashMap<BuildId, BuildView>,
+    /// Builds that have started but not finished
+    running: BTreeSet<BuildId>,
+    /// The status of the last started or finished build
+    status: StatusView,
+}
+
+impl State {
+    fn time(&self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+
+    fn view(&mut self, graph: &BuildGraph, id: BuildId) -> &BuildView {
+        self.builds
+            .entry(id)
+            .or_insert_with(|| BuildView::new(graph, id))
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    Prepare {
+        time: f64,
+        max_threads: Option<usize>,
+    },
+    BuildStarted {
+        time: f64,
+        build: &'a BuildView,
+        status: StatusView,
+    },
+    Output {
+        time: f64,
+        build: &'a BuildView,
+        output: Cow<'a, str>,
+    },
+    BuildFinished {
+        time: f64,
+        build: &'a BuildView,
+        result: &'static str,
+        failure: Option<FailureView<'a>>,
+        status: StatusView,
+    },
+    Finish {
+        time: f64,
+    },
+}
+
+#[derive(Serialize)]
+struct BuildView {
+    id: usize,
+    hash: String,
+    description: String,
+    outputs: Vec<String>,
+}
+
+impl BuildView {
+    fn new(graph: &BuildGraph, id: BuildId) -> Self {
+        let build = graph.lookup_build(id).expect("invalid build id");
+        Self {
+            id: id.index(),
+            hash: hash_build(build, graph).to_string(),
+            description: build.human_readable().to_string(),
+            outputs: build
+                .outs
+                .iter()
+                .map(|&out| graph.lookup_path(out).expect("invalid FileId"))
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Serialize)]
+struct StatusView {
+    total: usize,
+    started: usize,
+    done: usize,
+    failed: usize,
+}
+
+impl From<&ProgressStatus> for StatusView {
+    fn from(status: &ProgressStatus) -> Self {
+        Self {
+            total: status.total,
+            started: status.started,
+            done: status.done,
+            failed: status.failed,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct FailureView<'a> {
+    message: String,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    missing_input: Option<Cow<'a, str>>,
+    error: Option<String>,
+}
+
+impl<'a> From<&'a FailureDetail> for FailureView<'a> {
+    fn from(failure: &'a FailureDetail) -> Self {
+        Self {
+            message: failure.to_string(),
+            exit_code: failure.exit_code,
+            signal: failure.signal,
+            missing_input: failure.missing_input.as_ref().map(|p| p.to_string_lossy()),
+            error: failure.error.as_ref().map(|e| e.to_string()),
+        }
+    }
+}
+
+impl<W: Write + Send> JsonProgress<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out: Mutex::new(out),
+            state: Mutex::new(State {
+                start: Instant::now(),
+                builds: HashMap::new(),
+                running: BTreeSet::new(),
+                status: StatusView::default(),
+            }),
+        }
+    }
+
+    /// Get back the writer the events were written to.
+    pub fn into_inner(self) -> W {
+        self.out.into_inner().unwrap()
+    }
+
+    fn write(&self, event: &Event) {
+        let mut out = self.out.lock().unwrap();
+        let result = serde_json::to_writer(&mut *out, event)
+            .map_err(std::io::Error::from)
+            .and_then(|()| out.write_all(b"\n"))
+            .and_then(|()| out.flush());
+        if let Err(e) = result {
+            tracing::warn!("Failed to write progress event: {e}");
+        }
+    }
+}
+
+impl<W: Write + Send> Progress for JsonProgress<W> {
+    fn prepare(&self, config: &ProgressConfig) {
+        let mut state = self.state.lock().unwrap();
+        state.start = Instant::now();
+        state.builds.clear();
+        state.running.clear();
+        state.status = StatusView::default();
+        self.write(&Event::Prepare {
+            time: 0.0,
+            max_threads: config.max_threads,
+        });
+    }
+
+    fn build_started(&self, graph: &BuildGraph, id: BuildId, status: &ProgressStatus) {
+        let mut state = self.state.lock().unwrap();
+        state.running.insert(id);
+        state.status = status.into();
+        let time = state.time();
+        self.write(&Event::BuildStarted {
+            time,
+            build: state.view(graph, id),
+            status: status.into(),
+        });
+    }
+
+    fn stdout_line(&self, graph: &BuildGraph, id: BuildId, chunk: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        let time = state.time();
+        self.write(&Event::Output {
+            time,
+            build: state.view(graph, id),
+            output: String::from_utf8_lossy(chunk),
+        });
+    }
+
+    fn build_finished(
+        &self,
+        graph: &BuildGraph,
+        id: BuildId,
+        kind: BuildStatusKind,
+        failure: Option<&FailureDetail>,
+        status: &ProgressStatus,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        state.running.remove(&id);
+        state.status = status.into();
+        let time = state.time();
+        self.write(&Event::BuildFinished {
+            time,
+            build: state.view(graph, id),
+            result: super::result_name(kind),
+            failure: failure.map(Into::into),
+            status: status.into(),
+        });
+    }
+
+    fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        let time = state.time();
+        for id in std::mem::take(&mut state.running) {
+            self.write(&Event::BuildFinished {
+                time,
+                build: &state.builds[&id],
+                result: super::INTERRUPTED,
+                failure: None,
+                status: state.status,
+            });
+        }
+        self.write(&Event::Finish { time });
+    }
+}
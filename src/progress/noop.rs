@@ -1,6 +1,9 @@
 //! No-op progress reporter
 
-use crate::{BuildGraph, BuildId};
+use crate::{
+    BuildGraph, BuildId,
+    exec::{BuildStatusKind, FailureDetail},
+};
 
 use super::{Progress, ProgressConfig, ProgressStatus};
 
@@ -13,13 +16,18 @@ impl Progress for NoopProgress {
 
     fn build_started(&self, _graph: &BuildGraph, _id: BuildId, _status: &ProgressStatus) {}
 
+    fn captures_output(&self) -> bool {
+        false
+    }
+
     fn stdout_line(&self, _graph: &BuildGraph, _id: BuildId, _chunk: &[u8]) {}
 
     fn build_finished(
         &self,
         _graph: &BuildGraph,
         _id: BuildId,
-        _success: bool,
+        _kind: BuildStatusKind,
+        _failure: Option<&FailureDetail>,
         _status: &ProgressStatus,
     ) {
     }
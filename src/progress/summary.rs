@@ -0,0 +1,185 @@
+//! Collecting an end-of-build summary of failures
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Write},
+    sync::Mutex,
+};
+
+use crate::{
+    BuildGraph, BuildId,
+    exec::{BuildStatusKind, FailureDetail},
+};
+
+/// The number of output lines kept for each failed build.
+const OUTPUT_TAIL_LINES: usize = 20;
+
+/// Collects the outcome of each build for a summary at the end of the build,
+/// so that failures are not lost in the scrollback of a long build.
+///
+/// Feed it from [`Progress::stdout_line`] and [`Progress::build_finished`],
+/// and [write](Self::write) it in [`Progress::finish`].
+///
+/// [`Progress::stdout_line`]: super::Progress::stdout_line
+/// [`Progress::build_finished`]: super::Progress::build_finished
+/// [`Progress::finish`]: super::Progress::finish
+#[derive(Default)]
+pub struct BuildSummary {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    succeeded: usize,
+    up_to_date: usize,
+    /// Skipped builds, and their descriptions
+    skipped: Vec<(BuildId, String)>,
+    failed: Vec<FailedBuild>,
+    /// The output of builds that are still running
+    outputs: HashMap<BuildId, Vec<u8>>,
+}
+
+struct FailedBuild {
+    description: String,
+    command: String,
+    reason: Option<String>,
+    output: Vec<u8>,
+    /// All builds that transitively depend on this one
+    dependents: HashSet<BuildId>,
+    skipped: Vec<String>,
+}
+
+impl BuildSummary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a chunk of output of a running build.
+    pub fn record_output(&self, id: BuildId, chunk: &[u8]) {
+        let mut inner = self.inner.lock().unwrap();
+        let output = inner.outputs.entry(id).or_default();
+        output.extend_from_slice(chunk);
+        let start = tail_start(output);
+        output.drain(..start);
+    }
+
+    /// Record a finished build.
+    ///
+    /// Skipped builds are listed under every failed build they depend on,
+    /// including failures that are reported after them.
+    pub fn record_finished(
+        &self,
+        graph: &BuildGraph,
+        id: BuildId,
+        kind: BuildStatusKind,
+        failure: Option<&FailureDetail>,
+    ) {
+        // Walk the graph once per failure, and without holding the lock, so
+        // that matching skipped builds to failures is a lookup
+        let dependents = matches!(kind, BuildStatusKind::Failed).then(|| dependents(graph, id));
+        let mut inner = self.inner.lock().unwrap();
+        let output = inner.outputs.remove(&id).unwrap_or_default();
+        let build = graph.lookup_build(id).expect("invalid build id");
+        match kind {
+            BuildStatusKind::Succeeded => inner.succeeded += 1,
+            BuildStatusKind::UpToDate => inner.up_to_date += 1,
+            BuildStatusKind::Skipped => {
+                let description = build.human_readable().to_string();
+                for failed in &mut inner.failed {
+                    if failed.dependents.contains(&id) {
+                        failed.skipped.push(description.clone());
+                    }
+                }
+                inner.skipped.push((id, description));
+            }
+            BuildStatusKind::Failed => {
+                let mut command = String::new();
+                build
+                    .command
+                    .write_human_readable(&mut command)
+                    .expect("Failed to format command");
+                let dependents = dependents.unwrap_or_default();
+                let skipped = inner
+                    .skipped
+                    .iter()
+                    .filter(|(skipped, _)| dependents.contains(skipped))
+                    .map(|(_, description)| description.clone())
+                    .collect();
+                inner.failed.push(FailedBuild {
+                    description: build.human_readable().to_string(),
+                    command,
+                    reason: failure.map(|f| f.to_string()),
+                    output,
+                    dependents,
+                    skipped,
+                });
+            }
+            BuildStatusKind::Fresh | BuildStatusKind::Started => {}
+        }
+    }
+
+    /// Whether any build has failed.
+    pub fn has_failures(&self) -> bool {
+        !self.inner.lock().unwrap().failed.is_empty()
+    }
+
+    /// Write the summary of all failed builds and the number of builds of each
+    /// outcome. Only the numbers are written if no build has failed.
+    pub fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        let inner = self.inner.lock().unwrap();
+        for failed in &inner.failed {
+            writeln!(w, "FAILED: {}", failed.description)?;
+            if failed.command != failed.description {
+                writeln!(w, "  command: {}", failed.command)?;
+            }
+            if let Some(reason) = &failed.reason {
+                writeln!(w, "  reason: {reason}")?;
+            }
+            if !failed.output.is_empty() {
+                writeln!(w, "  output:")?;
+                for line in String::from_utf8_lossy(&failed.output).lines() {
+                    writeln!(w, "    {line}")?;
+                }
+            }
+            if !failed.skipped.is_empty() {
+                writeln!(w, "  skipped dependents:")?;
+                for skipped in &failed.skipped {
+                    writeln!(w, "    {skipped}")?;
+                }
+            }
+        }
+        writeln!(
+            w,
+            "{} succeeded, {} up-to-date, {} failed, {} skipped",
+            inner.succeeded,
+            inner.up_to_date,
+            inner.failed.len(),
+            inner.skipped.len()
+        )
+    }
+}
+
+/// All builds that transitively depend on `build`.
+fn dependents(graph: &BuildGraph, build: BuildId) -> HashSet<BuildId> {
+    let mut visited = HashSet::new();
+    let mut stack = vec![build];
+    while let Some(id) = stack.pop() {
+        for dependent in graph.build_dependents(id) {
+            if visited.insert(dependent) {
+                stack.push(dependent);
+            }
+        }
+    }
+    visited
+}
+
+/// The start of the last [`OUTPUT_TAIL_LINES`] lines of `output`.
+fn tail_start(output: &[u8]) -> usize {
+    let body = output.strip_suffix(b"\n").unwrap_or(output);
+    body.iter()
+        .enumerate()
+        .rev()
+        .filter(|&(_, &b)| b == b'\n')
+        .nth(OUTPUT_TAIL_LINES - 1)
+        .map_or(0, |(i, _)| i + 1)
+}
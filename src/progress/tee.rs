@@ -0,0 +1,50 @@
+//! Progress reporting to two reporters at once
+
+use crate::{
+    BuildGraph, BuildId,
+    exec::{BuildStatusKind, FailureDetail},
+};
+
+use super::{Progress, ProgressConfig, ProgressStatus};
+
+/// Reports progress to both of the inner reporters, first to `.0` and then to
+/// `.1`. Nest them to report to more.
+pub struct Tee<A, B>(pub A, pub B);
+
+impl<A: Progress, B: Progress> Progress for Tee<A, B> {
+    fn prepare(&self, config: &ProgressConfig) {
+        self.0.prepare(config);
+        self.1.prepare(config);
+    }
+
+    fn build_started(&self, graph: &BuildGraph, id: BuildId, status: &ProgressStatus) {
+        self.0.build_started(graph, id, status);
+        self.1.build_started(graph, id, status);
+    }
+
+    fn captures_output(&self) -> bool {
+        self.0.captures_output() || self.1.captures_output()
+    }
+
+    fn stdout_line(&self, graph: &BuildGraph, id: BuildId, chunk: &[u8]) {
+        self.0.stdout_line(graph, id, chunk);
+        self.1.stdout_line(graph, id, chunk);
+    }
+
+    fn build_finished(
+        &self,
+        graph: &BuildGraph,
+        id: BuildId,
+        kind: BuildStatusKind,
+        failure: Option<&FailureDetail>,
+        status: &ProgressStatus,
+    ) {
+        self.0.build_finished(graph, id, kind, failure, status);
+        self.1.build_finished(graph, id, kind, failure, status);
+    }
+
+    fn finish(&self) {
+        self.0.finish();
+        self.1.finish();
+    }
+}
@@ -0,0 +1,249 @@
+//! Build timelines in the Chrome Trace Event format
+//!
+//! [`ChromeTraceProgress`] writes a trace that can be opened in
+//! [Perfetto](https://ui.perfetto.dev) or `chrome://tracing`. Each build that
+//! started is a complete (`X`) event, named by its description and
+//! categorized by its result, so builds that were found up to date show up as
+//! the short checks they are. Builds are laid out on one lane per worker, at
+//! most [`ProgressConfig::max_threads`] of them.
+//!
+//! Events are written as they finish in the JSON array format, whose closing
+//! bracket is written by [`Progress::finish`]. Builds still running then, e.g.
+//! after another build failed, end there with the category `interrupted`. A
+//! trace cut short by a crash still loads, since the bracket is optional.
+
+use std::{collections::HashMap, io::Write, sync::Mutex, time::Instant};
+
+use serde::Serialize;
+
+use crate::{
+    BuildGraph, BuildId,
+    exec::{BuildStatusKind, FailureDetail},
+    graph::hash_build,
+    progress::{Progress, ProgressConfig, ProgressStatus},
+};
+
+/// Writes a Chrome trace of the build. See the [module documentation](self).
+///
+/// Errors writing the trace are logged and otherwise ignored, so that they do
+/// not interrupt the build.
+pub struct ChromeTraceProgress<W> {
+    out: Mutex<Trace<W>>,
+}
+
+struct Trace<W> {
+    out: W,
+    start: Instant,
+    /// Whether any event has been written, and a separator is needed
+    written: bool,
+    /// Whether each lane is running a build
+    lanes: Vec<bool>,
+    running: HashMap<BuildId, Running>,
+}
+
+/// A running build, described when it starts so that it can be closed without
+/// the graph
+struct Running {
+    lane: usize,
+    /// The start time in microseconds
+    start: u64,
+    name: String,
+    args: BuildArgs,
+}
+
+/// The process id of all events; the trace only has the one build.
+const PID: u32 = 1;
+
+#[derive(Serialize)]
+#[serde(tag = "ph")]
+enum Event {
+    #[serde(rename = "M")]
+    Metadata {
+        name: &'static str,
+        pid: u32,
+        tid: usize,
+        args: MetadataArgs,
+    },
+    #[serde(rename = "X")]
+    Complete {
+        name: String,
+        cat: &'static str,
+        pid: u32,
+        tid: usize,
+        ts: u64,
+        dur: u64,
+        args: BuildArgs,
+    },
+}
+
+#[derive(Serialize)]
+struct MetadataArgs {
+    name: String,
+}
+
+#[derive(Serialize)]
+struct BuildArgs {
+    hash: String,
+    outputs: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure: Option<String>,
+}
+
+impl<W: Write + Send> ChromeTraceProgress<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out: Mutex::new(Trace {
+                out,
+                start: Instant::now(),
+                written: false,
+                lanes: vec![],
+                running: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Get back the writer the trace was written to.
+    pub fn into_inner(self) -> W {
+        self.out.into_inner().unwrap().out
+    }
+}
+
+impl<W: Write> Trace<W> {
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+
+    fn write(&mut self, event: &Event) {
+        let separator: &[u8] = if self.written { b",\n" } else { b"[\n" };
+        self.written = true;
+        let result = self
+            .out
+            .write_all(separator)
+            .and_then(|()| serde_json::to_writer(&mut self.out, event).map_err(Into::into));
+        if let Err(e) = result {
+            tracing::warn!("Failed to write trace event: {e}");
+        }
+    }
+
+    /// Write the complete event of a build that is no longer running.
+    fn complete(&mut self, running: Running, cat: &'static str, failure: Option<String>) {
+        self.lanes[running.lane] = false;
+        let end = self.now();
+        self.write(&Event::Complete {
+            name: running.name,
+            cat,
+            pid: PID,
+            tid: running.lane,
+            ts: running.start,
+            dur: end - running.start,
+            args: BuildArgs {
+                failure,
+                ..running.args
+            },
+        });
+    }
+
+    /// Take the first free lane, naming it when it is used for the first time.
+    fn take_lane(&mut self) -> usize {
+        if let Some(lane) = self.lanes.iter().position(|busy| !busy) {
+            self.lanes[lane] = true;
+            return lane;
+        }
+        let lane = self.lanes.len();
+        self.lanes.push(true);
+        self.write(&Event::Metadata {
+            name: "thread_name",
+            pid: PID,
+            tid: lane,
+            args: MetadataArgs {
+                name: format!("worker {lane}"),
+            },
+        });
+        lane
+    }
+}
+
+impl<W: Write + Send> Progress for ChromeTraceProgress<W> {
+    fn prepare(&self, _config: &ProgressConfig) {
+        let mut trace = self.out.lock().unwrap();
+        trace.start = Instant::now();
+        trace.write(&Event::Metadata {
+            name: "process_name",
+            pid: PID,
+            tid: 0,
+            args: MetadataArgs {
+                name: "n2o5".into(),
+            },
+        });
+    }
+
+    fn build_started(&self, graph: &BuildGraph, id: BuildId, _status: &ProgressStatus) {
+        let build = graph.lookup_build(id).expect("invalid build id");
+        let name = build.human_readable().to_string();
+        let args = BuildArgs {
+            hash: hash_build(build, graph).to_string(),
+            outputs: build
+                .outs
+                .iter()
+                .map(|&out| graph.lookup_path(out).expect("invalid FileId"))
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect(),
+            failure: None,
+        };
+        let mut trace = self.out.lock().unwrap();
+        let lane = trace.take_lane();
+        let start = trace.now();
+        trace.running.insert(
+            id,
+            Running {
+                lane,
+                start,
+                name,
+                args,
+            },
+        );
+    }
+
+    fn captures_output(&self) -> bool {
+        false
+    }
+
+    fn stdout_line(&self, _graph: &BuildGraph, _id: BuildId, _chunk: &[u8]) {}
+
+    fn build_finished(
+        &self,
+        _graph: &BuildGraph,
+        id: BuildId,
+        kind: BuildStatusKind,
+        failure: Option<&FailureDetail>,
+        _status: &ProgressStatus,
+    ) {
+        let mut trace = self.out.lock().unwrap();
+        // Builds skipped because of a failed dependency never ran
+        let Some(running) = trace.running.remove(&id) else {
+            return;
+        };
+        trace.complete(
+            running,
+            super::result_name(kind),
+            failure.map(|f| f.to_string()),
+        );
+    }
+
+    fn finish(&self) {
+        let mut trace = self.out.lock().unwrap();
+        let mut running: Vec<_> = trace.running.drain().collect();
+        running.sort_by_key(|&(id, _)| id);
+        for (_, running) in running {
+            trace.complete(running, super::INTERRUPTED, None);
+        }
+        let closing: &[u8] = if trace.written { b"\n]\n" } else { b"[]\n" };
+        let result = trace
+            .out
+            .write_all(closing)
+            .and_then(|()| trace.out.flush());
+        if let Err(e) = result {
+            tracing::warn!("Failed to write trace event: {e}");
+        }
+    }
+}
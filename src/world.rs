@@ -1,6 +1,14 @@
 //! Abstractions on how the executor interacts with the outside world
 
-use std::{any::Any, path::Path, process::Command, time::SystemTime};
+use std::{
+    any::Any,
+    error::Error,
+    io::{PipeReader, Read},
+    path::Path,
+    process::{Child, Command, ExitStatus},
+    sync::mpsc,
+    time::{Duration, Instant, SystemTime},
+};
 
 use crate::{
     exec::BuildStatusKind,
@@ -29,9 +37,21 @@ pub trait World: Send + Sync {
     /// Get the modification time of a file.
     fn mtime(&self, path: &Path) -> std::io::Result<SystemTime>;
 
+    /// Remove a file. Removing a file that does not exist is not an error.
+    fn remove(&self, path: &Path) -> std::io::Result<()>;
+
     /// Get the current time. Implementations may return a mocked monotonic time.
     fn now(&self) -> SystemTime;
 
+    /// Prepare the outputs of a node before it is executed.
+    ///
+    /// This is called right before [`World::execute`] on an outdated node,
+    /// except for phony nodes, which never create their outputs. As in ninja,
+    /// implementations are expected to ensure that the parent directory of
+    /// every declared output exists, so that build commands can write their
+    /// outputs without creating directories first.
+    fn prepare_outputs(&self, graph: &BuildGraph, node: BuildId) -> std::io::Result<()>;
+
     /// Execute a given node within the build graph.
     ///
     /// This method passes the build graph and the build ID of the node to be
@@ -40,12 +60,52 @@ pub trait World: Send + Sync {
     /// structure, such as sandboxing or caching.
     ///
     /// The build ID is guaranteed to be valid within the given graph.
+    ///
+    /// If `output` is given, the combined stdout and stderr of the build
+    /// command should be passed to it as they are produced, in chunks of whole
+    /// lines where possible. Otherwise the command should write to the stdout
+    /// and stderr of this process directly.
     fn execute(
         &self,
         state: &dyn Any,
         graph: &BuildGraph,
         node: BuildId,
-    ) -> std::io::Result<BuildStatusKind>;
+        output: Option<OutputSink<'_>>,
+    ) -> std::io::Result<ExecOutcome>;
+}
+
+/// Receives the output of a build command from [`World::execute`].
+pub type OutputSink<'a> = &'a dyn Fn(&[u8]);
+
+/// The result of [`World::execute`].
+#[derive(Debug)]
+pub struct ExecOutcome {
+    /// The status of the build, either [`BuildStatusKind::Succeeded`] or
+    /// [`BuildStatusKind::Failed`].
+    pub status: BuildStatusKind,
+    /// The exit code of the build command, if it ran a process that exited
+    /// normally
+    pub exit_code: Option<i32>,
+    /// The signal that terminated the build command, if any. Always `None`
+    /// on platforms without signals.
+    pub signal: Option<i32>,
+    /// The peak resident memory of the build command in bytes, if known
+    pub peak_memory: Option<u64>,
+    /// Why the build has failed, if it was not a process exiting
+    /// unsuccessfully, e.g. the error returned by a callback
+    pub error: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl From<BuildStatusKind> for ExecOutcome {
+    fn from(status: BuildStatusKind) -> Self {
+        Self {
+            status,
+            exit_code: None,
+            signal: None,
+            peak_memory: None,
+            error: None,
+        }
+    }
 }
 
 /// The default implementation of [`World`], which interacts with the local
@@ -62,27 +122,51 @@ impl World for LocalWorld {
         path.metadata()?.modified()
     }
 
+    fn remove(&self, path: &Path) -> std::io::Result<()> {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            other => other,
+        }
+    }
+
     fn now(&self) -> SystemTime {
         SystemTime::now()
     }
 
+    fn prepare_outputs(&self, graph: &BuildGraph, node: BuildId) -> std::io::Result<()> {
+        let build_node = graph
+            .lookup_build(node)
+            .expect("invalid BuildId passed to World::prepare_outputs");
+        for &out in &build_node.outs {
+            let path = graph.lookup_path(out).expect("invalid FileId");
+            if let Some(parent) = path.parent()
+                && !parent.as_os_str().is_empty()
+            {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        Ok(())
+    }
+
     fn execute(
         &self,
         state: &dyn Any,
         graph: &BuildGraph,
         node: BuildId,
-    ) -> std::io::Result<BuildStatusKind> {
+        output: Option<OutputSink<'_>>,
+    ) -> std::io::Result<ExecOutcome> {
         let build_node = graph
             .lookup_build(node)
             .expect("invalid BuildId passed to World::execute");
-        run_build_inner(state, &build_node.command)
+        run_build_inner(state, &build_node.command, output)
     }
 }
 
 fn run_build_inner(
     state: &dyn Any,
     cmd: &crate::graph::BuildMethod,
-) -> Result<BuildStatusKind, std::io::Error> {
+    output: Option<OutputSink<'_>>,
+) -> Result<ExecOutcome, std::io::Error> {
     match cmd {
         crate::graph::BuildMethod::SubCommand(build_cmd) => {
             // FIXME: n2 reports that `Command::spawn` leaks file descriptors.
@@ -91,22 +175,175 @@ fn run_build_inner(
             let mut cmd = Command::new(&build_cmd.executable);
             cmd.args(&build_cmd.args);
 
-            let mut child = cmd.spawn()?;
-            let output = child.wait()?;
-
-            if output.success() {
-                Ok(BuildStatusKind::Succeeded)
+            let (status, peak_memory) = match output {
+                Some(output) => run_captured(cmd, output)?,
+                None => wait_child(cmd.spawn()?)?,
+            };
+            let kind = if status.success() {
+                BuildStatusKind::Succeeded
             } else {
-                Ok(BuildStatusKind::Failed)
+                BuildStatusKind::Failed
+            };
+            Ok(ExecOutcome {
+                status: kind,
+                exit_code: status.code(),
+                signal: exit_signal(&status),
+                peak_memory,
+                error: None,
+            })
+        }
+        crate::graph::BuildMethod::Callback(_, callback) => match callback(state) {
+            Ok(_) => Ok(BuildStatusKind::UpToDate.into()),
+            Err(e) => Ok(ExecOutcome {
+                error: Some(e),
+                ..BuildStatusKind::Failed.into()
+            }),
+        },
+        crate::graph::BuildMethod::Phony => Ok(BuildStatusKind::Succeeded.into()),
+    }
+}
+
+/// How long to wait for the rest of the output after a command has exited.
+/// Processes the command left running in the background may keep the output
+/// pipe open, and their output is not waited for.
+const OUTPUT_GRACE: Duration = Duration::from_millis(100);
+
+/// The length at which an unfinished line of output is passed on anyway.
+const MAX_LINE: usize = 64 * 1024;
+
+enum CommandEvent {
+    Output(Vec<u8>),
+    Exited(std::io::Result<(ExitStatus, Option<u64>)>),
+}
+
+/// Run the command with its stdout and stderr captured through the same pipe,
+/// so that they stay interleaved as the command wrote them, passing the output
+/// on as it arrives.
+fn run_captured(
+    mut cmd: Command,
+    output: OutputSink<'_>,
+) -> std::io::Result<(ExitStatus, Option<u64>)> {
+    let (reader, writer) = std::io::pipe()?;
+    cmd.stdout(writer.try_clone()?);
+    cmd.stderr(writer);
+    let child = cmd.spawn()?;
+    // Close our ends of the pipe, so that reading stops when the command and
+    // its children exit
+    drop(cmd);
+
+    // Both threads outlive this call if the pipe is kept open, so they
+    // communicate through a channel instead of borrowing `output`
+    let (tx, rx) = mpsc::channel();
+    let chunks = tx.clone();
+    std::thread::spawn(move || {
+        read_lines(reader, |chunk| {
+            chunks.send(CommandEvent::Output(chunk)).is_ok()
+        })
+    });
+    std::thread::spawn(move || {
+        let _ = tx.send(CommandEvent::Exited(wait_child(child)));
+    });
+
+    let mut exited = None;
+    let mut deadline: Option<Instant> = None;
+    loop {
+        let event = match deadline {
+            None => rx.recv().ok(),
+            Some(deadline) => rx
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .ok(),
+        };
+        match event {
+            Some(CommandEvent::Output(chunk)) => output(&chunk),
+            Some(CommandEvent::Exited(result)) => {
+                exited = Some(result);
+                deadline = Some(Instant::now() + OUTPUT_GRACE);
             }
+            None => break,
         }
-        crate::graph::BuildMethod::Callback(_name, callback) => match callback(state) {
-            Ok(_) => Ok(BuildStatusKind::UpToDate),
-            Err(e) => {
-                eprintln!("Failed to execute build step {_name}: {e}");
-                Ok(BuildStatusKind::Failed)
+    }
+    exited.unwrap_or_else(|| Err(std::io::Error::other("Lost the exit status of the command")))
+}
+
+/// Read the pipe to its end, passing on whole lines as they arrive. Once `emit`
+/// returns false, the rest is read and discarded, so that writers do not fail.
+fn read_lines(mut reader: PipeReader, mut emit: impl FnMut(Vec<u8>) -> bool) {
+    let mut buf = vec![0; 16 * 1024];
+    let mut pending = vec![];
+    let mut discard = false;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        if discard {
+            continue;
+        }
+        pending.extend_from_slice(&buf[..n]);
+        let end = if pending.len() >= MAX_LINE {
+            pending.len()
+        } else {
+            match pending.iter().rposition(|&b| b == b'\n') {
+                Some(i) => i + 1,
+                None => continue,
             }
-        },
-        crate::graph::BuildMethod::Phony => Ok(BuildStatusKind::Succeeded),
+        };
+        let rest = pending.split_off(end);
+        discard = !emit(std::mem::replace(&mut pending, rest));
+    }
+    if !discard && !pending.is_empty() {
+        emit(pending);
     }
 }
+
+/// Wait for the child to exit, returning its exit status and peak resident
+/// memory in bytes.
+#[cfg(unix)]
+fn wait_child(child: Child) -> std::io::Result<(ExitStatus, Option<u64>)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let pid = child.id() as libc::pid_t;
+    let mut status = 0;
+    // SAFETY: rusage is a plain C struct, for which all zeroes is valid.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: the pointers are valid for the duration of the call. The
+        // child is not waited for by anyone else, since we own it.
+        if unsafe { libc::wait4(pid, &mut status, 0, &mut usage) } != -1 {
+            break;
+        }
+        let e = std::io::Error::last_os_error();
+        if e.kind() != std::io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+
+    // ru_maxrss is in bytes on macOS, and in kilobytes elsewhere.
+    let maxrss = usage.ru_maxrss.max(0) as u64;
+    let peak_memory = if cfg!(target_os = "macos") {
+        maxrss
+    } else {
+        maxrss * 1024
+    };
+    Ok((ExitStatus::from_raw(status), Some(peak_memory)))
+}
+
+/// Wait for the child to exit, returning its exit status and peak resident
+/// memory in bytes.
+#[cfg(not(unix))]
+fn wait_child(mut child: Child) -> std::io::Result<(ExitStatus, Option<u64>)> {
+    Ok((child.wait()?, None))
+}
+
+#[cfg(unix)]
+fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
@@ -0,0 +1,42 @@
+use std::{sync::mpsc, time::Duration};
+
+use n2o5::lock::{BuildLock, LockError};
+
+#[test]
+fn second_lock_fails_fast() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("n2o5.lock");
+    let lock = BuildLock::try_acquire(&path).unwrap();
+
+    match BuildLock::try_acquire(&path) {
+        Err(LockError::Held { pid }) => {
+            if let Some(pid) = pid {
+                assert_eq!(pid, std::process::id());
+            }
+        }
+        other => panic!("Expected the lock to be held, got {other:?}"),
+    }
+
+    drop(lock);
+    BuildLock::try_acquire(&path).unwrap();
+}
+
+#[test]
+fn second_lock_waits() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("n2o5.lock");
+    let lock = BuildLock::acquire(&path).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let waiter = std::thread::spawn({
+        let path = path.clone();
+        move || {
+            let _lock = BuildLock::acquire_or_wait(&path, |_| tx.send(()).unwrap()).unwrap();
+        }
+    });
+
+    // The waiter reports that it is waiting, and gets the lock once released
+    rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    drop(lock);
+    waiter.join().unwrap();
+}
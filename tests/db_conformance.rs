@@ -0,0 +1,42 @@
+//! Runs the shared [`n2o5::db::conformance`] suite against the built-in
+//! databases.
+//!
+//! Their readers block writers, so they skip `transaction_isolation`.
+
+mod dumb {
+    use std::path::Path;
+
+    use n2o5::db::dumb::DumbDb;
+
+    n2o5::db_conformance_tests!(
+        |dir: &Path| DumbDb::new(dir.join("n2o5.db")).unwrap();
+        set_and_get,
+        overwrite,
+        invalidate,
+        uncommitted_writes_are_discarded,
+        reset,
+        persistence,
+        concurrent_readers,
+        iteration,
+        collect_garbage,
+    );
+}
+
+mod in_memory {
+    use std::path::Path;
+
+    use n2o5::db::in_memory::InMemoryDb;
+
+    // Nothing is persisted either
+    n2o5::db_conformance_tests!(
+        |_: &Path| InMemoryDb::new();
+        set_and_get,
+        overwrite,
+        invalidate,
+        uncommitted_writes_are_discarded,
+        reset,
+        concurrent_readers,
+        iteration,
+        collect_garbage,
+    );
+}
@@ -0,0 +1,221 @@
+//! Round trips of [`n2o5::db::portable`] exports between databases.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use n2o5::db::{
+    BuildHash, BuildInfo, BuildInfoV1, ExecDb, ExecRecord, FileInfo, InputHash,
+    in_memory::InMemoryDb,
+    portable::{Format, PathRewrite, PortableError, PortableStats, export, import},
+};
+
+fn time(secs: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::new(secs, 123_456_789)
+}
+
+fn build_info(n: u8) -> BuildInfo {
+    BuildInfo {
+        last_start: time(n as u64),
+        last_end: Some(time(n as u64 + 1)),
+        input_set_digest: InputHash([n; 16]),
+        additional_inputs: vec![
+            format!("/ci/src/in{n}.h").into(),
+            "/usr/include/stdio.h".into(),
+        ],
+        history: vec![ExecRecord {
+            start: time(n as u64),
+            duration: Duration::from_millis(n as u64 * 10),
+            succeeded: true,
+            exit_code: Some(0),
+            peak_memory: Some(1 << 20),
+        }],
+    }
+}
+
+fn file_info(n: u8) -> FileInfo {
+    FileInfo {
+        last_seen: time(n as u64 + 1),
+        generated_by: BuildHash([n; 16]),
+    }
+}
+
+fn source_db() -> InMemoryDb {
+    let db = InMemoryDb::default();
+    let mut txn = db.begin_write();
+    for n in 1..=3 {
+        txn.set_build_info(BuildHash([n; 16]), build_info(n));
+        txn.set_file_info(&PathBuf::from(format!("/ci/src/out{n}.o")), file_info(n));
+    }
+    txn.commit();
+    db
+}
+
+fn round_trip(format: Format) {
+    let src = source_db();
+    let mut data = vec![];
+    let stats = export(&src, format, &mut data).unwrap();
+    assert_eq!(
+        stats,
+        PortableStats {
+            builds: 3,
+            files: 3
+        }
+    );
+
+    let dst = InMemoryDb::default();
+    let rewrites = [PathRewrite::parse("/ci/src=/home/me/src").unwrap()];
+    let stats = import(&dst, format, &mut data.as_slice(), &rewrites).unwrap();
+    assert_eq!(
+        stats,
+        PortableStats {
+            builds: 3,
+            files: 3
+        }
+    );
+
+    let rd = dst.begin_read();
+    for n in 1..=3 {
+        let mut expected = build_info(n);
+        expected.additional_inputs[0] = format!("/home/me/src/in{n}.h").into();
+        assert_eq!(rd.get_build_info(BuildHash([n; 16])), Some(expected));
+        let path = PathBuf::from(format!("/home/me/src/out{n}.o"));
+        assert_eq!(rd.get_file_info(&path), Some(file_info(n)));
+    }
+    assert!(rd.get_file_info(Path::new("/ci/src/out1.o")).is_none());
+}
+
+#[test]
+fn json_lines_round_trip() {
+    round_trip(Format::JsonLines);
+}
+
+#[test]
+fn postcard_round_trip() {
+    round_trip(Format::Postcard);
+}
+
+#[test]
+fn concatenated_exports() {
+    let mut data = vec![];
+    export(&source_db(), Format::JsonLines, &mut data).unwrap();
+    let db = InMemoryDb::default();
+    let mut txn = db.begin_write();
+    txn.set_file_info(Path::new("extra.o"), file_info(4));
+    txn.commit();
+    export(&db, Format::JsonLines, &mut data).unwrap();
+
+    let dst = InMemoryDb::default();
+    let stats = import(&dst, Format::JsonLines, &mut data.as_slice(), &[]).unwrap();
+    assert_eq!(
+        stats,
+        PortableStats {
+            builds: 3,
+            files: 4
+        }
+    );
+}
+
+#[test]
+fn rejects_incompatible_exports() {
+    let dst = InMemoryDb::default();
+
+    let mut data = vec![];
+    export(&source_db(), Format::JsonLines, &mut data).unwrap();
+    let text = String::from_utf8(data).unwrap();
+    let (header, records) = text.split_once('\n').unwrap();
+
+    let err = import(&dst, Format::JsonLines, &mut records.as_bytes(), &[]);
+    assert!(matches!(err, Err(PortableError::MissingHeader)));
+
+    let newer = n2o5::db::SCHEMA_VERSION + 1;
+    let new = header.replace(
+        &format!("\"schema_version\":{}", n2o5::db::SCHEMA_VERSION),
+        &format!("\"schema_version\":{newer}"),
+    );
+    let new = format!("{new}\n{records}");
+    let err = import(&dst, Format::JsonLines, &mut new.as_bytes(), &[]);
+    assert!(matches!(
+        err,
+        Err(PortableError::SchemaMismatch { found, .. }) if found == newer
+    ));
+
+    let truncated = &text.as_bytes()[..text.len() - 10];
+    assert!(import(&dst, Format::JsonLines, &mut &truncated[..], &[]).is_err());
+
+    // Nothing was written
+    assert_eq!(dst.begin_read().builds().count(), 0);
+    assert_eq!(dst.begin_read().files().count(), 0);
+}
+
+/// The records of an export of schema version 1.
+#[derive(serde::Serialize)]
+enum RecordV1 {
+    Header {
+        format_version: u64,
+        schema_version: u64,
+    },
+    Build(BuildHash, BuildInfoV1),
+    File(PathBuf, FileInfo),
+}
+
+fn import_v1(format: Format) {
+    let old = |n: u8| {
+        let info = build_info(n);
+        // Schema version 1 never recorded the end of a build
+        BuildInfoV1 {
+            last_start: info.last_start,
+            last_end: None,
+            input_set_digest: info.input_set_digest,
+            additional_inputs: info.additional_inputs,
+        }
+    };
+    let records = [
+        RecordV1::Header {
+            format_version: n2o5::db::portable::FORMAT_VERSION,
+            schema_version: 1,
+        },
+        RecordV1::Build(BuildHash([9; 16]), old(9)),
+        RecordV1::File("out9.o".into(), file_info(9)),
+    ];
+    let mut data = vec![];
+    for record in &records {
+        match format {
+            Format::JsonLines => {
+                serde_json::to_writer(&mut data, record).unwrap();
+                data.push(b'\n');
+            }
+            Format::Postcard => data.extend(postcard::to_stdvec(record).unwrap()),
+        }
+    }
+    // Followed by an export of the current version
+    export(&source_db(), format, &mut data).unwrap();
+
+    let dst = InMemoryDb::default();
+    let stats = import(&dst, format, &mut data.as_slice(), &[]).unwrap();
+    assert_eq!(
+        stats,
+        PortableStats {
+            builds: 4,
+            files: 4
+        }
+    );
+    let rd = dst.begin_read();
+    let expected: BuildInfo = old(9).into();
+    assert!(expected.history.is_empty());
+    assert_eq!(expected.last_end, Some(expected.last_start));
+    assert_eq!(rd.get_build_info(BuildHash([9; 16])), Some(expected));
+    assert_eq!(rd.get_file_info(Path::new("out9.o")), Some(file_info(9)));
+    assert_eq!(rd.get_build_info(BuildHash([1; 16])), Some(build_info(1)));
+}
+
+#[test]
+fn json_lines_schema_v1_is_upgraded() {
+    import_v1(Format::JsonLines);
+}
+
+#[test]
+fn postcard_schema_v1_is_upgraded() {
+    import_v1(Format::Postcard);
+}
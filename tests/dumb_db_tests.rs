@@ -0,0 +1,294 @@
+//! Tests for the file-backed [`DumbDb`].
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use n2o5::db::{
+    BuildHash, BuildInfo, BuildInfoV1, ExecDb, FileInfo, InputHash, OpenExistingError,
+    SCHEMA_VERSION,
+    dumb::{self, DumbDb},
+};
+
+fn write_file_info(db: &DumbDb, path: &str) {
+    let mut txn = db.begin_write();
+    txn.set_file_info(
+        Path::new(path),
+        FileInfo {
+            last_seen: SystemTime::UNIX_EPOCH,
+            generated_by: BuildHash([1; 16]),
+        },
+    );
+    txn.commit();
+}
+
+#[test]
+fn test_persists_across_reopen() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("n2o5.db");
+
+    let db = DumbDb::new(&path).unwrap();
+    assert_eq!(db.get_schema_version(), SCHEMA_VERSION);
+    write_file_info(&db, "a.out");
+    drop(db);
+
+    let db = DumbDb::new(&path).unwrap();
+    assert_eq!(db.get_schema_version(), SCHEMA_VERSION);
+    assert!(db.begin_read().get_file_info(Path::new("a.out")).is_some());
+    drop(db);
+}
+
+#[test]
+fn test_incompatible_version_is_reset() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("n2o5.db");
+
+    let db = DumbDb::new(&path).unwrap();
+    write_file_info(&db, "a.out");
+    drop(db);
+
+    // Bump the stored schema version, which follows the 16-byte magic header
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[16..24].copy_from_slice(&(SCHEMA_VERSION + 1).to_le_bytes());
+    std::fs::write(&path, bytes).unwrap();
+
+    let db = DumbDb::new(&path).unwrap();
+    assert_eq!(db.get_schema_version(), SCHEMA_VERSION);
+    assert!(db.begin_read().get_file_info(Path::new("a.out")).is_none());
+    drop(db);
+}
+
+#[test]
+fn test_open_existing_keeps_other_versions() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("n2o5.db");
+
+    let db = DumbDb::new(&path).unwrap();
+    write_file_info(&db, "a.out");
+    drop(db);
+    let db = DumbDb::open_existing(&path).unwrap();
+    assert!(db.begin_read().get_file_info(Path::new("a.out")).is_some());
+    drop(db);
+
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[16..24].copy_from_slice(&(SCHEMA_VERSION + 1).to_le_bytes());
+    std::fs::write(&path, &bytes).unwrap();
+    assert!(matches!(
+        DumbDb::open_existing(&path),
+        Err(OpenExistingError::VersionMismatch { schema, .. }) if schema == SCHEMA_VERSION + 1
+    ));
+    assert_eq!(std::fs::read(&path).unwrap(), bytes);
+
+    std::fs::write(&path, b"garbage").unwrap();
+    assert!(matches!(
+        DumbDb::open_existing(&path),
+        Err(OpenExistingError::Backend(e)) if e.kind() == std::io::ErrorKind::InvalidData
+    ));
+    assert_eq!(std::fs::read(&path).unwrap(), b"garbage");
+
+    let missing = dir.path().join("missing.db");
+    assert!(DumbDb::open_existing(&missing).is_err());
+    assert!(!missing.exists());
+}
+
+/// The layout of the data in schema version 1.
+#[derive(bincode::Encode)]
+struct DataV1 {
+    schema_version: u64,
+    build_info: HashMap<BuildHash, BuildInfoV1>,
+    file_info: HashMap<PathBuf, FileInfo>,
+}
+
+/// The layout of a journaled change in schema version 1.
+#[derive(bincode::Encode)]
+enum WriteOpV1 {
+    SetBuild(BuildHash, BuildInfoV1),
+}
+
+#[test]
+fn test_schema_v1_is_upgraded() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("n2o5.db");
+    let cfg = bincode::config::standard();
+    // Schema version 1 never recorded the end of a build
+    let build = |n: u8| BuildInfoV1 {
+        last_start: SystemTime::UNIX_EPOCH,
+        last_end: None,
+        input_set_digest: InputHash([n; 16]),
+        additional_inputs: vec!["dep.h".into()],
+    };
+
+    let mut bytes = dumb::MAGIC.to_vec();
+    bytes.extend_from_slice(&1u64.to_le_bytes());
+    bytes.extend_from_slice(&2u64.to_le_bytes());
+    let data = DataV1 {
+        schema_version: 1,
+        build_info: HashMap::from([(BuildHash([1; 16]), build(1))]),
+        file_info: HashMap::new(),
+    };
+    bytes.extend(bincode::encode_to_vec(&data, cfg).unwrap());
+    let payload =
+        bincode::encode_to_vec(vec![WriteOpV1::SetBuild(BuildHash([2; 16]), build(2))], cfg)
+            .unwrap();
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&xxhash_rust::xxh3::xxh3_64(&payload).to_le_bytes());
+    bytes.extend(payload);
+    std::fs::write(&path, bytes).unwrap();
+
+    // Reopen after writing, to check that the upgraded data is stored with
+    // the current version
+    let db = DumbDb::new(&path).unwrap();
+    write_file_info(&db, "a.out");
+    drop(db);
+    let db = DumbDb::new(&path).unwrap();
+    assert_eq!(db.get_schema_version(), SCHEMA_VERSION);
+    let rd = db.begin_read();
+    for n in [1, 2] {
+        let expected: BuildInfo = build(n).into();
+        assert_eq!(expected.last_end, Some(SystemTime::UNIX_EPOCH));
+        assert_eq!(rd.get_build_info(BuildHash([n; 16])), Some(expected));
+    }
+    assert!(rd.get_file_info(Path::new("a.out")).is_some());
+    drop(rd);
+    drop(db);
+}
+
+#[test]
+fn test_reset() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("n2o5.db");
+
+    let db = DumbDb::new(&path).unwrap();
+    write_file_info(&db, "a.out");
+    db.reset();
+    assert!(db.begin_read().get_file_info(Path::new("a.out")).is_none());
+    drop(db);
+}
+
+#[test]
+fn test_journal_replayed_after_crash() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("n2o5.db");
+    let crashed = dir.path().join("crashed.db");
+
+    let db = DumbDb::new(&path).unwrap();
+    write_file_info(&db, "a.out");
+    write_file_info(&db, "b.out");
+    // Take the file as it is before the DB is compacted on drop, like after
+    // the process is killed.
+    std::fs::copy(&path, &crashed).unwrap();
+    drop(db);
+
+    let db = DumbDb::new(&crashed).unwrap();
+    let txn = db.begin_read();
+    assert!(txn.get_file_info(Path::new("a.out")).is_some());
+    assert!(txn.get_file_info(Path::new("b.out")).is_some());
+    drop(txn);
+    drop(db);
+}
+
+#[test]
+fn test_damaged_journal_tail_is_discarded() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("n2o5.db");
+    let crashed = dir.path().join("crashed.db");
+
+    let db = DumbDb::new(&path).unwrap();
+    write_file_info(&db, "a.out");
+    write_file_info(&db, "b.out");
+    // Cut the last record short, like a crash in the middle of appending it
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::write(&crashed, &bytes[..bytes.len() - 3]).unwrap();
+    drop(db);
+
+    let db = DumbDb::new(&crashed).unwrap();
+    assert!(db.begin_read().get_file_info(Path::new("a.out")).is_some());
+    assert!(db.begin_read().get_file_info(Path::new("b.out")).is_none());
+    // New records go after the last valid one
+    write_file_info(&db, "c.out");
+    let bytes = std::fs::read(&crashed).unwrap();
+    drop(db);
+
+    std::fs::write(&crashed, bytes).unwrap();
+    let db = DumbDb::new(&crashed).unwrap();
+    assert!(db.begin_read().get_file_info(Path::new("a.out")).is_some());
+    assert!(db.begin_read().get_file_info(Path::new("c.out")).is_some());
+    drop(db);
+}
+
+#[test]
+fn test_interrupted_compaction_keeps_data() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("n2o5.db");
+    let crashed = dir.path().join("crashed.db");
+
+    let db = DumbDb::new(&path).unwrap();
+    write_file_info(&db, "a.out");
+    drop(db);
+    assert!(!dir.path().join("n2o5.db.tmp").exists());
+
+    let db = DumbDb::new(&path).unwrap();
+    write_file_info(&db, "b.out");
+    // Take the file as it is when the process is killed while compacting on
+    // drop, with the new snapshot only partially written
+    std::fs::copy(&path, &crashed).unwrap();
+    drop(db);
+    let snapshot = std::fs::read(&path).unwrap();
+    let tmp = dir.path().join("crashed.db.tmp");
+    std::fs::write(&tmp, &snapshot[..snapshot.len() / 2]).unwrap();
+
+    let db = DumbDb::new(&crashed).unwrap();
+    assert!(db.begin_read().get_file_info(Path::new("a.out")).is_some());
+    assert!(db.begin_read().get_file_info(Path::new("b.out")).is_some());
+    drop(db);
+    assert!(!tmp.exists());
+
+    let db = DumbDb::new(&crashed).unwrap();
+    assert!(db.begin_read().get_file_info(Path::new("b.out")).is_some());
+}
+
+#[test]
+fn test_waiting_open_sees_compacted_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("n2o5.db");
+
+    let db = DumbDb::new(&path).unwrap();
+    write_file_info(&db, "a.out");
+    // Blocks on the lock until the first instance is dropped, which replaces
+    // the file it has opened
+    let waiter = std::thread::spawn({
+        let path = path.clone();
+        move || {
+            let db = DumbDb::new(&path).unwrap();
+            write_file_info(&db, "b.out");
+        }
+    });
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    drop(db);
+    waiter.join().unwrap();
+
+    let db = DumbDb::new(&path).unwrap();
+    assert!(db.begin_read().get_file_info(Path::new("a.out")).is_some());
+    assert!(db.begin_read().get_file_info(Path::new("b.out")).is_some());
+}
+
+#[test]
+fn test_uncommitted_writes_are_discarded() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("n2o5.db");
+
+    let db = DumbDb::new(&path).unwrap();
+    let mut txn = db.begin_write();
+    txn.set_file_info(
+        Path::new("a.out"),
+        FileInfo {
+            last_seen: SystemTime::UNIX_EPOCH,
+            generated_by: BuildHash([1; 16]),
+        },
+    );
+    drop(txn);
+    assert!(db.begin_read().get_file_info(Path::new("a.out")).is_none());
+    drop(db);
+}
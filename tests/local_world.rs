@@ -0,0 +1,67 @@
+#![cfg(unix)]
+
+use std::{
+    borrow::Cow,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use n2o5::{
+    BuildGraph, BuildId,
+    exec::BuildStatusKind,
+    graph::{BuildCommand, BuildMethod, BuildNode, GraphBuilder},
+    world::{LOCAL_WORLD, World},
+};
+
+fn shell_graph(script: &str) -> (BuildGraph, BuildId) {
+    let mut builder = GraphBuilder::new();
+    let id = builder.add_build(BuildNode {
+        command: BuildMethod::SubCommand(BuildCommand {
+            executable: "sh".into(),
+            args: vec![Cow::Borrowed("-c".as_ref()), Cow::Owned(script.into())],
+        }),
+        ins: vec![],
+        outs: vec![],
+        description: None,
+    });
+    (builder.build().unwrap(), id)
+}
+
+#[test]
+fn output_is_streamed_as_lines() {
+    let (graph, id) = shell_graph("echo first; sleep 0.3; echo second >&2; printf third");
+    let start = Instant::now();
+    let chunks = Mutex::new(vec![]);
+    let record = |chunk: &[u8]| {
+        let text = String::from_utf8_lossy(chunk).into_owned();
+        chunks.lock().unwrap().push((text, start.elapsed()));
+    };
+    let outcome = LOCAL_WORLD.execute(&(), &graph, id, Some(&record)).unwrap();
+    assert_eq!(outcome.status, BuildStatusKind::Succeeded);
+
+    let chunks = chunks.into_inner().unwrap();
+    let text: Vec<_> = chunks.iter().map(|(text, _)| text.as_str()).collect();
+    assert_eq!(text, ["first\n", "second\n", "third"]);
+    // The first line arrives before the command sleeps, not when it exits
+    assert!(chunks[0].1 < Duration::from_millis(250), "{chunks:?}");
+}
+
+#[test]
+fn background_process_does_not_hold_the_build() {
+    let (graph, id) = shell_graph("echo done; sleep 5 &");
+    let start = Instant::now();
+    let output = Mutex::new(vec![]);
+    let record = |chunk: &[u8]| output.lock().unwrap().extend_from_slice(chunk);
+    let outcome = LOCAL_WORLD.execute(&(), &graph, id, Some(&record)).unwrap();
+    assert_eq!(outcome.status, BuildStatusKind::Succeeded);
+    assert_eq!(output.into_inner().unwrap(), b"done\n");
+    assert!(start.elapsed() < Duration::from_secs(3));
+}
+
+#[test]
+fn failure_without_captured_output() {
+    let (graph, id) = shell_graph("exit 3");
+    let outcome = LOCAL_WORLD.execute(&(), &graph, id, None).unwrap();
+    assert_eq!(outcome.status, BuildStatusKind::Failed);
+    assert_eq!(outcome.exit_code, Some(3));
+}
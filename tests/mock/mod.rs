@@ -2,36 +2,46 @@
 
 use std::{
     any::Any,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
-    sync::Mutex,
+    sync::{Arc, Mutex},
 };
 
 use n2o5::{
     exec::BuildStatusKind,
     graph::{BuildCommand, BuildGraph, BuildId, BuildMethod},
-    world::World,
+    world::{ExecOutcome, OutputSink, World},
 };
 use smol_str::SmolStr;
 
 /// A mock [`World`] implementation that works entirely in-memory.
 pub struct MockWorld {
     inner: Mutex<MockWorldInner>,
+    /// Called before each execution, without holding the lock of the world
+    before_execute: Mutex<Option<MockHook>>,
 }
 
 pub type MockCallback =
     Box<dyn Fn(&dyn Any, &BuildMethod) -> std::io::Result<BuildStatusKind> + Send + Sync>;
 
+pub type MockHook = Arc<dyn Fn(&BuildMethod) + Send + Sync>;
+
 struct MockWorldInner {
     /// A number that roughly represent a mocked system time. Increases every
     /// time the world queries "now" or a file is touched.
     epoch: u64,
     /// Map from in-memory file list to their modification epoch
     files: HashMap<PathBuf, u64>,
+    /// Directories that have been created
+    dirs: HashSet<PathBuf>,
     /// A log of executed commands
     exec_log: Vec<MockExecResult>,
     /// Execution callback
     callback: Option<MockCallback>,
+    /// Output files that successful executions will not create
+    skipped_outputs: HashSet<PathBuf>,
+    /// Files that cannot be removed
+    protected: HashSet<PathBuf>,
 }
 
 #[derive(Debug, Clone)]
@@ -56,22 +66,57 @@ impl World for MockWorld {
         Ok(std::time::UNIX_EPOCH + std::time::Duration::from_secs(*epoch))
     }
 
+    fn remove(&self, path: &Path) -> std::io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.protected.contains(path) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "file is protected",
+            ));
+        }
+        inner.files.remove(path);
+        Ok(())
+    }
+
     fn now(&self) -> std::time::SystemTime {
         let mut inner = self.inner.lock().unwrap();
         inner.epoch += 1;
         std::time::UNIX_EPOCH + std::time::Duration::from_secs(inner.epoch)
     }
 
+    fn prepare_outputs(&self, graph: &BuildGraph, id: BuildId) -> std::io::Result<()> {
+        let node = graph
+            .lookup_build(id)
+            .expect("invalid BuildId passed to World::prepare_outputs");
+
+        let mut inner = self.inner.lock().unwrap();
+        for out in &node.outs {
+            let path = graph.lookup_path(*out).expect("invalid FileId");
+            for dir in path.ancestors().skip(1) {
+                if !dir.as_os_str().is_empty() {
+                    inner.dirs.insert(dir.to_owned());
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn execute(
         &self,
         state: &dyn std::any::Any,
         graph: &BuildGraph,
         id: BuildId,
-    ) -> std::io::Result<BuildStatusKind> {
+        _output: Option<OutputSink<'_>>,
+    ) -> std::io::Result<ExecOutcome> {
         let node = graph
             .lookup_build(id)
             .expect("invalid BuildId passed to World::execute");
 
+        let hook = self.before_execute.lock().unwrap().clone();
+        if let Some(hook) = hook {
+            hook(&node.command);
+        }
+
         let mut inner = self.inner.lock().unwrap();
         match &node.command {
             n2o5::graph::BuildMethod::Phony => {
@@ -101,10 +146,22 @@ impl World for MockWorld {
             let epoch = inner.epoch;
             for out in &node.outs {
                 let path = graph.lookup_path(*out).expect("invalid FileId");
+                if inner.skipped_outputs.contains(path) {
+                    continue;
+                }
                 inner.files.insert(path.to_owned(), epoch);
             }
         }
-        res
+
+        // Subcommands exit with 0 on success and 1 on failure
+        let is_subcommand = matches!(node.command, n2o5::graph::BuildMethod::SubCommand(_));
+        res.map(|status| ExecOutcome {
+            status,
+            exit_code: is_subcommand.then_some((status != BuildStatusKind::Succeeded) as i32),
+            signal: None,
+            peak_memory: None,
+            error: None,
+        })
     }
 }
 
@@ -115,9 +172,13 @@ impl MockWorld {
             inner: Mutex::new(MockWorldInner {
                 epoch: 0,
                 files: HashMap::new(),
+                dirs: HashSet::new(),
                 exec_log: Vec::new(),
                 callback: None,
+                skipped_outputs: HashSet::new(),
+                protected: HashSet::new(),
             }),
+            before_execute: Mutex::new(None),
         }
     }
 
@@ -139,12 +200,56 @@ impl MockWorld {
         inner.files.remove(path.as_ref());
     }
 
+    /// Move all files under `from` to the same relative paths under `to`,
+    /// keeping their modification times.
+    pub fn relocate(&self, from: impl AsRef<Path>, to: impl AsRef<Path>) {
+        let mut inner = self.inner.lock().unwrap();
+        let files = std::mem::take(&mut inner.files);
+        inner.files = files
+            .into_iter()
+            .map(|(path, epoch)| match path.strip_prefix(from.as_ref()) {
+                Ok(relative) => (to.as_ref().join(relative), epoch),
+                Err(_) => (path, epoch),
+            })
+            .collect();
+    }
+
+    /// Test whether a file exists in the mock world.
+    pub fn has_file(&self, path: impl AsRef<Path>) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.files.contains_key(path.as_ref())
+    }
+
+    /// Test whether a directory has been created.
+    pub fn has_dir(&self, path: impl AsRef<Path>) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.dirs.contains(path.as_ref())
+    }
+
     /// Take and clear the execution log.
     pub fn take_log(&self) -> Vec<MockExecResult> {
         let mut inner = self.inner.lock().unwrap();
         std::mem::take(&mut inner.exec_log)
     }
 
+    /// Make successful executions not create the given output file.
+    pub fn skip_output(&self, path: impl AsRef<Path>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.skipped_outputs.insert(path.as_ref().to_owned());
+    }
+
+    /// Make removing the given file fail.
+    pub fn protect_file(&self, path: impl AsRef<Path>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.protected.insert(path.as_ref().to_owned());
+    }
+
+    /// Set a hook that runs before each execution. Unlike the execution
+    /// callback, it may block without blocking other executions.
+    pub fn set_before_execute(&self, hook: MockHook) {
+        *self.before_execute.lock().unwrap() = Some(hook);
+    }
+
     /// Set an execution callback to customize command execution behavior.
     pub fn set_callback(&self, callback: MockCallback) {
         let mut inner = self.inner.lock().unwrap();
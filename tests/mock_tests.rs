@@ -7,7 +7,7 @@ use n2o5::db::ExecDb;
 use n2o5::progress::noop::NOOP_PROGRESS;
 use n2o5::{
     db::in_memory::InMemoryDb,
-    exec::{BuildStatusKind, ExecConfig, Executor},
+    exec::{BuildStatusKind, ExecConfig, Executor, MissingOutputPolicy},
     graph::BuildMethod,
 };
 
@@ -332,7 +332,10 @@ fn test_parallelism_one_two_leaves() {
     let log = run_graph(
         &world,
         &cx.graph,
-        ExecConfig { parallelism: 1 },
+        ExecConfig {
+            parallelism: 1,
+            ..Default::default()
+        },
         &db,
         [cx.d, cx.e],
     );
@@ -421,6 +424,87 @@ fn test_two_dependency_failures_skip_consumer() {
     assert_db_missing(&db, "c.out");
 }
 
+/// A [`Progress`](n2o5::progress::Progress) that keeps the builds reported as
+/// finished and the last status.
+#[derive(Default)]
+struct StatusProgress {
+    finished: std::sync::Mutex<Vec<(n2o5::graph::BuildId, BuildStatusKind)>>,
+    last_status: std::sync::Mutex<Option<n2o5::progress::ProgressStatus>>,
+}
+
+impl n2o5::progress::Progress for StatusProgress {
+    fn prepare(&self, _config: &n2o5::progress::ProgressConfig) {}
+
+    fn build_started(
+        &self,
+        _graph: &n2o5::graph::BuildGraph,
+        _id: n2o5::graph::BuildId,
+        _status: &n2o5::progress::ProgressStatus,
+    ) {
+    }
+
+    fn stdout_line(
+        &self,
+        _graph: &n2o5::graph::BuildGraph,
+        _id: n2o5::graph::BuildId,
+        _chunk: &[u8],
+    ) {
+    }
+
+    fn build_finished(
+        &self,
+        _graph: &n2o5::graph::BuildGraph,
+        id: n2o5::graph::BuildId,
+        kind: BuildStatusKind,
+        _failure: Option<&n2o5::exec::FailureDetail>,
+        status: &n2o5::progress::ProgressStatus,
+    ) {
+        self.finished.lock().unwrap().push((id, kind));
+        *self.last_status.lock().unwrap() = Some(status.clone());
+    }
+
+    fn finish(&self) {}
+}
+
+#[test]
+fn test_failure_skips_dependents_only() {
+    let cx = mock_graph! {
+        z: "z.out" => Z("z.in");
+        a, dep(z): "a.out" => A("z.out");
+        b, dep(a): "b.out" => B("a.out");
+        c, dep(b): "c.out" => C("b.out");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["z.in"]);
+    set_fail_on(&world, "A");
+
+    let db = declare_db();
+    let progress = StatusProgress::default();
+    let cfg = ExecConfig::default();
+    let mut exec = Executor::with_world(&cfg, &cx.graph, &db, &world, &progress, &());
+    exec.want([cx.c]);
+    exec.run().unwrap();
+
+    // The dependents of A are skipped without running, while its dependency
+    // is kept
+    assert_eq!(
+        progress.finished.into_inner().unwrap(),
+        vec![
+            (cx.z, BuildStatusKind::Succeeded),
+            (cx.a, BuildStatusKind::Failed),
+            (cx.b, BuildStatusKind::Skipped),
+            (cx.c, BuildStatusKind::Skipped),
+        ]
+    );
+    assert!(world.has_file("z.out"));
+    assert!(!world.has_file("b.out"));
+
+    // Skipped builds count as done, but not as failed
+    let status = progress.last_status.into_inner().unwrap().unwrap();
+    assert_eq!((status.total, status.done, status.failed), (4, 4, 1));
+}
+
 #[test]
 fn test_touch_input_after_first_build_triggers_rebuild() {
     let cx = mock_graph! {
@@ -531,3 +615,826 @@ fn test_nonexisting_input_file_fails_without_execution() {
 
     assert_db_missing(&db, "out.txt");
 }
+
+#[test]
+fn test_missing_output_fails_build() {
+    let cx = mock_graph! {
+        a: "a.out", "a.extra" => A("a.in");
+        b, dep(a): "b.out" => B("a.out");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["a.in"]);
+    world.skip_output("a.extra");
+
+    let db = declare_db();
+
+    let log = run_graph(&world, &cx.graph, ExecConfig::default(), &db, [cx.b]);
+    assert_eq!(log, vec!["A"]);
+
+    assert_db_missing(&db, "a.out");
+    assert_db_missing(&db, "b.out");
+}
+
+#[test]
+fn test_missing_output_warn_policy_succeeds() {
+    let cx = mock_graph! {
+        a: "a.out", "a.extra" => A("a.in");
+        b, dep(a): "b.out" => B("a.out");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["a.in"]);
+    world.skip_output("a.extra");
+
+    let db = declare_db();
+
+    let cfg = ExecConfig {
+        missing_output: MissingOutputPolicy::Warn,
+        ..Default::default()
+    };
+    let log = run_graph(&world, &cx.graph, cfg, &db, [cx.b]);
+    assert_eq!(log, vec!["A", "B"]);
+    assert_db_has(&db, "b.out");
+
+    // The missing output still makes the build outdated on the next run
+    let log = run_graph(&world, &cx.graph, ExecConfig::default(), &db, [cx.a]);
+    assert_eq!(log, vec!["A"]);
+}
+
+#[test]
+fn test_output_directories_prepared() {
+    let cx = mock_graph! {
+        a: "gen/sub/a.out", "gen/a.extra" => A("a.in");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["a.in"]);
+
+    let db = declare_db();
+
+    let log = run_graph(&world, &cx.graph, ExecConfig::default(), &db, [cx.a]);
+    assert_eq!(log, vec!["A"]);
+
+    assert!(world.has_dir("gen"));
+    assert!(world.has_dir("gen/sub"));
+}
+
+#[test]
+fn test_phony_output_directories_not_prepared() {
+    let mut gb = n2o5::graph::GraphBuilder::new();
+    let ins = vec![gb.add_file("a.in")];
+    let outs = vec![gb.add_file("phony/all")];
+    let all = gb.add_build(n2o5::graph::BuildNode {
+        command: BuildMethod::Phony,
+        ins,
+        outs,
+        description: None,
+    });
+    let graph = gb.build().unwrap();
+
+    let world = MockWorld::new();
+    touch_all(&world, &["a.in"]);
+
+    let db = declare_db();
+
+    let log = run_graph(&world, &graph, ExecConfig::default(), &db, [all]);
+    assert_eq!(log, vec!["PHONY"]);
+
+    assert!(!world.has_dir("phony"));
+}
+
+#[test]
+fn test_failed_build_outputs_removed() {
+    let cx = mock_graph! {
+        a: "a.out" => A("a.in");
+    };
+
+    let world = MockWorld::new();
+    // A stale output left behind by an earlier build
+    touch_all(&world, &["a.in", "a.out"]);
+    set_fail_on(&world, "A");
+
+    let db = declare_db();
+
+    let cfg = ExecConfig {
+        remove_failed_outputs: true,
+        ..Default::default()
+    };
+    let log = run_graph(&world, &cx.graph, cfg, &db, [cx.a]);
+    assert_eq!(log, vec!["A"]);
+
+    assert!(!world.has_file("a.out"));
+    assert_db_missing(&db, "a.out");
+}
+
+#[test]
+fn test_failed_build_outputs_kept_by_default() {
+    let cx = mock_graph! {
+        a: "a.out" => A("a.in");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["a.in", "a.out"]);
+    set_fail_on(&world, "A");
+
+    let db = declare_db();
+
+    let log = run_graph(&world, &cx.graph, ExecConfig::default(), &db, [cx.a]);
+    assert_eq!(log, vec!["A"]);
+
+    assert!(world.has_file("a.out"));
+}
+
+#[test]
+fn test_missing_output_removes_partial_outputs() {
+    let cx = mock_graph! {
+        a: "a.out", "a.extra" => A("a.in");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["a.in"]);
+    world.skip_output("a.extra");
+
+    let db = declare_db();
+
+    let cfg = ExecConfig {
+        remove_failed_outputs: true,
+        ..Default::default()
+    };
+    let log = run_graph(&world, &cx.graph, cfg, &db, [cx.a]);
+    assert_eq!(log, vec!["A"]);
+
+    assert!(!world.has_file("a.out"));
+}
+
+#[test]
+fn test_outputs_of_build_running_at_failure_removed() {
+    let cx = mock_graph! {
+        a: "a.out" => A("in");
+        b: "b.out" => B("in");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["in", "b.out"]);
+    set_fail_on_any(&world, &["A", "B"]);
+
+    // B only finishes after the failure of A has stopped the run loop
+    let (failed_tx, failed_rx) = std::sync::mpsc::channel();
+    let failed_rx = std::sync::Mutex::new(failed_rx);
+    world.set_before_execute(std::sync::Arc::new(move |method| {
+        if let BuildMethod::SubCommand(cmd) = method
+            && cmd.executable == Path::new("B")
+        {
+            failed_rx
+                .lock()
+                .unwrap()
+                .recv_timeout(std::time::Duration::from_secs(10))
+                .expect("A should fail while B is running");
+        }
+    }));
+    let failed_tx = std::sync::Mutex::new(failed_tx);
+    let progress = RecordingProgress {
+        on_failure: Some(Box::new(move || {
+            let _ = failed_tx.lock().unwrap().send(());
+        })),
+        ..Default::default()
+    };
+
+    let db = declare_db();
+    let cfg = ExecConfig {
+        parallelism: 2,
+        remove_failed_outputs: true,
+        ..Default::default()
+    };
+    let mut exec = Executor::with_world(&cfg, &cx.graph, &db, &world, &progress, &());
+    exec.want([cx.a, cx.b]);
+    exec.run().unwrap();
+
+    assert!(!world.has_file("b.out"));
+    assert_db_missing(&db, "b.out");
+}
+
+#[test]
+fn test_panicked_build_outputs_removed() {
+    let cx = mock_graph! {
+        p: "p.out" => P("in");
+    };
+
+    let world = MockWorld::new();
+    // A stale output, which the panicking build may have been rewriting
+    touch_all(&world, &["in", "p.out"]);
+    world.set_before_execute(std::sync::Arc::new(|method| {
+        if let BuildMethod::SubCommand(cmd) = method
+            && cmd.executable == Path::new("P")
+        {
+            panic!("P panics");
+        }
+    }));
+
+    let db = declare_db();
+    let cfg = ExecConfig {
+        remove_failed_outputs: true,
+        ..Default::default()
+    };
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        run_graph(&world, &cx.graph, cfg, &db, [cx.p])
+    }));
+
+    assert!(result.is_err());
+    assert!(!world.has_file("p.out"));
+}
+
+#[test]
+fn test_clean_removes_outputs_of_dependencies() {
+    let cx = mock_graph! {
+        a: "a.out" => A("a.in");
+        b, dep(a): "b.out" => B("a.out");
+        c: "c.out" => C("c.in");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["a.in", "c.in"]);
+
+    let db = declare_db();
+    let _ = run_graph(&world, &cx.graph, ExecConfig::default(), &db, [cx.b, cx.c]);
+
+    let removed = n2o5::clean::clean(&cx.graph, &db, &world, [cx.b]).unwrap();
+    assert_eq!(removed, 2);
+
+    assert!(!world.has_file("a.out"));
+    assert!(!world.has_file("b.out"));
+    assert!(world.has_file("c.out"));
+    assert!(world.has_file("a.in"));
+    assert_db_missing(&db, "a.out");
+    assert_db_missing(&db, "b.out");
+    assert_db_has(&db, "c.out");
+
+    // Cleaned builds are rebuilt
+    let log = run_graph(&world, &cx.graph, ExecConfig::default(), &db, [cx.b, cx.c]);
+    assert_eq!(log, vec!["A", "B"]);
+}
+
+#[test]
+fn test_clean_commits_removals_before_error() {
+    let cx = mock_graph! {
+        a: "a.out" => A("a.in");
+        b: "b.out" => B("b.in");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["a.in", "b.in"]);
+
+    let db = declare_db();
+    let _ = run_graph(&world, &cx.graph, ExecConfig::default(), &db, [cx.a, cx.b]);
+
+    world.protect_file("a.out");
+    let err = n2o5::clean::clean(&cx.graph, &db, &world, [cx.a, cx.b]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+
+    // The file that could not be removed keeps its record
+    assert!(world.has_file("a.out"));
+    assert_db_has(&db, "a.out");
+    assert!(!world.has_file("b.out"));
+    assert_db_missing(&db, "b.out");
+}
+
+#[test]
+fn test_clean_dead_removes_stale_outputs() {
+    let cx1 = mock_graph! {
+        a: "a.out" => A("a.in");
+        old: "old.out" => OLD("a.in");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["a.in"]);
+
+    let db = declare_db();
+    let _ = run_graph(
+        &world,
+        &cx1.graph,
+        ExecConfig::default(),
+        &db,
+        [cx1.a, cx1.old],
+    );
+
+    // `old.out` is no longer produced, and `a.out` is produced by a new command
+    let cx2 = mock_graph! {
+        a: "a.out" => X("a.in");
+    };
+    let removed = n2o5::clean::clean_dead(&cx2.graph, &db, &world).unwrap();
+    assert_eq!(removed, 1);
+
+    assert!(!world.has_file("old.out"));
+    assert!(world.has_file("a.out"));
+    assert_db_missing(&db, "old.out");
+    assert_db_has(&db, "a.out");
+}
+
+#[test]
+fn test_gc_removes_stale_records() {
+    let cx1 = mock_graph! {
+        a: "a.out" => A("a.in");
+        old: "old.out" => OLD("a.in");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["a.in"]);
+
+    let db = declare_db();
+    let _ = run_graph(
+        &world,
+        &cx1.graph,
+        ExecConfig::default(),
+        &db,
+        [cx1.a, cx1.old],
+    );
+
+    let cx2 = mock_graph! {
+        a: "a.out" => X("a.in");
+    };
+    let _ = run_graph(&world, &cx2.graph, ExecConfig::default(), &db, [cx2.a]);
+    assert_eq!(db.begin_read().builds().count(), 3);
+
+    let stats = n2o5::db::gc::collect_garbage(&db, &cx2.graph);
+    assert_eq!(stats.builds, 2);
+    assert_eq!(stats.files, 1);
+
+    assert_eq!(db.begin_read().builds().count(), 1);
+    assert_db_missing(&db, "old.out");
+    assert_db_has(&db, "a.out");
+
+    // The live build is still up-to-date
+    let log = run_graph(&world, &cx2.graph, ExecConfig::default(), &db, [cx2.a]);
+    assert!(log.is_empty(), "Expected no execution, got {:?}", log);
+}
+
+#[test]
+fn test_files_generated_by() {
+    let cx = mock_graph! {
+        a: "a.out", "a.extra" => A("a.in");
+        b: "b.out" => B("a.in");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["a.in"]);
+
+    let db = declare_db();
+    let _ = run_graph(&world, &cx.graph, ExecConfig::default(), &db, [cx.a, cx.b]);
+
+    let a = cx.graph.lookup_build(cx.a).unwrap();
+    let hash = n2o5::graph::hash_build(a, &cx.graph);
+    let mut files: Vec<_> = db.begin_read().files_generated_by(hash).collect();
+    files.sort();
+    assert_eq!(files, vec![Path::new("a.extra"), Path::new("a.out")]);
+}
+
+/// An [`InMemoryDb`] that counts its write transactions.
+#[derive(Default)]
+struct CountingDb {
+    inner: InMemoryDb,
+    writes: std::sync::atomic::AtomicUsize,
+}
+
+impl ExecDb for CountingDb {
+    fn get_schema_version(&self) -> u64 {
+        self.inner.get_schema_version()
+    }
+
+    fn reset(&self) {
+        self.inner.reset()
+    }
+
+    fn begin_read<'r>(&'r self) -> Box<dyn n2o5::db::DbReader + 'r> {
+        self.inner.begin_read()
+    }
+
+    fn begin_write<'w>(&'w self) -> Box<dyn n2o5::db::DbWriter + 'w> {
+        self.writes
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.inner.begin_write()
+    }
+}
+
+#[test]
+fn test_db_updates_are_batched() {
+    let cx = mock_graph! {
+        a: "a.out" => A("in");
+        b: "b.out" => B("in");
+        c, dep(a, b): "c.out" => C("a.out", "b.out");
+        d, dep(c): "d.out" => D("c.out");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["in"]);
+
+    let db = CountingDb::default();
+    let cfg = ExecConfig {
+        db_batch_delay: std::time::Duration::from_secs(3600),
+        ..Default::default()
+    };
+    let _ = run_graph(&world, &cx.graph, cfg, &db, [cx.d]);
+
+    // Everything is written at once when the build ends
+    assert_eq!(db.writes.load(std::sync::atomic::Ordering::Relaxed), 1);
+    assert_db_has(&db.inner, "a.out");
+    assert_db_has(&db.inner, "b.out");
+    assert_db_has(&db.inner, "c.out");
+    assert_db_has(&db.inner, "d.out");
+}
+
+#[test]
+fn test_db_batch_size_one_writes_each_build() {
+    let cx = mock_graph! {
+        a: "a.out" => A("in");
+        b, dep(a): "b.out" => B("a.out");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["in"]);
+
+    let db = CountingDb::default();
+    let cfg = ExecConfig {
+        db_batch_size: 1,
+        ..Default::default()
+    };
+    let _ = run_graph(&world, &cx.graph, cfg, &db, [cx.b]);
+
+    assert_eq!(db.writes.load(std::sync::atomic::Ordering::Relaxed), 2);
+    assert_db_has(&db.inner, "a.out");
+    assert_db_has(&db.inner, "b.out");
+}
+
+#[test]
+fn test_batched_db_updates_written_on_failure() {
+    let cx = mock_graph! {
+        a: "a.out" => A("in");
+        b, dep(a): "b.out" => B("a.out");
+        f, dep(a): "f.out" => F("a.out");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["in"]);
+    set_fail_on(&world, "F");
+
+    // B only finishes after the failure of F has stopped the run loop, so
+    // that it is still running when the executor stops
+    let (failed_tx, failed_rx) = std::sync::mpsc::channel();
+    let failed_rx = std::sync::Mutex::new(failed_rx);
+    world.set_before_execute(std::sync::Arc::new(move |method| {
+        if let BuildMethod::SubCommand(cmd) = method
+            && cmd.executable == Path::new("B")
+        {
+            failed_rx
+                .lock()
+                .unwrap()
+                .recv_timeout(std::time::Duration::from_secs(10))
+                .expect("F should fail while B is running");
+        }
+    }));
+    let failed_tx = std::sync::Mutex::new(failed_tx);
+    let progress = RecordingProgress {
+        on_failure: Some(Box::new(move || {
+            failed_tx.lock().unwrap().send(()).unwrap()
+        })),
+        ..Default::default()
+    };
+
+    let db = declare_db();
+    // A stale record of the failing build, which must be invalidated
+    let mut txn = db.begin_write();
+    txn.set_file_info(
+        Path::new("f.out"),
+        n2o5::db::FileInfo {
+            last_seen: std::time::SystemTime::UNIX_EPOCH,
+            generated_by: n2o5::db::BuildHash([0; 16]),
+        },
+    );
+    txn.commit();
+    assert_db_has(&db, "f.out");
+    let cfg = ExecConfig {
+        parallelism: 2,
+        db_batch_delay: std::time::Duration::from_secs(3600),
+        ..Default::default()
+    };
+    let mut exec = Executor::with_world(&cfg, &cx.graph, &db, &world, &progress, &());
+    exec.want([cx.b, cx.f]);
+    exec.run().unwrap();
+
+    assert_db_has(&db, "a.out");
+    assert_db_has(&db, "b.out");
+    assert_db_missing(&db, "f.out");
+
+    // The build still running at the failure is reported too
+    let finished = progress.finished.into_inner().unwrap();
+    assert_eq!(
+        finished
+            .iter()
+            .map(|&(id, kind, _)| (id, kind))
+            .collect::<Vec<_>>(),
+        vec![
+            (cx.a, BuildStatusKind::Succeeded),
+            (cx.f, BuildStatusKind::Failed),
+            (cx.b, BuildStatusKind::Succeeded),
+        ]
+    );
+}
+
+#[test]
+fn test_batched_db_updates_written_on_panic() {
+    let cx = mock_graph! {
+        a: "a.out" => A("in");
+        p, dep(a): "p.out" => P("a.out");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["in"]);
+    world.set_before_execute(std::sync::Arc::new(|method| {
+        if let BuildMethod::SubCommand(cmd) = method
+            && cmd.executable == Path::new("P")
+        {
+            panic!("P panics");
+        }
+    }));
+
+    let db = declare_db();
+    let cfg = ExecConfig {
+        db_batch_delay: std::time::Duration::from_secs(3600),
+        ..Default::default()
+    };
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        run_graph(&world, &cx.graph, cfg, &db, [cx.p])
+    }));
+
+    assert!(result.is_err());
+    assert_db_has(&db, "a.out");
+}
+
+#[test]
+fn test_progress_finished_on_executor_error() {
+    let cx = mock_graph! {
+        a: "a.out" => A("in");
+        e, dep(a): "e.out" => E("a.out");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["in"]);
+    world.set_callback(Box::new(|_, method| match method {
+        BuildMethod::SubCommand(cmd) if cmd.executable == Path::new("E") => {
+            Err(std::io::Error::other("E cannot run"))
+        }
+        _ => Ok(BuildStatusKind::Succeeded),
+    }));
+
+    let db = declare_db();
+    let progress = RecordingProgress::default();
+    let cfg = ExecConfig::default();
+    let mut exec = Executor::with_world(&cfg, &cx.graph, &db, &world, &progress, &());
+    exec.want([cx.e]);
+    assert!(exec.run().is_err());
+    assert!(
+        progress
+            .finished_session
+            .load(std::sync::atomic::Ordering::Relaxed)
+    );
+}
+
+/// Build `in -> a.out -> b.out` with absolute paths under `root`.
+fn rooted_graph(root: &Path) -> (n2o5::graph::BuildGraph, n2o5::graph::BuildId) {
+    let mut gb = n2o5::graph::GraphBuilder::new();
+    gb.set_root(root);
+    let input = gb.add_file(root.join("in"));
+    let a_out = gb.add_file(root.join("a.out"));
+    let b_out = gb.add_file(root.join("b.out"));
+    let command = |name: &str| {
+        BuildMethod::SubCommand(n2o5::graph::BuildCommand {
+            executable: name.into(),
+            args: vec![],
+        })
+    };
+    let a = gb.add_build(n2o5::graph::BuildNode {
+        command: command("A"),
+        ins: vec![input],
+        outs: vec![a_out],
+        description: None,
+    });
+    let b = gb.add_build(n2o5::graph::BuildNode {
+        command: command("B"),
+        ins: vec![a_out],
+        outs: vec![b_out],
+        description: None,
+    });
+    gb.add_build_dep(b, a);
+    (gb.build().unwrap(), b)
+}
+
+#[test]
+fn test_rooted_db_survives_relocation() {
+    let old_root = Path::new("/old/checkout");
+    let new_root = Path::new("/new/workspace");
+
+    let world = MockWorld::new();
+    touch_all(&world, &["/old/checkout/in"]);
+
+    let db = declare_db();
+    let (graph, b) = rooted_graph(old_root);
+    let rooted = n2o5::db::rooted::RootedDb::new(&db, old_root);
+    let log = run_graph(&world, &graph, ExecConfig::default(), &rooted, [b]);
+    assert_eq!(log, vec!["A", "B"]);
+
+    // Records are stored relative to the root
+    assert_db_has(&db, "a.out");
+    assert_db_has(&db, "b.out");
+
+    // Moving the checkout keeps everything up-to-date
+    world.relocate(old_root, new_root);
+    let (graph, b) = rooted_graph(new_root);
+    let rooted = n2o5::db::rooted::RootedDb::new(&db, new_root);
+    let log = run_graph(&world, &graph, ExecConfig::default(), &rooted, [b]);
+    assert!(log.is_empty(), "Expected no execution, got {:?}", log);
+
+    let files: Vec<_> = rooted.begin_read().files().map(|(path, _)| path).collect();
+    assert!(files.contains(&new_root.join("a.out")));
+}
+
+#[test]
+fn test_history_is_recorded() {
+    let cx = mock_graph! {
+        a: "out.txt" => A("in.txt");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["in.txt"]);
+
+    let db = declare_db();
+    let cfg = || ExecConfig {
+        history_len: 2,
+        ..Default::default()
+    };
+    let a = cx.graph.lookup_build(cx.a).unwrap();
+    let hash = n2o5::graph::hash_build(a, &cx.graph);
+    let history = || {
+        db.begin_read()
+            .get_build_info(hash)
+            .unwrap()
+            .history
+            .iter()
+            .map(|r| (r.succeeded, r.exit_code))
+            .collect::<Vec<_>>()
+    };
+
+    let _ = run_graph(&world, &cx.graph, cfg(), &db, [cx.a]);
+    assert_eq!(history(), vec![(true, Some(0))]);
+
+    // A failed build is recorded and runs again next time
+    world.touch_file("in.txt");
+    set_fail_on(&world, "A");
+    let _ = run_graph(&world, &cx.graph, cfg(), &db, [cx.a]);
+    assert_eq!(history(), vec![(true, Some(0)), (false, Some(1))]);
+    assert!(
+        db.begin_read()
+            .get_build_info(hash)
+            .unwrap()
+            .last_end
+            .is_none()
+    );
+
+    world.set_callback(Box::new(|_, _| Ok(BuildStatusKind::Succeeded)));
+    let log = run_graph(&world, &cx.graph, cfg(), &db, [cx.a]);
+    assert_eq!(log, vec!["A"]);
+    // Only the most recent runs are kept
+    assert_eq!(history(), vec![(false, Some(1)), (true, Some(0))]);
+}
+
+/// A [`Progress`](n2o5::progress::Progress) that records finished builds.
+#[derive(Default)]
+struct RecordingProgress {
+    finished: std::sync::Mutex<Vec<(n2o5::graph::BuildId, BuildStatusKind, Option<String>)>>,
+    /// Called after a failed build is recorded
+    on_failure: Option<Box<dyn Fn() + Send + Sync>>,
+    /// Whether the session has finished
+    finished_session: std::sync::atomic::AtomicBool,
+}
+
+impl n2o5::progress::Progress for RecordingProgress {
+    fn prepare(&self, _config: &n2o5::progress::ProgressConfig) {}
+
+    fn build_started(
+        &self,
+        _graph: &n2o5::graph::BuildGraph,
+        _id: n2o5::graph::BuildId,
+        _status: &n2o5::progress::ProgressStatus,
+    ) {
+    }
+
+    fn stdout_line(
+        &self,
+        _graph: &n2o5::graph::BuildGraph,
+        _id: n2o5::graph::BuildId,
+        _chunk: &[u8],
+    ) {
+    }
+
+    fn build_finished(
+        &self,
+        _graph: &n2o5::graph::BuildGraph,
+        id: n2o5::graph::BuildId,
+        kind: BuildStatusKind,
+        failure: Option<&n2o5::exec::FailureDetail>,
+        _status: &n2o5::progress::ProgressStatus,
+    ) {
+        let failure = failure.map(|f| f.to_string());
+        self.finished.lock().unwrap().push((id, kind, failure));
+        if kind == BuildStatusKind::Failed
+            && let Some(on_failure) = &self.on_failure
+        {
+            on_failure();
+        }
+    }
+
+    fn finish(&self) {
+        self.finished_session
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+fn run_recorded(
+    world: &MockWorld,
+    graph: &n2o5::graph::BuildGraph,
+    want: impl IntoIterator<Item = n2o5::graph::BuildId>,
+) -> Vec<(n2o5::graph::BuildId, BuildStatusKind, Option<String>)> {
+    let db = declare_db();
+    let progress = RecordingProgress::default();
+    let cfg = ExecConfig::default();
+    let mut exec = Executor::with_world(&cfg, graph, &db, world, &progress, &());
+    exec.want(want);
+    exec.run().unwrap();
+    progress.finished.into_inner().unwrap()
+}
+
+#[test]
+fn test_progress_reports_failure_details() {
+    let cx = mock_graph! {
+        a: "a.out" => A("in");
+        b, dep(a): "b.out" => B("a.out");
+        c, dep(b): "c.out" => C("b.out");
+        m: "m.out" => M("missing.in");
+    };
+
+    let world = MockWorld::new();
+    touch_all(&world, &["in"]);
+    set_fail_on(&world, "A");
+
+    // Dependents of a failed build are reported as skipped
+    assert_eq!(
+        run_recorded(&world, &cx.graph, [cx.c]),
+        vec![
+            (
+                cx.a,
+                BuildStatusKind::Failed,
+                Some("exited with code 1".into())
+            ),
+            (cx.b, BuildStatusKind::Skipped, None),
+            (cx.c, BuildStatusKind::Skipped, None),
+        ]
+    );
+
+    assert_eq!(
+        run_recorded(&world, &cx.graph, [cx.m]),
+        vec![(
+            cx.m,
+            BuildStatusKind::Failed,
+            Some("missing input \"missing.in\"".into())
+        )]
+    );
+
+    world.set_callback(Box::new(|_, _| Ok(BuildStatusKind::Succeeded)));
+    assert_eq!(
+        run_recorded(&world, &cx.graph, [cx.a]),
+        vec![(cx.a, BuildStatusKind::Succeeded, None)]
+    );
+}
+
+#[test]
+fn test_progress_reports_callback_errors() {
+    let mut gb = n2o5::graph::GraphBuilder::new();
+    let id = gb.add_build(n2o5::graph::BuildNode {
+        command: BuildMethod::Callback("boom".into(), Box::new(|_| Err("it broke".into()))),
+        ins: vec![],
+        outs: vec![],
+        description: None,
+    });
+    let graph = gb.build().unwrap();
+
+    let db = declare_db();
+    let progress = RecordingProgress::default();
+    let cfg = ExecConfig::default();
+    let mut exec = Executor::new(&cfg, &graph, &db, &progress, &());
+    exec.want([id]);
+    exec.run().unwrap();
+
+    assert_eq!(
+        progress.finished.into_inner().unwrap(),
+        vec![(id, BuildStatusKind::Failed, Some("it broke".into()))]
+    );
+}
@@ -0,0 +1,146 @@
+use n2o5::{
+    exec::{BuildStatusKind, FailureDetail},
+    graph::{BuildCommand, BuildMethod, BuildNode, GraphBuilder, hash_build},
+    progress::{Progress, ProgressConfig, ProgressStatus, json::JsonProgress},
+};
+use serde_json::{Value, json};
+
+fn status(started: usize, done: usize, failed: usize) -> ProgressStatus {
+    ProgressStatus {
+        total: 2,
+        started,
+        done,
+        failed,
+    }
+}
+
+#[test]
+fn events_are_json_lines() {
+    let mut gb = GraphBuilder::new();
+    let out = gb.add_file("a.o");
+    let cc = gb.add_build(BuildNode {
+        command: BuildMethod::SubCommand(BuildCommand {
+            executable: "cc".into(),
+            args: vec![],
+        }),
+        ins: vec![],
+        outs: vec![out],
+        description: Some("CC a.o".into()),
+    });
+    let link = gb.add_build(BuildNode {
+        command: BuildMethod::Phony,
+        ins: vec![out],
+        outs: vec![],
+        description: None,
+    });
+    gb.add_build_dep(link, cc);
+    let graph = gb.build().unwrap();
+
+    let progress = JsonProgress::new(vec![]);
+    progress.prepare(&ProgressConfig {
+        max_threads: Some(4),
+    });
+    progress.build_started(&graph, cc, &status(0, 0, 0));
+    progress.stdout_line(&graph, cc, b"error: oops\n");
+    let failure = FailureDetail {
+        exit_code: Some(1),
+        ..Default::default()
+    };
+    progress.build_finished(
+        &graph,
+        cc,
+        BuildStatusKind::Failed,
+        Some(&failure),
+        &status(1, 2, 1),
+    );
+    progress.build_finished(
+        &graph,
+        link,
+        BuildStatusKind::Skipped,
+        None,
+        &status(1, 2, 1),
+    );
+    progress.finish();
+
+    let written = String::from_utf8(progress.into_inner()).unwrap();
+    let mut events: Vec<Value> = written
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    for event in &mut events {
+        assert!(event["time"].as_f64().unwrap() >= 0.0);
+        event.as_object_mut().unwrap().remove("time");
+    }
+
+    let hash = hash_build(graph.lookup_build(cc).unwrap(), &graph).to_string();
+    let cc_view = json!({
+        "id": cc.index(),
+        "hash": hash,
+        "description": "CC a.o",
+        "outputs": ["a.o"],
+    });
+    assert_eq!(events.len(), 6);
+    assert_eq!(events[0], json!({"event": "prepare", "max_threads": 4}));
+    assert_eq!(
+        events[1],
+        json!({
+            "event": "build_started",
+            "build": cc_view,
+            "status": {"total": 2, "started": 0, "done": 0, "failed": 0},
+        })
+    );
+    assert_eq!(
+        events[2],
+        json!({"event": "output", "build": cc_view, "output": "error: oops\n"})
+    );
+    assert_eq!(
+        events[3],
+        json!({
+            "event": "build_finished",
+            "build": cc_view,
+            "result": "failed",
+            "failure": {
+                "message": "exited with code 1",
+                "exit_code": 1,
+                "signal": null,
+                "missing_input": null,
+                "error": null,
+            },
+            "status": {"total": 2, "started": 1, "done": 2, "failed": 1},
+        })
+    );
+    assert_eq!(events[4]["event"], "build_finished");
+    assert_eq!(events[4]["build"]["id"], link.index());
+    assert_eq!(events[4]["result"], "skipped");
+    assert_eq!(events[4]["failure"], Value::Null);
+    assert_eq!(events[5], json!({"event": "finish"}));
+}
+
+#[test]
+fn running_builds_are_interrupted_on_finish() {
+    let mut gb = GraphBuilder::new();
+    let slow = gb.add_build(BuildNode {
+        command: BuildMethod::Phony,
+        ins: vec![],
+        outs: vec![],
+        description: Some("slow".into()),
+    });
+    let graph = gb.build().unwrap();
+
+    let progress = JsonProgress::new(vec![]);
+    progress.prepare(&ProgressConfig { max_threads: None });
+    progress.build_started(&graph, slow, &status(0, 0, 0));
+    progress.finish();
+
+    let written = String::from_utf8(progress.into_inner()).unwrap();
+    let events: Vec<Value> = written
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(events.len(), 4);
+    assert_eq!(events[2]["event"], "build_finished");
+    assert_eq!(events[2]["build"], events[1]["build"]);
+    assert_eq!(events[2]["result"], "interrupted");
+    assert_eq!(events[2]["status"], events[1]["status"]);
+    assert_eq!(events[3]["event"], "finish");
+}
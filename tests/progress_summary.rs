@@ -0,0 +1,111 @@
+use n2o5::{
+    exec::{BuildStatusKind, FailureDetail},
+    graph::{BuildCommand, BuildMethod, BuildNode, GraphBuilder},
+    progress::summary::BuildSummary,
+};
+
+fn node(gb: &mut GraphBuilder, cmd: &str, description: Option<&'static str>) -> n2o5::BuildId {
+    gb.add_build(BuildNode {
+        command: BuildMethod::SubCommand(BuildCommand {
+            executable: cmd.into(),
+            args: vec![],
+        }),
+        ins: vec![],
+        outs: vec![],
+        description: description.map(Into::into),
+    })
+}
+
+#[test]
+fn summary_lists_failures() {
+    let mut gb = GraphBuilder::new();
+    let cc = node(&mut gb, "cc", Some("CC a.o"));
+    let link = node(&mut gb, "ld", None);
+    let ok = node(&mut gb, "ok", None);
+    let fresh = node(&mut gb, "fresh", None);
+    gb.add_build_dep(link, cc);
+    let graph = gb.build().unwrap();
+
+    let summary = BuildSummary::new();
+    summary.record_output(ok, b"fine\n");
+    summary.record_finished(&graph, ok, BuildStatusKind::Succeeded, None);
+    summary.record_finished(&graph, fresh, BuildStatusKind::UpToDate, None);
+    assert!(!summary.has_failures());
+
+    let output: String = (0..30).map(|i| format!("line {i}\n")).collect();
+    summary.record_output(cc, output.as_bytes());
+    let failure = FailureDetail {
+        exit_code: Some(1),
+        ..Default::default()
+    };
+    summary.record_finished(&graph, cc, BuildStatusKind::Failed, Some(&failure));
+    summary.record_finished(&graph, link, BuildStatusKind::Skipped, None);
+    assert!(summary.has_failures());
+
+    let mut written = vec![];
+    summary.write(&mut written).unwrap();
+    let tail: String = (10..30).map(|i| format!("    line {i}\n")).collect();
+    assert_eq!(
+        String::from_utf8(written).unwrap(),
+        format!(
+            "FAILED: CC a.o\n  \
+            command: cc\n  \
+            reason: exited with code 1\n  \
+            output:\n{tail}  \
+            skipped dependents:\n    \
+            ld\n\
+            1 succeeded, 1 up-to-date, 1 failed, 1 skipped\n"
+        )
+    );
+}
+
+#[test]
+fn skipped_builds_are_listed_under_each_failure() {
+    let mut gb = GraphBuilder::new();
+    let a = node(&mut gb, "a", None);
+    let b = node(&mut gb, "b", None);
+    let link = node(&mut gb, "link", None);
+    let test = node(&mut gb, "test", None);
+    gb.add_build_dep(link, a);
+    gb.add_build_dep(link, b);
+    gb.add_build_dep(test, link);
+    let graph = gb.build().unwrap();
+
+    // b fails in parallel, after the dependents of a have been skipped
+    let summary = BuildSummary::new();
+    summary.record_finished(&graph, a, BuildStatusKind::Failed, None);
+    summary.record_finished(&graph, link, BuildStatusKind::Skipped, None);
+    summary.record_finished(&graph, test, BuildStatusKind::Skipped, None);
+    summary.record_finished(&graph, b, BuildStatusKind::Failed, None);
+
+    let mut written = vec![];
+    summary.write(&mut written).unwrap();
+    assert_eq!(
+        String::from_utf8(written).unwrap(),
+        "FAILED: a\n  \
+        skipped dependents:\n    \
+        link\n    \
+        test\n\
+        FAILED: b\n  \
+        skipped dependents:\n    \
+        link\n    \
+        test\n\
+        0 succeeded, 0 up-to-date, 2 failed, 2 skipped\n"
+    );
+}
+
+#[test]
+fn summary_without_failures_has_counts() {
+    let mut gb = GraphBuilder::new();
+    let ok = node(&mut gb, "ok", None);
+    let graph = gb.build().unwrap();
+
+    let summary = BuildSummary::new();
+    summary.record_finished(&graph, ok, BuildStatusKind::Succeeded, None);
+    let mut written = vec![];
+    summary.write(&mut written).unwrap();
+    assert_eq!(
+        String::from_utf8(written).unwrap(),
+        "1 succeeded, 0 up-to-date, 0 failed, 0 skipped\n"
+    );
+}
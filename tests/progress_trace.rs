@@ -0,0 +1,104 @@
+use n2o5::{
+    exec::BuildStatusKind,
+    graph::{BuildMethod, BuildNode, GraphBuilder},
+    progress::{Progress, ProgressConfig, ProgressStatus, trace::ChromeTraceProgress},
+};
+use serde_json::Value;
+
+fn status() -> ProgressStatus {
+    ProgressStatus {
+        total: 4,
+        started: 0,
+        done: 0,
+        failed: 0,
+    }
+}
+
+#[test]
+fn builds_are_laid_out_on_lanes() {
+    let mut gb = GraphBuilder::new();
+    let mut node = |description: &'static str| {
+        gb.add_build(BuildNode {
+            command: BuildMethod::Phony,
+            ins: vec![],
+            outs: vec![],
+            description: Some(description.into()),
+        })
+    };
+    let a = node("a");
+    let b = node("b");
+    let c = node("c");
+    let d = node("d");
+    let graph = gb.build().unwrap();
+
+    let progress = ChromeTraceProgress::new(vec![]);
+    progress.prepare(&ProgressConfig {
+        max_threads: Some(2),
+    });
+    progress.build_started(&graph, a, &status());
+    progress.build_started(&graph, b, &status());
+    progress.build_finished(&graph, a, BuildStatusKind::UpToDate, None, &status());
+    progress.build_started(&graph, c, &status());
+    progress.build_finished(&graph, c, BuildStatusKind::Failed, None, &status());
+    progress.build_finished(&graph, d, BuildStatusKind::Skipped, None, &status());
+    progress.build_finished(&graph, b, BuildStatusKind::Succeeded, None, &status());
+    progress.finish();
+
+    let trace: Value = serde_json::from_slice(&progress.into_inner()).unwrap();
+    let events = trace.as_array().unwrap();
+
+    let lanes: Vec<_> = events
+        .iter()
+        .filter(|e| e["ph"] == "M" && e["name"] == "thread_name")
+        .map(|e| (e["tid"].as_u64().unwrap(), e["args"]["name"].clone()))
+        .collect();
+    assert_eq!(lanes, [(0, "worker 0".into()), (1, "worker 1".into())]);
+
+    let builds: Vec<_> = events
+        .iter()
+        .filter(|e| e["ph"] == "X")
+        .map(|e| {
+            assert!(e["ts"].is_u64() && e["dur"].is_u64());
+            (
+                e["name"].as_str().unwrap(),
+                e["cat"].as_str().unwrap(),
+                e["tid"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        builds,
+        [
+            ("a", "up_to_date", 0),
+            ("c", "failed", 0),
+            ("b", "succeeded", 1),
+        ]
+    );
+}
+
+#[test]
+fn running_builds_are_interrupted_on_finish() {
+    let mut gb = GraphBuilder::new();
+    let slow = gb.add_build(BuildNode {
+        command: BuildMethod::Phony,
+        ins: vec![],
+        outs: vec![],
+        description: Some("slow".into()),
+    });
+    let graph = gb.build().unwrap();
+
+    let progress = ChromeTraceProgress::new(vec![]);
+    progress.prepare(&ProgressConfig { max_threads: None });
+    progress.build_started(&graph, slow, &status());
+    progress.finish();
+
+    let trace: Value = serde_json::from_slice(&progress.into_inner()).unwrap();
+    let builds: Vec<_> = trace
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|e| e["ph"] == "X")
+        .map(|e| (e["name"].as_str().unwrap(), e["cat"].as_str().unwrap()))
+        .collect();
+    assert_eq!(builds, [("slow", "interrupted")]);
+}
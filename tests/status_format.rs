@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+use n2o5::progress::{ProgressStatus, format::StatusFormat};
+
+fn status() -> ProgressStatus {
+    ProgressStatus {
+        total: 8,
+        started: 5,
+        done: 2,
+        failed: 0,
+    }
+}
+
+fn render(format: &str, rate: Option<f64>) -> String {
+    StatusFormat::parse(format)
+        .unwrap()
+        .render(&status(), Duration::from_millis(4000), rate)
+}
+
+#[test]
+fn placeholders() {
+    assert_eq!(render(StatusFormat::NINJA_DEFAULT, None), "[2/8] ");
+    assert_eq!(render("%s %t %r %u %f|%p|%e", None), "5 8 3 3 2| 25%|4.000");
+    assert_eq!(render("%o %c", Some(1.25)), "0.5 1.2");
+    assert_eq!(render("%c", None), "?");
+    assert_eq!(render("100%% done%", None), "100% done%");
+}
+
+#[test]
+fn unknown_placeholder() {
+    let err = StatusFormat::parse("[%f/%x]").unwrap_err();
+    assert_eq!(err.0, 'x');
+}